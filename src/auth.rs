@@ -1,6 +1,21 @@
 use crate::errors::{Error, Result};
 use crate::Client;
-use reqwest::{header::HeaderValue, StatusCode, Url};
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::{header::HeaderValue, Method, StatusCode, Url};
+use std::sync::{Arc, MutexGuard, PoisonError, RwLock, TryLockError};
+use std::time::{Duration, SystemTime};
+
+/// Client identifier sent with OAuth2 token requests.
+const OAUTH2_CLIENT_ID: &str = "ghregistry";
+
+/// User name denoting that the password is an identity (refresh) token.
+pub(crate) const IDENTITY_TOKEN_USER: &str = "<token>";
+
+/// Lifetime of tokens without `expires_in`, as defined by the token spec.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Tokens are refreshed this long before they expire to absorb clock skew.
+const TOKEN_EXPIRY_SKEW: Duration = Duration::from_secs(30);
 
 /// Represents all supported authentication schemes and is stored by `Client`.
 #[derive(Debug, Clone)]
@@ -25,48 +40,104 @@ impl Auth {
 }
 
 /// Used for Bearer HTTP Authentication.
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct BearerAuth {
     token: String,
     expires_in: Option<u32>,
     issued_at: Option<String>,
     refresh_token: Option<String>,
+    /// When the token expires, unknown for tokens acquired out of band.
+    #[serde(skip)]
+    expires_at: Option<SystemTime>,
+    /// Challenge and scopes the token was acquired for.
+    #[serde(skip)]
+    refresh: Option<(WwwAuthenticateHeaderContentBearer, Vec<String>)>,
+}
+
+impl std::fmt::Debug for BearerAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Tokens are secrets, keep them out of logs
+        f.debug_struct("BearerAuth")
+            .field("token", &"<redacted>")
+            .field("expires_in", &self.expires_in)
+            .field("issued_at", &self.issued_at)
+            .field("expires_at", &self.expires_at)
+            .field(
+                "refresh_token",
+                &self.refresh_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
 }
 
 impl BearerAuth {
+    /// Value of the `Authorization` header for this token.
+    fn header_value(&self) -> Result<HeaderValue> {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", self.token))
+            .map_err(|_| Error::LoginReturnedBadToken)?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+
+    /// Use a token which was acquired out of band.
+    pub(crate) fn from_token(token: String) -> Self {
+        BearerAuth {
+            token,
+            ..Default::default()
+        }
+    }
+
     fn try_from_header_content(
         client: Client,
         scopes: &[&str],
         credentials: Option<(String, String)>,
         bearer_header_content: WwwAuthenticateHeaderContentBearer,
     ) -> Result<Self> {
-        let auth_ep = bearer_header_content.auth_ep(scopes);
-        trace!("authenticate: token endpoint: {}", auth_ep);
-
-        let url = reqwest::Url::parse(&auth_ep)?;
-
-        let auth_req = {
-            Client {
-                auth: credentials.map(|(user, password)| {
-                    Auth::Basic(BasicAuth {
-                        user,
-                        password: Some(password),
-                    })
-                }),
-                ..client
-            }
+        let url = bearer_header_content.auth_ep(scopes)?;
+        trace!("authenticate: token endpoint: {}", url);
+
+        if matches!(&credentials, Some((user, _)) if user == IDENTITY_TOKEN_USER) {
+            // Identity tokens are only accepted by the OAuth2 flow
+            debug!("authenticate: exchanging identity token with OAuth2 POST");
+            let bearer_auth: BearerAuth =
+                Self::fetch_oauth2_token(client, scopes, credentials, &bearer_header_content)?
+                    .into();
+            return Self::finish_token(bearer_auth, bearer_header_content, scopes);
         }
-        .build_reqwest(reqwest::Method::GET, url);
 
-        let r = auth_req.send()?;
+        let auth_client = Client {
+            auth: Arc::new(RwLock::new(credentials.clone().map(|(user, password)| {
+                Auth::Basic(BasicAuth {
+                    user,
+                    password: Some(password),
+                })
+            }))),
+            ..client.clone()
+        };
+        let auth_req = auth_client.build_reqwest(reqwest::Method::GET, url);
+
+        let r = auth_client.execute(auth_req.build()?)?;
         let status = r.status();
         trace!("authenticate: got status {}", status);
-        if status != StatusCode::OK {
-            return Err(Error::UnexpectedHttpStatus(status));
-        }
-
-        let bearer_auth = r.json::<BearerAuth>()?;
+        let bearer_auth: BearerAuth = match status {
+            StatusCode::OK => r.json::<TokenResponse>()?.into(),
+            // The token endpoint only implements the OAuth2 flow
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => {
+                debug!("authenticate: GET returned {}, trying OAuth2 POST", status);
+                Self::fetch_oauth2_token(client, scopes, credentials, &bearer_header_content)?
+                    .into()
+            }
+            _ => return Err(Error::from_response(&reqwest::Method::GET, r)),
+        };
+        Self::finish_token(bearer_auth, bearer_header_content, scopes)
+    }
 
+    /// Validate a token from the endpoint and remember how to refresh it.
+    fn finish_token(
+        bearer_auth: BearerAuth,
+        bearer_header_content: WwwAuthenticateHeaderContentBearer,
+        scopes: &[&str],
+    ) -> Result<Self> {
         match bearer_auth.token.as_str() {
             "unauthenticated" | "" => return Err(Error::InvalidAuthToken(bearer_auth.token)),
             _ => {}
@@ -81,7 +152,198 @@ impl BearerAuth {
 
         trace!("authenticate: got token: {:?}", masked_token);
 
-        Ok(bearer_auth)
+        let scopes = scopes.iter().map(ToString::to_string).collect();
+        Ok(BearerAuth {
+            refresh: Some((bearer_header_content, scopes)),
+            ..bearer_auth
+        })
+    }
+
+    /// Request a token with the OAuth2 form POST flow.
+    ///
+    /// Credentials are sent with the `password` grant, or with the
+    /// `refresh_token` grant for identity tokens, which use `<token>` as
+    /// user name.
+    fn fetch_oauth2_token(
+        client: Client,
+        scopes: &[&str],
+        credentials: Option<(String, String)>,
+        bearer_header_content: &WwwAuthenticateHeaderContentBearer,
+    ) -> Result<TokenResponse> {
+        let url = reqwest::Url::parse(&bearer_header_content.realm)?;
+
+        let mut form = vec![("client_id", OAUTH2_CLIENT_ID.to_string())];
+        if let Some(service) = &bearer_header_content.service {
+            form.push(("service", service.clone()));
+        }
+        if !scopes.is_empty() {
+            form.push(("scope", scopes.join(" ")));
+        } else if let Some(scope) = &bearer_header_content.scope {
+            form.push(("scope", scope.clone()));
+        }
+        match credentials {
+            Some((user, token)) if user == IDENTITY_TOKEN_USER => {
+                form.push(("grant_type", "refresh_token".to_string()));
+                form.push(("refresh_token", token));
+            }
+            Some((user, password)) => {
+                form.push(("grant_type", "password".to_string()));
+                form.push(("username", user));
+                form.push(("password", password));
+            }
+            None => return Err(Error::NoCredentials),
+        }
+
+        let client = Client {
+            auth: Default::default(),
+            ..client
+        };
+        let r = client.execute(
+            client
+                .build_reqwest(reqwest::Method::POST, url)
+                .form(&form)
+                .build()?,
+        )?;
+        let status = r.status();
+        trace!("authenticate: OAuth2 POST got status {}", status);
+        if status != StatusCode::OK {
+            return Err(Error::from_response(&reqwest::Method::POST, r));
+        }
+
+        Ok(r.json::<TokenResponse>()?)
+    }
+}
+
+/// Token endpoint response, for both the Docker and the OAuth2 flavour.
+#[derive(Default, Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+    expires_in: Option<u32>,
+    issued_at: Option<String>,
+    refresh_token: Option<String>,
+}
+
+impl std::fmt::Debug for TokenResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Same as for `BearerAuth`, responses are logged when debugging logins
+        let redacted = |token: &Option<String>| token.as_ref().map(|_| "<redacted>");
+        f.debug_struct("TokenResponse")
+            .field("token", &redacted(&self.token))
+            .field("access_token", &redacted(&self.access_token))
+            .field("expires_in", &self.expires_in)
+            .field("issued_at", &self.issued_at)
+            .field("refresh_token", &redacted(&self.refresh_token))
+            .finish()
+    }
+}
+
+impl From<TokenResponse> for BearerAuth {
+    fn from(response: TokenResponse) -> Self {
+        // Tokens count from their receipt. The server's issue date only
+        // shortens that, by no more than the clock skew we tolerate, so that
+        // skewed clocks neither extend tokens nor expire them on arrival.
+        let received_at = SystemTime::now();
+        let issued_at = response
+            .issued_at
+            .as_deref()
+            .and_then(|issued_at| humantime::parse_rfc3339_weak(issued_at).ok())
+            .map_or(received_at, |issued_at| {
+                issued_at.clamp(received_at - TOKEN_EXPIRY_SKEW, received_at)
+            });
+        let lifetime = response
+            .expires_in
+            .map_or(DEFAULT_TOKEN_LIFETIME, |s| Duration::from_secs(s.into()));
+
+        BearerAuth {
+            token: response.token.or(response.access_token).unwrap_or_default(),
+            expires_in: response.expires_in,
+            issued_at: response.issued_at,
+            refresh_token: response.refresh_token,
+            expires_at: Some(issued_at + lifetime),
+            refresh: None,
+        }
+    }
+}
+
+/// Result of a request to the registry with the current authentication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthStatus {
+    /// Requests succeed without credentials, possibly with an anonymous token.
+    Anonymous,
+    /// Requests succeed with the configured credentials or token.
+    Authenticated,
+    /// Requests fail and no credentials are configured.
+    Unauthorized,
+    /// Requests fail although credentials are configured.
+    CredentialsRejected,
+}
+
+/// Access requested for a token, like `repository:org/app:pull,push`.
+///
+/// Format is described at https://docs.docker.com/registry/spec/auth/scope/.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Scope {
+    resource_type: String,
+    name: String,
+    actions: Vec<String>,
+}
+
+impl Scope {
+    /// Build a scope for arbitrary resources and actions.
+    pub fn new(resource_type: &str, name: &str, actions: &[&str]) -> Self {
+        Scope {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            actions: actions.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    /// Pull access to a repository.
+    pub fn repo_pull(name: &str) -> Self {
+        Self::new("repository", name, &["pull"])
+    }
+
+    /// Pull and push access to a repository.
+    pub fn repo_push_pull(name: &str) -> Self {
+        Self::new("repository", name, &["pull", "push"])
+    }
+
+    /// Access to the catalog of repositories.
+    pub fn catalog() -> Self {
+        Self::new("registry", "catalog", &["*"])
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.resource_type,
+            self.name,
+            self.actions.join(",")
+        )
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = WwwHeaderParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // Names may contain a registry port, so actions are split from the end
+        let (resource_type, rest) = s.split_once(':').ok_or(WwwHeaderParseError::InvalidScope)?;
+        let (name, actions) = rest
+            .rsplit_once(':')
+            .ok_or(WwwHeaderParseError::InvalidScope)?;
+        if resource_type.is_empty() || name.is_empty() {
+            return Err(WwwHeaderParseError::InvalidScope);
+        }
+        Ok(Scope {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            actions: actions.split(',').map(ToString::to_string).collect(),
+        })
     }
 }
 
@@ -93,99 +355,194 @@ pub struct BasicAuth {
 }
 
 /// Structured representation for the content of the authentication response header.
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Debug, PartialEq, Eq)]
 pub(crate) enum WwwAuthenticateHeaderContent {
     Bearer(WwwAuthenticateHeaderContentBearer),
     Basic(WwwAuthenticateHeaderContentBasic),
 }
 
-const REGEX: &str = r#"(?x)\s*
-((?P<method>[A-Z][a-z]+)\s*)?
-(
-    \s*
-        (?P<key>[a-z]+)
-    \s*
-        =
-    \s*
-        "(?P<value>[^"]+)"
-    \s*
-)
-"#;
-
 #[derive(Debug, thiserror::Error)]
 pub enum WwwHeaderParseError {
-    #[error("header value must conform to {}", REGEX)]
+    #[error("header value is not a valid list of challenges")]
     InvalidValue,
     #[error("'method' field missing")]
     FieldMethodMissing,
+    #[error("challenge for {0} lacks the 'realm' parameter")]
+    RealmMissing(String),
+    #[error("no supported authentication scheme in challenges")]
+    UnsupportedScheme,
+    #[error("scope must have the form 'type:name:actions'")]
+    InvalidScope,
 }
 
-impl WwwAuthenticateHeaderContent {
-    /// Create a `WwwAuthenticateHeaderContent` by parsing a `HeaderValue` instance.
-    pub(crate) fn from_www_authentication_header(header_value: HeaderValue) -> Result<Self> {
-        let header = String::from_utf8(header_value.as_bytes().to_vec())?;
-
-        // This regex will result in multiple captures which will contain one key-value pair each.
-        // The first capture will be the only one with the "method" group set.
-        let re = regex::Regex::new(REGEX).expect("this static regex is valid");
-        let captures = re.captures_iter(&header).collect::<Vec<_>>();
-
-        let method = captures
-            .get(0)
-            .ok_or(WwwHeaderParseError::InvalidValue)?
-            .name("method")
-            .ok_or(WwwHeaderParseError::FieldMethodMissing)?
-            .as_str()
-            .to_string();
-
-        let serialized_content = {
-            let serialized_captures = captures
-                .iter()
-                .filter_map(|capture| {
-                    match (
-                        capture.name("key").map(|n| n.as_str().to_string()),
-                        capture.name("value").map(|n| n.as_str().to_string()),
-                    ) {
-                        (Some(key), Some(value)) => Some(format!(
-                            r#"{}: {}"#,
-                            serde_json::Value::String(key),
-                            serde_json::Value::String(value),
-                        )),
-                        _ => None,
-                    }
-                })
-                .collect::<Vec<_>>()
-                .join(", ");
-
-            format!(
-                r#"{{ {}: {{ {} }} }}"#,
-                serde_json::Value::String(method),
-                serialized_captures
-            )
-        };
+/// A single authentication challenge, as defined by RFC 7235.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Challenge {
+    /// Authentication scheme, like `Bearer`.
+    scheme: String,
+    /// Parameters with lower-cased names.
+    params: Vec<(String, String)>,
+}
 
-        // Deserialize the content
-        let mut unsupported_keys = std::collections::HashSet::new();
-        let content: WwwAuthenticateHeaderContent = serde_ignored::deserialize(
-            &mut serde_json::Deserializer::from_str(&serialized_content),
-            |path| {
-                unsupported_keys.insert(path.to_string());
-            },
-        )?;
+impl Challenge {
+    fn param(&self, name: &str) -> Option<String> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    }
 
-        if !unsupported_keys.is_empty() {
+    fn warn_unknown_params(&self, known: &[&str]) {
+        let unknown = self
+            .params
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .filter(|key| !known.contains(key))
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
             warn!(
-                "skipping unrecognized keys in authentication header: {:#?}",
-                unsupported_keys
+                "skipping unrecognized keys in authentication header: {:?}",
+                unknown
             );
         }
+    }
+}
+
+/// Parse all challenges of a `WWW-Authenticate` header value.
+///
+/// Challenges and their parameters are both separated by commas. A new
+/// challenge starts with a token which isn't followed by `=`. Token68
+/// credentials of unknown schemes are skipped.
+fn parse_challenges(header: &str) -> std::result::Result<Vec<Challenge>, WwwHeaderParseError> {
+    fn is_tchar(c: char) -> bool {
+        c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+    }
+
+    let mut challenges: Vec<Challenge> = Vec::new();
+    let mut chars = header.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut token = String::new();
+        while let Some(c) = chars.peek().copied().filter(|c| is_tchar(*c) || *c == '/') {
+            token.push(c);
+            chars.next();
+        }
+        if token.is_empty() {
+            return Err(WwwHeaderParseError::InvalidValue);
+        }
+        while chars.peek().is_some_and(|c| *c == ' ' || *c == '\t') {
+            chars.next();
+        }
 
-        Ok(content)
+        if chars.peek() != Some(&'=') {
+            challenges.push(Challenge {
+                scheme: token,
+                params: Vec::new(),
+            });
+            continue;
+        }
+        chars.next();
+        while chars.peek().is_some_and(|c| *c == ' ' || *c == '\t') {
+            chars.next();
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => value.extend(chars.next()),
+                    Some(c) => value.push(c),
+                    None => return Err(WwwHeaderParseError::InvalidValue),
+                }
+            }
+        } else {
+            while let Some(c) = chars
+                .peek()
+                .copied()
+                .filter(|c| *c != ',' && !c.is_whitespace())
+            {
+                value.push(c);
+                chars.next();
+            }
+        }
+
+        let challenge = challenges
+            .last_mut()
+            .ok_or(WwwHeaderParseError::FieldMethodMissing)?;
+        if value.is_empty() || value.starts_with('=') {
+            // Padding of a token68 credential, not a parameter
+            continue;
+        }
+        challenge.params.push((token.to_ascii_lowercase(), value));
+    }
+
+    Ok(challenges)
+}
+
+impl WwwAuthenticateHeaderContent {
+    /// Create a `WwwAuthenticateHeaderContent` from all `WWW-Authenticate` header values.
+    ///
+    /// Each value may hold several challenges. Bearer is preferred over Basic,
+    /// and challenges with other schemes are ignored.
+    pub(crate) fn from_www_authentication_headers<'a, I>(header_values: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a HeaderValue>,
+    {
+        let mut challenges = Vec::new();
+        for header_value in header_values {
+            let header = String::from_utf8(header_value.as_bytes().to_vec())?;
+            challenges.extend(parse_challenges(&header)?);
+        }
+
+        let find = |scheme: &str| {
+            challenges
+                .iter()
+                .find(|c| c.scheme.eq_ignore_ascii_case(scheme))
+        };
+
+        if let Some(challenge) = find("Bearer") {
+            challenge.warn_unknown_params(&[
+                "realm",
+                "service",
+                "scope",
+                "error",
+                "error_description",
+            ]);
+            let realm = challenge
+                .param("realm")
+                .ok_or_else(|| WwwHeaderParseError::RealmMissing(challenge.scheme.clone()))?;
+            Ok(WwwAuthenticateHeaderContent::Bearer(
+                WwwAuthenticateHeaderContentBearer {
+                    realm,
+                    service: challenge.param("service"),
+                    scope: challenge.param("scope"),
+                },
+            ))
+        } else if let Some(challenge) = find("Basic") {
+            challenge.warn_unknown_params(&["realm", "charset"]);
+            Ok(WwwAuthenticateHeaderContent::Basic(
+                WwwAuthenticateHeaderContentBasic {
+                    realm: challenge.param("realm").unwrap_or_default(),
+                },
+            ))
+        } else if challenges.is_empty() {
+            Err(WwwHeaderParseError::InvalidValue.into())
+        } else {
+            Err(WwwHeaderParseError::UnsupportedScheme.into())
+        }
     }
 }
 
 /// Structured content for the Bearer authentication response header.
-#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub(crate) struct WwwAuthenticateHeaderContentBearer {
     realm: String,
     service: Option<String>,
@@ -193,85 +550,121 @@ pub(crate) struct WwwAuthenticateHeaderContentBearer {
 }
 
 impl WwwAuthenticateHeaderContentBearer {
-    fn auth_ep(&self, scopes: &[&str]) -> String {
-        let service = self
-            .service
-            .as_ref()
-            .map(|sv| format!("?service={}", sv))
-            .unwrap_or_default();
-
-        let scope = scopes
-            .iter()
-            .enumerate()
-            .fold(String::new(), |acc, (i, &s)| {
-                let separator = if i > 0 { "&" } else { "" };
-                acc + separator + "scope=" + s
-            });
-
-        let scope_prefix = if scopes.is_empty() {
-            ""
-        } else if service.is_empty() {
-            "?"
-        } else {
-            "&"
+    /// URL of the token endpoint for the given scopes.
+    ///
+    /// The `service` of the challenge is passed on verbatim, token services
+    /// like ghcr.io deny tokens for any other. Without requested scopes, the
+    /// scope of the challenge is used. Query parameters of the realm are kept.
+    fn auth_ep(&self, scopes: &[&str]) -> Result<Url> {
+        let mut url = Url::parse(&self.realm)?;
+        let scopes = match (scopes, &self.scope) {
+            ([], Some(scope)) => scope.split_whitespace().collect(),
+            _ => scopes.to_vec(),
         };
-
-        format!("{}{}{}{}", self.realm, service, scope_prefix, scope)
+        if self.service.is_some() || !scopes.is_empty() {
+            let mut query = url.query_pairs_mut();
+            if let Some(service) = &self.service {
+                query.append_pair("service", service);
+            }
+            for scope in scopes {
+                query.append_pair("scope", scope);
+            }
+        }
+        Ok(url)
     }
 }
 
 /// Structured content for the Basic authentication response header.
-#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Default, PartialEq, Eq)]
 pub(crate) struct WwwAuthenticateHeaderContentBasic {
     realm: String,
 }
 
 impl Client {
-    /// Make a request and return the response's www authentication header.
-
-    fn get_www_authentication_header(&self) -> Result<HeaderValue> {
+    /// Make a request and return the response's www authentication headers, if any.
+    fn get_www_authentication_headers(&self) -> Result<Vec<HeaderValue>> {
         let url = {
             let ep = format!("{}/v2/", self.base_url.clone(),);
             reqwest::Url::parse(&ep)?
         };
 
-        let r = self.build_reqwest(reqwest::Method::GET, url).send()?;
+        let r = self.execute(self.build_reqwest(reqwest::Method::GET, url).build()?)?;
 
         trace!("GET '{}' status: {:?}", r.url(), r.status());
-        r.headers()
-            .get(reqwest::header::WWW_AUTHENTICATE)
-            .ok_or(Error::MissingAuthHeader("WWW-Authenticate"))
-            .map(ToOwned::to_owned)
+        Ok(r.headers()
+            .get_all(reqwest::header::WWW_AUTHENTICATE)
+            .iter()
+            .cloned()
+            .collect())
     }
 
     /// Perform registry authentication and return the authenticated client.
     ///
     /// If Bearer authentication is used the returned client will be authorized for the requested scopes.
-    pub fn authenticate(mut self, scopes: &[&str]) -> Result<Self> {
-        let credentials = self.credentials.clone();
+    /// Registries asking for Basic authentication, or not sending a challenge at all,
+    /// get the configured credentials with every request.
+    /// Without credentials an anonymous token is requested, which is enough to pull
+    /// public images from registries like ghcr.io or Docker Hub.
+    /// The scopes are requested again when the token is refreshed.
+    /// A client configured with `Config::bearer_token` is returned unchanged.
+    pub fn authenticate(mut self, scopes: &[Scope]) -> Result<Self> {
+        if self.static_token {
+            trace!("authenticate: using pre-acquired bearer token");
+            return Ok(self);
+        }
+
+        let scopes = scopes.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let scopes = scopes.iter().map(String::as_str).collect::<Vec<_>>();
 
         let client = Client {
-            auth: None,
+            auth: Default::default(),
             ..self.clone()
         };
 
-        let authentication_header = client.get_www_authentication_header()?;
-        let auth = match WwwAuthenticateHeaderContent::from_www_authentication_header(
-            authentication_header,
-        )? {
-            WwwAuthenticateHeaderContent::Basic(_) => {
-                let basic_auth = credentials
-                    .map(|(user, password)| BasicAuth {
-                        user,
-                        password: Some(password),
-                    })
-                    .ok_or(Error::NoCredentials)?;
-
-                Auth::Basic(basic_auth)
+        let authentication_headers = client.get_www_authentication_headers()?;
+        let auth = client.login(&scopes, &authentication_headers)?;
+        if auth.is_some() {
+            trace!("authenticate: login succeeded");
+        }
+        self.auth = Arc::new(RwLock::new(auth));
+
+        Ok(self)
+    }
+
+    /// Answer the authentication challenges of the registry for `scopes`.
+    ///
+    /// Returns `None` when the client continues anonymously. `self` must not
+    /// carry any authentication itself.
+    fn login(
+        self,
+        scopes: &[&str],
+        authentication_headers: &[HeaderValue],
+    ) -> Result<Option<Auth>> {
+        let credentials = self.credentials.clone();
+        let content = if authentication_headers.is_empty() {
+            // Registries without a token service may not challenge at all
+            if credentials.is_none() {
+                return Err(Error::MissingAuthHeader("WWW-Authenticate"));
             }
+            debug!("authenticate: no challenge, falling back to basic auth");
+            WwwAuthenticateHeaderContent::Basic(Default::default())
+        } else {
+            WwwAuthenticateHeaderContent::from_www_authentication_headers(authentication_headers)?
+        };
+        let auth = match content {
+            WwwAuthenticateHeaderContent::Basic(_) => match credentials {
+                Some((user, password)) => Auth::Basic(BasicAuth {
+                    user,
+                    password: Some(password),
+                }),
+                None => {
+                    debug!("authenticate: no credentials for basic auth, continuing anonymously");
+                    return Ok(None);
+                }
+            },
             WwwAuthenticateHeaderContent::Bearer(bearer_header_content) => {
                 let bearer_auth = BearerAuth::try_from_header_content(
-                    client,
+                    self,
                     scopes,
                     credentials,
                     bearer_header_content,
@@ -280,11 +673,284 @@ impl Client {
                 Auth::Bearer(bearer_auth)
             }
         };
+        Ok(Some(auth))
+    }
 
-        trace!("authenticate: login succeeded");
-        self.auth = Some(auth);
+    /// Check that the registry is reachable and accepts the client, logging in if needed.
+    ///
+    /// This probes the `/v2/` endpoint. If the registry challenges the
+    /// client, the credentials are exchanged for a token right away, which
+    /// this client and its clones then use, so later requests don't have to
+    /// log in first. Hosts which aren't v2 registries fail with
+    /// `Error::V2NotSupported`, rejected clients with `Error::Unauthorized`
+    /// wrapped in `Error::RequestFailed`.
+    pub fn ping(&self) -> Result<()> {
+        let url = Url::parse(&format!("{}/v2/", self.base_url))?;
+        let mut response = self.execute(self.build_reqwest(Method::GET, url.clone()).build()?)?;
+        trace!("GET '{}' status: {:?}", response.url(), response.status());
 
-        Ok(self)
+        if response.status() == StatusCode::UNAUTHORIZED && !self.static_token {
+            let challenges = response
+                .headers()
+                .get_all(reqwest::header::WWW_AUTHENTICATE)
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>();
+            let client = Client {
+                auth: Default::default(),
+                ..self.clone()
+            };
+            let logged_in = {
+                let _refresh = self.lock_auth_refresh();
+                let auth = client.login(&[], &challenges)?;
+                let logged_in = auth.is_some();
+                if logged_in {
+                    debug!("ping: logged in");
+                    *self.auth.write().unwrap_or_else(PoisonError::into_inner) = auth;
+                }
+                logged_in
+            };
+            // Not holding the lock, as a short-lived token is refreshed right away
+            if logged_in {
+                response = self.execute(self.build_reqwest(Method::GET, url).build()?)?;
+            }
+        }
+
+        let is_v2 = response
+            .headers()
+            .get(crate::API_VERSION_HEADER)
+            .is_some_and(crate::is_v2_api_version);
+        match response.status() {
+            StatusCode::OK if is_v2 => Ok(()),
+            StatusCode::OK => Err(Error::V2NotSupported),
+            status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                Err(Error::Unauthorized(status).with_request(&Method::GET, response.url()))
+            }
+            status => Err(Error::from_status(status, response.headers())
+                .with_request(&Method::GET, response.url())),
+        }
+    }
+
+    /// Pull scope for a repository of this registry, to pass to `authenticate`.
+    ///
+    /// The name may start with the registry host, which token services don't
+    /// expect in scopes. On ghcr.io, which only knows lowercase repository
+    /// names and denies tokens for others, the name is lowercased.
+    pub fn pull_scope(&self, name: &str) -> Scope {
+        let name = name
+            .strip_prefix(self.index.as_str())
+            .and_then(|name| name.strip_prefix('/'))
+            .unwrap_or(name);
+        if self.index == "ghcr.io" {
+            Scope::repo_pull(&name.to_ascii_lowercase())
+        } else {
+            Scope::repo_pull(name)
+        }
+    }
+
+    /// Return the authentication currently used by this client and its clones.
+    pub(crate) fn current_auth(&self) -> Option<Auth> {
+        self.auth
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Return when the current bearer token expires.
+    ///
+    /// This is `None` if the client doesn't use a token or its lifetime is
+    /// unknown, as for tokens set with `Config::bearer_token`.
+    pub fn token_expires_at(&self) -> Option<SystemTime> {
+        match self.current_auth() {
+            Some(Auth::Bearer(bearer_auth)) => bearer_auth.expires_at,
+            _ => None,
+        }
+    }
+
+    /// Refresh the bearer token if it is about to expire.
+    ///
+    /// Failures are only logged, the request then goes out with the old
+    /// token and a `401` is handled by `send_reqwest`. So is a refresh
+    /// already in progress: the refresh lock isn't waited for, which also
+    /// keeps callers holding it from deadlocking when they build requests.
+    pub(crate) fn refresh_expiring_token(&self) {
+        if !self.token_expiring() {
+            return;
+        }
+        let _refresh = match self.auth_refresh.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                trace!("token refresh in progress, not waiting for it");
+                return;
+            }
+        };
+        // Another thread may have refreshed the token since it was checked
+        let bearer_auth = match self.current_auth() {
+            Some(Auth::Bearer(bearer_auth)) => bearer_auth,
+            _ => return,
+        };
+        let (expires_at, (challenge, scopes)) = match (bearer_auth.expires_at, bearer_auth.refresh)
+        {
+            (Some(expires_at), Some(refresh)) => (expires_at, refresh),
+            _ => return,
+        };
+        if SystemTime::now() + TOKEN_EXPIRY_SKEW < expires_at {
+            return;
+        }
+
+        debug!("token is about to expire, refreshing");
+        let scopes = scopes.iter().map(String::as_str).collect::<Vec<_>>();
+        let client = Client {
+            auth: Default::default(),
+            ..self.clone()
+        };
+        match BearerAuth::try_from_header_content(
+            client,
+            &scopes,
+            self.credentials.clone(),
+            challenge,
+        ) {
+            Ok(bearer_auth) => {
+                *self.auth.write().unwrap_or_else(PoisonError::into_inner) =
+                    Some(Auth::Bearer(bearer_auth));
+            }
+            Err(e) => warn!("unable to refresh expiring token: {}", e),
+        }
+    }
+
+    /// Whether the bearer token expires soon and can be refreshed.
+    fn token_expiring(&self) -> bool {
+        match self.current_auth() {
+            Some(Auth::Bearer(BearerAuth {
+                expires_at: Some(expires_at),
+                refresh: Some(_),
+                ..
+            })) => SystemTime::now() + TOKEN_EXPIRY_SKEW >= expires_at,
+            _ => false,
+        }
+    }
+
+    /// Serialize token refreshes between clones and threads.
+    ///
+    /// The lock isn't reentrant. Requests built while holding it still go
+    /// through `refresh_expiring_token`, which skips the refresh rather than
+    /// waiting for the lock.
+    fn lock_auth_refresh(&self) -> MutexGuard<'_, ()> {
+        self.auth_refresh
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Send a request, refreshing an expired bearer token on `401`.
+    ///
+    /// The request is retried exactly once with the new token. Responses
+    /// which can't be answered by a token refresh are returned unchanged.
+    pub(crate) fn send_reqwest(&self, builder: RequestBuilder) -> Result<Response> {
+        let request = builder.build()?;
+        let retry = request.try_clone();
+        let response = self.execute(request)?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let mut retry = match retry {
+            Some(retry) => retry,
+            None => return Ok(response),
+        };
+        let rejected = retry.headers().get(reqwest::header::AUTHORIZATION).cloned();
+        match self.refresh_bearer_auth(&response, rejected.as_ref())? {
+            Some(value) => {
+                trace!("retrying {} with a refreshed token", retry.url());
+                retry
+                    .headers_mut()
+                    .insert(reqwest::header::AUTHORIZATION, value);
+                Ok(self.execute(retry)?)
+            }
+            None => Ok(response),
+        }
+    }
+
+    /// Acquire a new bearer token for the scope of a `401` challenge.
+    ///
+    /// The token is shared with all clones of this client. Returns the new
+    /// `Authorization` header value, or `None` if the client doesn't use
+    /// token authentication or the challenge names no scope.
+    ///
+    /// `rejected` is the `Authorization` header of the failed request. If
+    /// another thread replaced that token in the meantime, its token is
+    /// reused instead of requesting one more.
+    fn refresh_bearer_auth(
+        &self,
+        response: &Response,
+        rejected: Option<&HeaderValue>,
+    ) -> Result<Option<HeaderValue>> {
+        if self.static_token {
+            return Ok(None);
+        }
+        let _refresh = self.lock_auth_refresh();
+        let remembered = match self.current_auth() {
+            Some(Auth::Bearer(bearer_auth)) => {
+                let current = bearer_auth.header_value()?;
+                if rejected.is_some_and(|rejected| *rejected != current) {
+                    trace!("token was refreshed concurrently, reusing it");
+                    return Ok(Some(current));
+                }
+                bearer_auth
+                    .refresh
+                    .map(|(_, scopes)| scopes)
+                    .unwrap_or_default()
+            }
+            _ => return Ok(None),
+        };
+
+        let challenges = response
+            .headers()
+            .get_all(reqwest::header::WWW_AUTHENTICATE);
+        let content =
+            match WwwAuthenticateHeaderContent::from_www_authentication_headers(challenges) {
+                Ok(WwwAuthenticateHeaderContent::Bearer(content)) => content,
+                _ => return Ok(None),
+            };
+        let scope = match content.scope.clone() {
+            Some(scope) => scope,
+            None => return Ok(None),
+        };
+
+        debug!("token rejected, refreshing for scope {}", scope);
+        // Keep the scopes chosen at login, the challenge only names the current one
+        let mut scopes = remembered;
+        for scope in scope.split_whitespace() {
+            if !scopes.iter().any(|s| s == scope) {
+                scopes.push(scope.to_string());
+            }
+        }
+        let scopes = scopes.iter().map(String::as_str).collect::<Vec<_>>();
+        let bearer_auth = BearerAuth::try_from_header_content(
+            self.clone(),
+            &scopes,
+            self.credentials.clone(),
+            content,
+        )?;
+        let value = bearer_auth.header_value()?;
+        *self.auth.write().unwrap_or_else(PoisonError::into_inner) =
+            Some(Auth::Bearer(bearer_auth));
+
+        Ok(Some(value))
+    }
+
+    /// Check whether requests succeed, and whether credentials were used for that.
+    ///
+    /// This tells apart anonymous access from rejected credentials, which
+    /// `is_auth` and `is_v2_supported_and_authorized` both report as a plain `false`.
+    pub fn auth_status(&self) -> Result<AuthStatus> {
+        let has_credentials = self.credentials.is_some() || self.static_token;
+        Ok(match (self.is_auth()?, has_credentials) {
+            (true, false) => AuthStatus::Anonymous,
+            (true, true) => AuthStatus::Authenticated,
+            (false, false) => AuthStatus::Unauthorized,
+            (false, true) => AuthStatus::CredentialsRejected,
+        })
     }
 
     /// Check whether the client can successfully make requests to the registry.
@@ -299,14 +965,15 @@ impl Client {
         let req = self.build_reqwest(reqwest::Method::GET, url.clone());
 
         trace!("Sending request to '{}'", url);
-        let resp = req.send()?;
+        let resp = self.execute(req.build()?)?;
         trace!("GET '{:?}'", resp);
 
         let status = resp.status();
         match status {
             reqwest::StatusCode::OK => Ok(true),
             reqwest::StatusCode::UNAUTHORIZED => Ok(false),
-            _ => Err(Error::UnexpectedHttpStatus(status)),
+            _ => Err(Error::from_status(status, resp.headers())
+                .with_request(&reqwest::Method::GET, resp.url())),
         }
     }
 }
@@ -316,6 +983,19 @@ mod tests {
     use super::*;
     use test_case::test_case;
 
+    #[test]
+    fn token_response_debug_is_redacted() {
+        let response: TokenResponse = serde_json::from_str(
+            r#"{"token": "t0ken", "access_token": "acc3ss", "refresh_token": "r3fresh", "expires_in": 300}"#,
+        )
+        .unwrap();
+        let debug = format!("{:?}", response);
+        for secret in ["t0ken", "acc3ss", "r3fresh"] {
+            assert!(!debug.contains(secret), "{}", debug);
+        }
+        assert!(debug.contains("300"), "{}", debug);
+    }
+
     #[test]
     fn bearer_realm_parses_correctly() -> Result<()> {
         let realm = "https://sat-r220-02.lab.eng.rdu2.redhat.com/v2/token";
@@ -327,7 +1007,8 @@ mod tests {
             realm, service, scope
         )).expect("this statically known header value only contains ASCII chars so it is correct header value");
 
-        let content = WwwAuthenticateHeaderContent::from_www_authentication_header(header_value)?;
+        let content =
+            WwwAuthenticateHeaderContent::from_www_authentication_headers(&[header_value])?;
 
         assert_eq!(
             WwwAuthenticateHeaderContent::Bearer(WwwAuthenticateHeaderContentBearer {
@@ -355,7 +1036,8 @@ mod tests {
 
         let header_value = HeaderValue::from_str(&format!(r#"Basic realm="{}""#, realm)).unwrap();
 
-        let content = WwwAuthenticateHeaderContent::from_www_authentication_header(header_value)?;
+        let content =
+            WwwAuthenticateHeaderContent::from_www_authentication_headers(&[header_value])?;
 
         assert_eq!(
             WwwAuthenticateHeaderContent::Basic(WwwAuthenticateHeaderContentBasic {
@@ -399,12 +1081,173 @@ mod tests {
             expected_headers.insert(0, ("service".to_owned(), service.to_string()));
         }
 
-        let result = bearer_header_content.auth_ep(scopes);
-        let url = Url::parse(&result).unwrap();
+        let url = bearer_header_content.auth_ep(scopes).unwrap();
 
         assert_eq!(
             url.query_pairs().into_owned().collect::<Vec<_>>(),
             expected_headers
         );
     }
+
+    #[test]
+    fn bearer_auth_ep_keeps_challenge_verbatim() {
+        let bearer_header_content = WwwAuthenticateHeaderContentBearer {
+            realm: "https://ghcr.io/token?client=docker".to_string(),
+            service: Some("ghcr.io".to_string()),
+            scope: Some("repository:user/image:pull".to_string()),
+        };
+
+        let url = bearer_header_content.auth_ep(&[]).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://ghcr.io/token?client=docker&service=ghcr.io&scope=repository%3Auser%2Fimage%3Apull"
+        );
+        let url = bearer_header_content
+            .auth_ep(&["repository:acheta/app:pull"])
+            .unwrap();
+        assert_eq!(
+            url.query_pairs().into_owned().collect::<Vec<_>>(),
+            vec![
+                ("client".to_string(), "docker".to_string()),
+                ("service".to_string(), "ghcr.io".to_string()),
+                (
+                    "scope".to_string(),
+                    "repository:acheta/app:pull".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test_case("registry-1.docker.io", "library/alpine", "repository:library/alpine:pull"; "docker hub")]
+    #[test_case("ghcr.io", "AchetaGames/App", "repository:achetagames/app:pull"; "ghcr lowercase")]
+    #[test_case("ghcr.io", "ghcr.io/acheta/app", "repository:acheta/app:pull"; "ghcr with host")]
+    fn pull_scope_for_registry(index: &str, name: &str, expected: &str) {
+        let client = Client::configure().registry(index).build().unwrap();
+        assert_eq!(client.pull_scope(name).to_string(), expected);
+    }
+
+    #[test_case(-10, 10; "issued earlier")]
+    #[test_case(-3600, 30; "server clock behind")]
+    #[test_case(3600, 0; "server clock ahead")]
+    fn token_expiry_shortened_by_issue_date(offset: i64, shortened: u64) {
+        let now = SystemTime::now();
+        let issued_at = if offset < 0 {
+            now - Duration::from_secs(offset.unsigned_abs())
+        } else {
+            now + Duration::from_secs(offset as u64)
+        };
+        let bearer_auth = BearerAuth::from(TokenResponse {
+            token: Some("token".to_string()),
+            expires_in: Some(300),
+            issued_at: Some(humantime::format_rfc3339_seconds(issued_at).to_string()),
+            ..Default::default()
+        });
+        let lifetime = bearer_auth
+            .expires_at
+            .unwrap()
+            .duration_since(now)
+            .unwrap()
+            .as_secs();
+        // The issue date is truncated to seconds
+        assert!((300 - shortened - 1..=300 - shortened + 1).contains(&lifetime));
+    }
+
+    #[test]
+    fn token_expiry_defaults_to_sixty_seconds() {
+        let before = SystemTime::now();
+        let bearer_auth = BearerAuth::from(TokenResponse {
+            access_token: Some("token".to_string()),
+            ..Default::default()
+        });
+        let expires_at = bearer_auth.expires_at.unwrap();
+        assert!(expires_at >= before + DEFAULT_TOKEN_LIFETIME);
+        assert!(expires_at <= SystemTime::now() + DEFAULT_TOKEN_LIFETIME);
+    }
+
+    #[test_case(Scope::repo_pull("org/app"), "repository:org/app:pull"; "pull")]
+    #[test_case(Scope::repo_push_pull("org/app"), "repository:org/app:pull,push"; "push and pull")]
+    #[test_case(Scope::catalog(), "registry:catalog:*"; "catalog")]
+    #[test_case(Scope::repo_pull("localhost:5000/app"), "repository:localhost:5000/app:pull"; "name with port")]
+    fn scope_round_trips(scope: Scope, expected: &str) {
+        assert_eq!(scope.to_string(), expected);
+        assert_eq!(expected.parse::<Scope>().unwrap(), scope);
+    }
+
+    #[test]
+    fn scope_rejects_missing_actions() {
+        assert!("repository".parse::<Scope>().is_err());
+        assert!("repository:org/app".parse::<Scope>().is_err());
+    }
+
+    fn bearer(
+        realm: &str,
+        service: Option<&str>,
+        scope: Option<&str>,
+    ) -> WwwAuthenticateHeaderContent {
+        WwwAuthenticateHeaderContent::Bearer(WwwAuthenticateHeaderContentBearer {
+            realm: realm.to_string(),
+            service: service.map(ToString::to_string),
+            scope: scope.map(ToString::to_string),
+        })
+    }
+
+    fn basic(realm: &str) -> WwwAuthenticateHeaderContent {
+        WwwAuthenticateHeaderContent::Basic(WwwAuthenticateHeaderContentBasic {
+            realm: realm.to_string(),
+        })
+    }
+
+    #[test_case(
+        &[r#"Bearer realm="https://ghcr.io/token",service="ghcr.io",scope="repository:a/b:pull,push""#],
+        bearer("https://ghcr.io/token", Some("ghcr.io"), Some("repository:a/b:pull,push"));
+        "ghcr with comma in scope"
+    )]
+    #[test_case(
+        &[r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull",error="insufficient_scope""#],
+        bearer("https://auth.docker.io/token", Some("registry.docker.io"), Some("repository:library/alpine:pull"));
+        "docker hub with error"
+    )]
+    #[test_case(
+        &[r#"Basic realm="Registry Realm", Bearer realm="https://r.example.com/token", service="r""#],
+        bearer("https://r.example.com/token", Some("r"), None);
+        "basic and bearer in one header"
+    )]
+    #[test_case(
+        &[r#"Basic realm="Registry Realm""#, r#"Bearer realm="https://r.example.com/token""#],
+        bearer("https://r.example.com/token", None, None);
+        "bearer in a second header"
+    )]
+    #[test_case(
+        &[r#"Negotiate YIIFyQYGKwYBBQUCoIIFvTCCBbmg==, Basic realm="Registry", charset="UTF-8""#],
+        basic("Registry");
+        "unknown scheme with token68 skipped"
+    )]
+    #[test_case(
+        &[r#"bearer Realm="https://r.example.com/token",Service=r"#],
+        bearer("https://r.example.com/token", Some("r"), None);
+        "case insensitive with token value"
+    )]
+    #[test_case(
+        &[r#"Basic realm="quoted \"realm\", with comma""#],
+        basic(r#"quoted "realm", with comma"#);
+        "escaped quotes"
+    )]
+    fn www_authenticate_challenges_parse(headers: &[&str], expected: WwwAuthenticateHeaderContent) {
+        let headers = headers
+            .iter()
+            .map(|h| HeaderValue::from_str(h).unwrap())
+            .collect::<Vec<_>>();
+        let content =
+            WwwAuthenticateHeaderContent::from_www_authentication_headers(&headers).unwrap();
+        assert_eq!(content, expected);
+    }
+
+    #[test_case(r#"Negotiate abc"#; "only unsupported schemes")]
+    #[test_case(r#"realm="no scheme""#; "parameter without scheme")]
+    #[test_case(r#"Bearer realm="unterminated"#; "unterminated quote")]
+    #[test_case(r#"Bearer service="no realm""#; "bearer without realm")]
+    fn www_authenticate_challenges_reject(header: &str) {
+        let header = HeaderValue::from_str(header).unwrap();
+        assert!(WwwAuthenticateHeaderContent::from_www_authentication_headers(&[header]).is_err());
+    }
 }
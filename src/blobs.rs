@@ -1,322 +1,566 @@
+use crate::encoding::blob_headers;
 use crate::errors::{Error, Result};
-use crate::Client;
+use crate::{Client, ContentDigest, DigestReader, DigestWriter};
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, HeaderValue};
 use reqwest::{Method, StatusCode};
-use sha2::Digest;
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::os::unix::fs::MetadataExt;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 
+/// Number of concurrent requests made by `which_blobs_missing`.
+const MAX_CONCURRENT_CHECKS: usize = 8;
+
+/// Largest buffer allocated up front for a blob download.
+const MAX_PREALLOCATION: u64 = 256 * 1024 * 1024;
+
 impl Client {
     /// Check if a blob exists.
-    pub fn has_blob(&self, name: &str, digest: &str) -> Result<bool> {
-        let url = {
-            let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
-            reqwest::Url::parse(&ep)?
+    ///
+    /// Only `404` means that the blob is missing. Authorization failures
+    /// result in `Error::Unauthorized`, other statuses in
+    /// `Error::UnexpectedHttpStatus`, both wrapped in `Error::RequestFailed`.
+    pub fn has_blob(&self, name: &str, digest: impl AsRef<str>) -> Result<bool> {
+        Ok(self.head_blob(name, digest.as_ref())?.is_some())
+    }
+
+    /// Check which of the given blobs don't exist in a repository.
+    ///
+    /// The blobs are checked concurrently with the semantics of `has_blob`.
+    /// The missing digests are returned in the order given, the first
+    /// failed check fails the whole call.
+    pub fn which_blobs_missing(&self, name: &str, digests: &[&str]) -> Result<Vec<String>> {
+        if digests.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let chunk_size = digests.len().div_ceil(MAX_CONCURRENT_CHECKS);
+        let present = std::thread::scope(|scope| {
+            let workers = digests
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|digest| self.has_blob(name, digest))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("blob check panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut missing = Vec::new();
+        for (digest, present) in digests.iter().zip(present) {
+            if !present? {
+                missing.push(digest.to_string());
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Get the size of a blob without downloading it.
+    ///
+    /// Returns `None` if the blob doesn't exist or the registry doesn't report
+    /// its length. Errors are the same as for `has_blob`.
+    pub fn blob_size(&self, name: &str, digest: impl AsRef<str>) -> Result<Option<u64>> {
+        let headers = match self.head_blob(name, digest.as_ref())? {
+            Some(headers) => headers,
+            None => return Ok(None),
         };
+        let size =
+            match headers.get(reqwest::header::CONTENT_LENGTH) {
+                Some(len) => Some(len.to_str()?.parse().map_err(|_| {
+                    Error::MissingHeader(reqwest::header::CONTENT_LENGTH.to_string())
+                })?),
+                None => None,
+            };
+        Ok(size)
+    }
 
-        let res = self.build_reqwest(Method::HEAD, url).send()?;
+    /// Send a `HEAD` request for a blob, returning the headers if it exists.
+    fn head_blob(&self, name: &str, digest: &str) -> Result<Option<HeaderMap>> {
+        let res = self.send_mirrored(
+            Method::HEAD,
+            &format!("{}/blobs/{}", name, digest),
+            blob_headers(),
+        )?;
 
         trace!("Blob HEAD status: {:?}", res.status());
 
         match res.status() {
-            StatusCode::OK => Ok(true),
-            _ => Ok(false),
+            StatusCode::OK => Ok(Some(res.headers().clone())),
+            StatusCode::NOT_FOUND => Ok(None),
+            status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                Err(Error::Unauthorized(status).with_request(&Method::HEAD, res.url()))
+            }
+            status => {
+                Err(Error::from_status(status, res.headers())
+                    .with_request(&Method::HEAD, res.url()))
+            }
         }
     }
 
     /// Retrieve blob.
-    pub fn get_blob(&self, name: &str, digest: &str) -> Result<Vec<u8>> {
-        let digest = crate::ContentDigest::try_new(digest.to_string())?;
-
-        let blob = {
-            let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
-            let url = reqwest::Url::parse(&ep)?;
-
-            let res = self.build_reqwest(Method::GET, url).send()?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = digest.as_ref()))
+    )]
+    pub fn get_blob(&self, name: &str, digest: impl AsRef<str>) -> Result<Vec<u8>> {
+        self.get_blob_verified(name, digest).map(|(blob, _)| blob)
+    }
 
-            trace!("GET {} status: {}", res.url(), res.status());
-            let status = res.status();
+    /// Retrieve blob together with its verified digest.
+    ///
+    /// The digest is the one checked against the received content, so it
+    /// can be recorded without hashing the blob again.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = digest.as_ref()))
+    )]
+    pub fn get_blob_verified(
+        &self,
+        name: &str,
+        digest: impl AsRef<str>,
+    ) -> Result<(Vec<u8>, ContentDigest)> {
+        let digest = crate::ContentDigest::try_new(digest.as_ref().to_string())?;
+        if let Some(blob) = self.cached_blob(&digest) {
+            return Ok((blob, digest));
+        }
 
-            // Let client errors through to populate them with the body
-            if !(status.is_success() || status.is_client_error()) {
-                return Err(Error::UnexpectedHttpStatus(status));
-            }
+        let res = self.fetch_blob(name, &digest, blob_headers())?;
+        let mut reader = DigestReader::new(res, digest.clone());
+        let mut blob = Vec::new();
+        reader.read_to_end(&mut blob)?;
+        trace!("Successfully received blob with {} bytes ", blob.len());
 
-            let status = res.status();
-            let body_vec = res.bytes()?.to_vec();
-            let len = body_vec.len();
-
-            if status.is_success() {
-                trace!("Successfully received blob with {} bytes ", len);
-                Ok(body_vec)
-            } else if status.is_client_error() {
-                Err(Error::Client {
-                    status,
-                    len,
-                    body: body_vec,
-                })
-            } else {
-                // We only want to handle success and client errors here
-                error!(
-                    "Received unexpected HTTP status '{}' after fetching the body. Please submit a bug report.",
-                    status
-                );
-                Err(Error::UnexpectedHttpStatus(status))
-            }
-        }?;
+        reader.verify()?;
+        self.populate_cache(&digest, |path| std::fs::write(path, &blob));
+        Ok((blob, digest))
+    }
 
-        digest.try_verify(&blob)?;
-        Ok(blob.to_vec())
+    /// Retrieve blob as a stream, without holding it in memory.
+    ///
+    /// The content is verified against `digest` once the stream has been
+    /// read to its end, a mismatch fails that last read. The stream can be
+    /// passed to `render::unpack_stream`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = digest.as_ref()))
+    )]
+    pub fn get_blob_stream(&self, name: &str, digest: impl AsRef<str>) -> Result<BlobStream> {
+        let digest = crate::ContentDigest::try_new(digest.as_ref().to_string())?;
+        let reader = self.open_blob(name, &digest)?;
+        Ok(BlobStream {
+            reader: Some(DigestReader::new(reader, digest)),
+        })
     }
 
     /// Retrieve blob with progress
+    ///
+    /// The number of bytes received is sent to `sender` as they arrive. If
+    /// the receiver is dropped, the download continues without reporting.
+    /// The buffer is allocated up front for `size`, like the size of the
+    /// blob's descriptor, falling back to the response's `Content-Length`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = digest.as_ref()))
+    )]
     pub fn get_blob_with_progress(
         &self,
         name: &str,
-        digest: &str,
+        digest: impl AsRef<str>,
+        size: Option<u64>,
         sender: Option<Sender<u64>>,
     ) -> Result<Vec<u8>> {
-        let digest = crate::ContentDigest::try_new(digest.to_string())?;
-        let mut hash = digest.start_hash();
-        let blob = {
-            let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
-            let url = reqwest::Url::parse(&ep)?;
-
-            let mut res = self.build_reqwest(Method::GET, url).send()?;
-
-            trace!("GET {} status: {}", res.url(), res.status());
-            let status = res.status();
-            // Let client errors through to populate them with the body
-            if !(status.is_success() || status.is_client_error()) {
-                if let Some(send) = sender {
-                    drop(send);
-                };
-                return Err(Error::UnexpectedHttpStatus(status));
-            }
+        let digest = crate::ContentDigest::try_new(digest.as_ref().to_string())?;
+        let mut progress = Progress(sender);
+        if let Some(blob) = self.cached_blob(&digest) {
+            progress.report(blob.len() as u64);
+            return Ok(blob);
+        }
 
-            let status = res.status();
-
-            let mut buffer: [u8; 1024] = [0; 1024];
-            let mut body_vec: Vec<u8> = Vec::new();
-
-            loop {
-                match res.read(&mut buffer) {
-                    Ok(size) => {
-                        if size > 0 {
-                            if let Some(send) = &sender {
-                                if let Err(e) = send.send(size as u64) {
-                                    return Err(e.into());
-                                };
-                            };
-                            Digest::update(&mut hash, &buffer[0..size]);
-                            body_vec.append(&mut buffer[0..size].to_vec());
-                        } else {
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        error!("Download error: {:?}", e);
-                        break;
-                    }
+        let res = self.fetch_blob(name, &digest, blob_headers())?;
+        let capacity = size.or_else(|| res.content_length()).unwrap_or(0);
+        let url = res.url().clone();
+        let mut reader = DigestReader::new(res, digest.clone());
+        // Don't trust the size with more memory than a reasonable blob needs
+        let mut blob = Vec::with_capacity(capacity.min(MAX_PREALLOCATION) as usize);
+        let mut buffer: [u8; 1024] = [0; 1024];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(size) => {
+                    progress.report(size as u64);
+                    blob.extend_from_slice(&buffer[0..size]);
+                }
+                Err(e) => {
+                    let source = read_error(e, &url);
+                    debug!("Download of {} failed: {}", digest, source);
+                    return Err(source.with_request(&Method::GET, &url));
                 }
             }
-            let len = body_vec.len();
-
-            if let Some(send) = sender {
-                drop(send);
-            };
-            if status.is_success() {
-                trace!("Successfully received blob with {} bytes ", len);
-                Ok(body_vec)
-            } else if status.is_client_error() {
-                Err(Error::Client {
-                    status,
-                    len,
-                    body: body_vec,
-                })
-            } else {
-                // We only want to handle success and client errors here
-                error!(
-                    "Received unexpected HTTP status '{}' after fetching the body. Please submit a bug report.",
-                    status
-                );
-                Err(Error::UnexpectedHttpStatus(status))
-            }
-        }?;
+        }
+        drop(progress);
+        trace!("Successfully received blob with {} bytes ", blob.len());
 
-        digest.try_verify_hash(&hash)?;
-        Ok(blob.to_vec())
+        reader.verify()?;
+        self.populate_cache(&digest, |path| std::fs::write(path, &blob));
+        Ok(blob)
     }
 
-    /// Retrieve blob with progress
+    /// Retrieve blob with progress into a file named after its digest.
+    ///
+    /// The file is named `<algorithm>-<hex>` after the canonical (lowercase,
+    /// trimmed) form of `digest_hash`, so differently spelled digests share
+    /// one download and the name is valid on Windows.
+    ///
+    /// Progress is reported like in `get_blob_with_progress`. A download
+    /// failing midway returns `Error::DownloadInterrupted` with the partial
+    /// file, which is resumed by calling this again with the blob's `size`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = digest_hash.as_ref()))
+    )]
     pub fn get_blob_with_progress_file(
         &self,
         name: &str,
-        digest_hash: &str,
+        digest_hash: impl AsRef<str>,
         size: Option<u64>,
         sender: Option<Sender<u64>>,
         target_dir: &Path,
     ) -> Result<PathBuf> {
-        let digest = crate::ContentDigest::try_new(digest_hash.to_string())?;
-        let mut target = target_dir.to_path_buf();
-        std::fs::create_dir_all(&target).unwrap();
-        target.push(digest_hash);
+        let digest = crate::ContentDigest::try_new(digest_hash.as_ref().to_string())?;
+        let mut progress = Progress(sender);
+        std::fs::create_dir_all(target_dir).map_err(|source| Error::FileIo {
+            path: target_dir.to_path_buf(),
+            source,
+        })?;
+        let target = target_dir.join(digest.file_name());
         trace!("Going to downloaad to: {:?}", target);
+        let file_error = |source| Error::FileIo {
+            path: target.clone(),
+            source,
+        };
 
-        let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
-        let url = reqwest::Url::parse(&ep)?;
-        let mut hash = digest.start_hash();
+        if let Some(cached) = self.cached_blob_path(&digest) {
+            let len = std::fs::copy(&cached, &target).map_err(file_error)?;
+            progress.report(len);
+            return Ok(target);
+        }
 
-        let client =
+        let range =
         // Continue previous download
         if target.exists() {
             if let Some(s) = size {
-                let metadata =
-                    std::fs::metadata(&target.as_path()).expect("unable to read metadata");
-                if metadata.size() == s {
-                    let mut hasher = sha2::Sha256::new();
-                    if let Ok(mut f) = File::open(&target) {
-                        std::io::copy(&mut f, &mut hasher).unwrap_or_default();
-                        match digest.try_verify_hash(&hasher) {
-                            Ok(_) => {
-                                debug!("Already downloaded {}", digest_hash);
-                                if let Some(send) = &sender {
-                                    if let Err(e) = send.send(s as u64) {
-                                        return Err(e.into());
-                                    };
-                                };
-                                return Ok(target);
-                            }
-                            Err(_) => {
-                                std::fs::remove_file(&target).unwrap_or_default();
-                            }
+                let metadata = std::fs::metadata(&target).map_err(file_error)?;
+                if metadata.len() == s {
+                    match digest.verify_file(&target) {
+                        Ok(_) => {
+                            debug!("Already downloaded {}", digest);
+                            progress.report(s);
+                            return Ok(target);
+                        }
+                        Err(Error::IO(source)) => return Err(Error::FileIo { path: target, source }),
+                        Err(e) => {
+                            debug!("Discarding previous download of {}: {}", digest, e);
+                            std::fs::remove_file(&target).unwrap_or_default();
                         }
                     }
-                    self.build_reqwest(Method::GET, url)
+                    None
                 } else {
-                    debug!("Trying to resume {}", digest_hash);
-                    if let Ok(mut f) = File::open(&target) {
-                        std::io::copy(&mut f, &mut hash).unwrap_or_default();
-                    }
-                    self.build_reqwest(Method::GET, url).header(
-                        reqwest::header::RANGE,
-                        format! {"bytes={}-{}", metadata.size(), s},
-                    )
+                    debug!("Trying to resume {}", digest);
+                    Some(format!("bytes={}-{}", metadata.len(), s))
                 }
             } else {
-                self.build_reqwest(Method::GET, url)
+                None
             }
         } else {
-            self.build_reqwest(Method::GET, url)
+            None
         };
 
-        let mut res = match client.send() {
-            Ok(res) => res,
-            Err(e) => {
-                warn!("Unable to create request: {:?}", e);
-                return Err(Error::DownloadFailed);
-            }
-        };
-
-        trace!("GET {} status: {}", res.url(), res.status());
-        let status = res.status();
-        // Let client errors through to populate them with the body
-        if !(status.is_success() || status.is_client_error()) {
-            if let Some(send) = sender {
-                drop(send);
-            };
-            return Err(Error::UnexpectedHttpStatus(status));
+        let mut headers = blob_headers();
+        if let Some(range) = &range {
+            let range = HeaderValue::from_str(range).expect("range is always a valid header value");
+            headers.insert(reqwest::header::RANGE, range);
         }
+        let mut res = self.fetch_blob(name, &digest, headers)?;
 
-        let status = res.status();
-
-        let mut file = match res.headers().get("Accept-Ranges") {
-            None => OpenOptions::new()
+        // Registries ignoring the range send the whole blob
+        let resuming = range.is_some() && res.status() == StatusCode::PARTIAL_CONTENT;
+        let mut downloaded = 0;
+        let mut writer = if resuming {
+            let existing = File::open(&target).map_err(file_error)?;
+            downloaded = existing.metadata().map_err(file_error)?.len();
+            progress.report(downloaded);
+            let file = OpenOptions::new()
+                .append(true)
+                .truncate(false)
+                .create(true)
+                .open(&target)
+                .map_err(file_error)?;
+            DigestWriter::resume(file, digest.clone(), existing).map_err(file_error)?
+        } else {
+            let file = OpenOptions::new()
                 .write(true)
                 .truncate(true)
                 .create(true)
                 .open(&target)
-                .unwrap(),
-            Some(v) => {
-                if v.eq("none") {
-                    OpenOptions::new()
-                        .write(true)
-                        .truncate(true)
-                        .create(true)
-                        .open(&target)
-                        .unwrap()
-                } else {
-                    match std::fs::metadata(&target.as_path()) {
-                        Ok(metadata) => {
-                            if let Some(send) = &sender {
-                                if let Err(e) = send.send(metadata.size()) {
-                                    return Err(e.into());
-                                };
-                            };
-                            OpenOptions::new()
-                                .append(true)
-                                .truncate(false)
-                                .create(true)
-                                .open(&target)
-                                .unwrap()
-                        }
-                        Err(_) => OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .create(true)
-                            .open(&target)
-                            .unwrap(),
-                    }
-                }
-            }
+                .map_err(file_error)?;
+            DigestWriter::new(file, digest.clone())
         };
-        let mut len: usize = 0;
+
         let mut buffer: [u8; 1024] = [0; 1024];
         loop {
             match res.read(&mut buffer) {
+                Ok(0) => break,
                 Ok(size) => {
-                    if size > 0 {
-                        if let Some(send) = &sender {
-                            if let Err(e) = send.send(size as u64) {
-                                return Err(e.into());
-                            };
-                        };
-                        len += size;
-                        Digest::update(&mut hash, &buffer[0..size]);
-                        file.write_all(&buffer[0..size])?;
-                    } else {
-                        break;
-                    }
+                    progress.report(size as u64);
+                    downloaded += size as u64;
+                    writer.write_all(&buffer[0..size]).map_err(file_error)?;
                 }
                 Err(e) => {
-                    error!("Download error: {:?}", e);
-                    break;
+                    let source = read_error(e, res.url());
+                    debug!("Download of {} interrupted: {}", digest, source);
+                    return Err(Error::DownloadInterrupted {
+                        partial: target,
+                        downloaded,
+                        source: Box::new(source.with_request(&Method::GET, res.url())),
+                    });
                 }
             }
         }
 
-        if let Some(send) = sender {
-            drop(send);
+        drop(progress);
+        trace!("Successfully received blob with {} bytes ", downloaded);
+        writer.verify()?;
+        self.populate_cache(&digest, |path| std::fs::copy(&target, path).map(|_| ()));
+        Ok(target)
+    }
+
+    /// Open a blob for reading as it is received, without verifying it.
+    ///
+    /// Cached blobs are read from the cache.
+    pub(crate) fn open_blob(
+        &self,
+        name: &str,
+        digest: &ContentDigest,
+    ) -> Result<Box<dyn Read + Send>> {
+        if let Some(path) = self.cached_blob_path(digest) {
+            return Ok(Box::new(File::open(path)?));
+        }
+        Ok(Box::new(self.fetch_blob(name, digest, blob_headers())?))
+    }
+
+    /// Send a `GET` request for a blob, failing unless the registry serves it.
+    fn fetch_blob(
+        &self,
+        name: &str,
+        digest: &ContentDigest,
+        headers: HeaderMap,
+    ) -> Result<Response> {
+        let res =
+            self.send_mirrored(Method::GET, &format!("{}/blobs/{}", name, digest), headers)?;
+
+        trace!("GET {} status: {}", res.url(), res.status());
+        let status = res.status();
+        if status == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound {
+                name: name.to_string(),
+                reference: digest.to_string(),
+            });
+        }
+        if !status.is_success() {
+            return Err(Error::from_response(&Method::GET, res));
+        }
+        Ok(res)
+    }
+}
+
+/// Reports download progress until the receiving end hangs up.
+struct Progress(Option<Sender<u64>>);
+
+impl Progress {
+    fn report(&mut self, bytes: u64) {
+        if let Some(sender) = &self.0 {
+            if sender.send(bytes).is_err() {
+                debug!("progress receiver dropped, no longer reporting");
+                self.0 = None;
+            }
+        }
+    }
+}
+
+/// Blob being downloaded, returned by `Client::get_blob_stream`.
+///
+/// Reading fails with `io::ErrorKind::InvalidData` when the content doesn't
+/// match the digest of the blob, once all of it has been read.
+pub struct BlobStream {
+    reader: Option<DigestReader<Box<dyn Read + Send>>>,
+}
+
+impl std::fmt::Debug for BlobStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlobStream")
+            .field("finished", &self.reader.is_none())
+            .finish()
+    }
+}
+
+impl Read for BlobStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return Ok(0),
         };
-        if status.is_success() {
-            trace!("Successfully received blob with {} bytes ", len);
-            digest.try_verify_hash(&hash)?;
-            Ok(target.clone())
-        } else if status.is_client_error() {
-            Err(Error::Client {
-                status,
-                len,
-                body: vec![],
-            })
-        } else {
-            // We only want to handle success and client errors here
-            error!(
-                    "Received unexpected HTTP status '{}' after fetching the body. Please submit a bug report.",
-                    status
-                );
-            Err(Error::UnexpectedHttpStatus(status))
+        let n = reader.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let reader = self.reader.take().expect("reader is set");
+            reader
+                .verify()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        Ok(n)
+    }
+}
+
+/// Reader reporting the number of bytes read through it.
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub(crate) fn new(inner: R, sender: Option<Sender<u64>>) -> Self {
+        ProgressReader {
+            inner,
+            progress: Progress(sender),
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.progress.report(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+/// Whether reading a response body failed because of a timeout.
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::TimedOut
+        || e.get_ref()
+            .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+            .is_some_and(reqwest::Error::is_timeout)
+}
+
+/// Classify an error reading the response body from `url`.
+///
+/// reqwest reports failures like truncated bodies as I/O errors wrapping its
+/// own, which are unwrapped so they are classified like request errors.
+fn read_error(e: io::Error, url: &reqwest::Url) -> Error {
+    if is_timeout(&e) {
+        return Error::timeout(url);
+    }
+    if !e
+        .get_ref()
+        .is_some_and(|inner| inner.is::<reqwest::Error>())
+    {
+        return Error::IO(e);
+    }
+    let kind = e.kind();
+    match e
+        .into_inner()
+        .map(|inner| inner.downcast::<reqwest::Error>())
+    {
+        Some(Ok(inner)) => (*inner).into(),
+        Some(Err(inner)) => Error::IO(io::Error::new(kind, inner)),
+        None => Error::IO(kind.into()),
+    }
+}
+
+impl Client {
+    /// Return the path of a cached blob, if caching is enabled and the
+    /// cached content matches its digest.
+    ///
+    /// Entries failing verification are removed.
+    fn cached_blob_path(&self, digest: &ContentDigest) -> Option<PathBuf> {
+        let path = self.cache_dir.as_ref()?.join(digest.relative_path());
+        let verified = digest.verify_file(&path).map(|_| path.clone());
+        cache_hit(&path, digest, verified)
+    }
+
+    /// Read a cached blob, if caching is enabled and the cached content
+    /// matches its digest.
+    ///
+    /// The content is hashed while it is read, so the file is only read
+    /// once. Entries failing verification are removed.
+    fn cached_blob(&self, digest: &ContentDigest) -> Option<Vec<u8>> {
+        let path = self.cache_dir.as_ref()?.join(digest.relative_path());
+        let read = File::open(&path).and_then(|file| {
+            let mut reader = DigestReader::new(file, digest.clone());
+            let mut blob = Vec::new();
+            reader.read_to_end(&mut blob)?;
+            Ok((reader, blob))
+        });
+        let verified = match read {
+            Ok((reader, blob)) => reader.verify().map(|_| blob).map_err(Error::from),
+            Err(e) => Err(Error::IO(e)),
+        };
+        cache_hit(&path, digest, verified)
+    }
+
+    /// Store a verified blob in the cache, if caching is enabled.
+    ///
+    /// The content is written to a temporary file by `write` and then
+    /// moved into place. Failures are logged and otherwise ignored.
+    fn populate_cache<F>(&self, digest: &ContentDigest, write: F)
+    where
+        F: FnOnce(&Path) -> io::Result<()>,
+    {
+        let path = match &self.cache_dir {
+            Some(dir) => dir.join(digest.relative_path()),
+            None => return,
+        };
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let res = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| write(&tmp))
+            .and_then(|_| std::fs::rename(&tmp, &path));
+        if let Err(e) = res {
+            warn!("Unable to cache blob {}: {}", digest, e);
+            std::fs::remove_file(&tmp).unwrap_or_default();
+        }
+    }
+}
+
+/// Turn the verification result of the cache entry at `path` into a hit.
+///
+/// Entries failing verification are removed, read errors are logged.
+fn cache_hit<T>(path: &Path, digest: &ContentDigest, verified: Result<T>) -> Option<T> {
+    match verified {
+        Ok(hit) => {
+            debug!("Serving {} from cache", digest);
+            Some(hit)
+        }
+        Err(Error::IO(e)) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Unable to read cached blob {:?}: {}", path, e);
+            }
+            None
+        }
+        Err(e) => {
+            warn!("Discarding cached blob {:?}: {}", path, e);
+            std::fs::remove_file(path).unwrap_or_default();
+            None
         }
     }
 }
@@ -0,0 +1,123 @@
+//! Repository listing through the catalog endpoint.
+
+use crate::errors::{Error, Result};
+use crate::tags::Pagination;
+use crate::Client;
+use reqwest::{self, header, StatusCode, Url};
+
+/// A chunk of repository names from the catalog.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CatalogChunk {
+    /// Subset of repositories.
+    repositories: Vec<String>,
+}
+
+impl Client {
+    /// List the repositories available on the registry.
+    ///
+    /// Registries which don't implement the catalog endpoint, like ghcr.io,
+    /// result in `Error::UnsupportedOperation`.
+    pub fn get_catalog(&self, paginate: Option<u32>) -> Result<Vec<String>> {
+        self.catalog_iter(paginate).collect()
+    }
+
+    /// Lazily iterate over the repositories available on the registry.
+    ///
+    /// Pages are fetched on demand like for `tags_iter`. If the registry
+    /// rejects the current credentials, the client authenticates once more
+    /// with the `registry:catalog:*` scope and retries the request.
+    pub fn catalog_iter<'a>(
+        &'a self,
+        paginate: Option<u32>,
+    ) -> impl Iterator<Item = Result<String>> + 'a {
+        let base_url = format!("{}/v2/_catalog", self.base_url);
+        let next = Url::parse(&base_url).map(|mut url| {
+            if let Some(n) = paginate {
+                url.query_pairs_mut().append_pair("n", &n.to_string());
+            }
+            url
+        });
+
+        CatalogIter {
+            client: self,
+            reauthenticated: None,
+            paginate,
+            next: Some(next.map_err(Into::into)),
+            buffer: Vec::new().into_iter(),
+        }
+    }
+
+    /// Fetch a single page of the catalog and the URL of the following page, if any.
+    fn fetch_catalog_chunk(
+        &self,
+        url: Url,
+        paginate: Option<u32>,
+    ) -> Result<(CatalogChunk, Option<Url>)> {
+        let resp = self.send_reqwest(
+            self.build_reqwest(reqwest::Method::GET, url.clone())
+                .header(header::ACCEPT, "application/json"),
+        )?;
+
+        let status = resp.status();
+        trace!("GET '{}' status: {:?}", resp.url(), status);
+        if status == StatusCode::NOT_FOUND {
+            return Err(Error::UnsupportedOperation("catalog listing".to_string()));
+        } else if !status.is_success() {
+            return Err(Error::from_response(&reqwest::Method::GET, resp));
+        }
+
+        let pagination = Pagination::new(url, &resp, paginate);
+        let chunk = resp.json::<CatalogChunk>()?;
+        let next = pagination.next(&chunk.repositories)?;
+        Ok((chunk, next))
+    }
+}
+
+/// Iterator fetching pages of the catalog on demand.
+struct CatalogIter<'a> {
+    client: &'a Client,
+    /// Client authenticated with the catalog scope, after a rejected request.
+    reauthenticated: Option<Client>,
+    paginate: Option<u32>,
+    next: Option<Result<Url>>,
+    buffer: std::vec::IntoIter<String>,
+}
+
+impl<'a> Iterator for CatalogIter<'a> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(repository) = self.buffer.next() {
+                return Some(Ok(repository));
+            }
+
+            let url = match self.next.take()? {
+                Ok(url) => url,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let client = self.reauthenticated.as_ref().unwrap_or(self.client);
+            match client.fetch_catalog_chunk(url.clone(), self.paginate) {
+                Ok((chunk, next)) => {
+                    self.buffer = chunk.repositories.into_iter();
+                    self.next = next.map(Ok);
+                }
+                Err(e)
+                    if e.status() == Some(StatusCode::UNAUTHORIZED)
+                        && self.reauthenticated.is_none() =>
+                {
+                    debug!("catalog request unauthorized, authenticating with catalog scope");
+                    match self.client.clone().authenticate(&[crate::Scope::catalog()]) {
+                        Ok(client) => {
+                            self.reauthenticated = Some(client);
+                            self.next = Some(Ok(url));
+                        }
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
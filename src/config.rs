@@ -2,6 +2,16 @@
 
 use crate::errors::Result;
 use crate::Client;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Credentials read by `Config::credentials_from_env`.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct EnvCredentials {
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+}
 
 /// Configuration for a `Client`.
 #[derive(Debug)]
@@ -11,21 +21,71 @@ pub struct Config {
     user_agent: Option<String>,
     username: Option<String>,
     password: Option<String>,
+    bearer_token: Option<String>,
+    identity_token: Option<String>,
+    /// Prefix of the variables read by `credentials_from_env`, when building.
+    env_prefix: Option<String>,
     accept_invalid_certs: bool,
+    strict_content_types: bool,
+    cache_dir: Option<PathBuf>,
+    http_client: Option<reqwest::blocking::Client>,
+    observer: Option<Arc<dyn crate::Observer>>,
+    mirrors: Vec<String>,
+    accept_encoding: bool,
+    max_redirects: usize,
 }
 
-impl Config {
+impl Default for Config {
     /// Initialize `Config` with default values.
-    pub fn default() -> Self {
+    fn default() -> Self {
         Self {
             index: "registry-1.docker.io".into(),
             insecure_registry: false,
             accept_invalid_certs: false,
+            strict_content_types: false,
+            cache_dir: None,
+            http_client: None,
+            observer: None,
+            mirrors: Vec::new(),
+            accept_encoding: false,
+            max_redirects: 10,
             user_agent: Some(crate::USER_AGENT.to_owned()),
             username: None,
             password: None,
+            bearer_token: None,
+            identity_token: None,
+            env_prefix: None,
         }
     }
+}
+
+/// Read the credentials of `Config::credentials_from_env` with `var`.
+fn env_credentials(
+    prefix: &str,
+    index: &str,
+    var: impl Fn(&str) -> Option<String>,
+) -> EnvCredentials {
+    let credentials = EnvCredentials {
+        username: var(&format!("{}USERNAME", prefix)),
+        password: var(&format!("{}PASSWORD", prefix)),
+        token: var(&format!("{}TOKEN", prefix)),
+    };
+    if crate::normalize_registry(index) != "ghcr.io" || credentials != EnvCredentials::default() {
+        return credentials;
+    }
+    match var("GITHUB_TOKEN").or_else(|| var("CR_PAT")) {
+        Some(token) => EnvCredentials {
+            username: var("GITHUB_ACTOR"),
+            password: Some(token),
+            token: None,
+        },
+        None => credentials,
+    }
+}
+
+impl Config {
+    /// Prefix of the variables read by `credentials_from_env`.
+    pub const DEFAULT_ENV_PREFIX: &'static str = "GHREGISTRY_";
 
     /// Set registry service to use (vhost or IP).
     pub fn registry(mut self, reg: &str) -> Self {
@@ -45,9 +105,81 @@ impl Config {
         self
     }
 
-    /// Set the user-agent to be used for registry authentication.
-    pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
-        self.user_agent = user_agent;
+    /// Use a pre-built HTTP client instead of building one.
+    ///
+    /// This allows sharing a connection pool and controlling transport
+    /// details. Settings applied to the internal builder, like
+    /// `accept_invalid_certs` and `max_redirects`, are ignored in that case.
+    pub fn with_http_client(mut self, client: reqwest::blocking::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Set whether responses with an unexpected content type are an error.
+    ///
+    /// By default a mismatch is only logged, as some registries don't set
+    /// the content type correctly.
+    pub fn strict_content_types(mut self, strict: bool) -> Self {
+        self.strict_content_types = strict;
+        self
+    }
+
+    /// Set a directory used as a content-addressable blob cache.
+    ///
+    /// Blobs are stored as `<cache_dir>/<algorithm>/<hex>`. They are
+    /// verified against their digest before being served from the cache,
+    /// and fetched from the registry again if verification fails.
+    pub fn cache_dir(mut self, dir: PathBuf) -> Self {
+        self.cache_dir = Some(dir);
+        self
+    }
+
+    /// Set an observer notified about every request, e.g. to record metrics.
+    pub fn observer(mut self, observer: Arc<dyn crate::Observer>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Set pull-through mirrors of the registry, like `mirror.local:5000`.
+    ///
+    /// Blob and manifest requests try the mirrors in order and only reach
+    /// the registry if no mirror has the content. Mirrors without a scheme
+    /// use HTTPS. Blobs are verified against their digest whichever host
+    /// serves them, while manifests fetched by tag are trusted as is.
+    pub fn mirrors(mut self, mirrors: Vec<String>) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    /// Set whether manifests and tag lists may be sent gzip compressed.
+    ///
+    /// Compressed responses are decoded transparently. Blobs are always
+    /// requested with `Accept-Encoding: identity` regardless, so that their
+    /// bytes match the digest even behind re-encoding proxies.
+    pub fn accept_encoding(mut self, accept_encoding: bool) -> Self {
+        self.accept_encoding = accept_encoding;
+        self
+    }
+
+    /// Set how many redirects are followed per request, 10 by default.
+    ///
+    /// Registries like ghcr.io redirect blob downloads to object storage.
+    /// The `Authorization` header is not sent along when a redirect leads to
+    /// another host, so tokens don't leak to it. Requests redirected more
+    /// often fail with `Error::Redirect`. With `0`, redirects aren't
+    /// followed and their response is handled like any other status.
+    ///
+    /// This is ignored with `with_http_client`, whose redirect policy applies.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Set the user-agent sent with every request, `USER_AGENT` by default.
+    ///
+    /// Use `Client::with_user_agent` to change it for some calls only.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
         self
     }
 
@@ -63,17 +195,76 @@ impl Config {
         self
     }
 
+    /// Set a pre-acquired bearer token, like `GITHUB_TOKEN` for ghcr.io.
+    ///
+    /// The token is sent with every request and the authentication
+    /// challenge flow is skipped, so `Client::authenticate` doesn't change
+    /// the client. The token takes precedence over username and password.
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    /// Set an identity token, exchanged for access tokens with the OAuth2 flow.
+    ///
+    /// This is what `docker login` stores as `identitytoken` for Docker Hub
+    /// accounts with 2FA. It takes precedence over username and password.
+    pub fn identity_token(mut self, token: Option<String>) -> Self {
+        self.identity_token = token;
+        self
+    }
+
     /// Read credentials from a JSON config file
     pub fn read_credentials<T: ::std::io::Read>(mut self, reader: T) -> Self {
-        if let Ok(creds) = crate::get_credentials(reader, &self.index) {
-            self.username = creds.0;
-            self.password = creds.1;
+        if let Ok(creds) = crate::get_docker_credentials(reader, &self.index) {
+            self.username = creds.username;
+            self.password = creds.password;
+            self.identity_token = creds.identity_token;
         };
         self
     }
 
+    /// Read credentials from the `GHREGISTRY_USERNAME`, `GHREGISTRY_PASSWORD`
+    /// and `GHREGISTRY_TOKEN` environment variables.
+    ///
+    /// A token is used as with `bearer_token`. Credentials set explicitly
+    /// with the other methods take precedence, and unset variables mean
+    /// anonymous access.
+    ///
+    /// For ghcr.io, when none of these is set, `GITHUB_TOKEN` or else `CR_PAT`
+    /// is used as password for the `GITHUB_ACTOR` user, like in GitHub
+    /// Actions workflows logging in with `docker login`. The username is
+    /// left empty without `GITHUB_ACTOR`. Variables are read by `build`, so
+    /// the registry may be set after calling this.
+    pub fn credentials_from_env(self) -> Self {
+        self.credentials_from_env_prefixed(Self::DEFAULT_ENV_PREFIX)
+    }
+
+    /// Read credentials like `credentials_from_env`, from the `<prefix>USERNAME`,
+    /// `<prefix>PASSWORD` and `<prefix>TOKEN` environment variables.
+    pub fn credentials_from_env_prefixed(mut self, prefix: &str) -> Self {
+        self.env_prefix = Some(prefix.to_owned());
+        self
+    }
+
     /// Return a `Client` to interact with a v2 registry.
-    pub fn build(self) -> Result<Client> {
+    pub fn build(mut self) -> Result<Client> {
+        let explicit = self.username.is_some()
+            || self.password.is_some()
+            || self.bearer_token.is_some()
+            || self.identity_token.is_some();
+        if let Some(prefix) = self.env_prefix.take().filter(|_| !explicit) {
+            let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+            let env = env_credentials(&prefix, &self.index, var);
+            match env.token {
+                Some(token) => self.bearer_token = Some(token),
+                None => {
+                    self.username = env.username;
+                    self.password = env.password;
+                }
+            }
+        }
+
         let base = if self.insecure_registry {
             "http://".to_string() + &self.index
         } else {
@@ -85,25 +276,118 @@ impl Config {
             base,
             self.username
         );
-        let creds = match (self.username, self.password) {
-            (None, None) => None,
-            (u, p) => Some((
+        let creds = match (self.identity_token, self.username, self.password) {
+            (Some(token), _, _) => Some((crate::auth::IDENTITY_TOKEN_USER.to_string(), token)),
+            (None, None, None) => None,
+            (None, u, p) => Some((
                 u.unwrap_or_else(|| "".into()),
                 p.unwrap_or_else(|| "".into()),
             )),
         };
-        let client = reqwest::blocking::ClientBuilder::new()
-            .danger_accept_invalid_certs(self.accept_invalid_certs)
-            .build()?;
+        let client = match self.http_client {
+            Some(client) => client,
+            None => reqwest::blocking::ClientBuilder::new()
+                .danger_accept_invalid_certs(self.accept_invalid_certs)
+                .redirect(match self.max_redirects {
+                    0 => reqwest::redirect::Policy::none(),
+                    // The limit of reqwest counts the initial request as well
+                    max => reqwest::redirect::Policy::limited(max.saturating_add(1)),
+                })
+                .build()?,
+        };
 
         let c = Client {
             base_url: base,
             credentials: creds,
             index: self.index,
             user_agent: self.user_agent,
-            auth: None,
+            static_token: self.bearer_token.is_some(),
+            auth: std::sync::Arc::new(std::sync::RwLock::new(self.bearer_token.map(|token| {
+                crate::auth::Auth::Bearer(crate::auth::BearerAuth::from_token(token))
+            }))),
+            auth_refresh: Default::default(),
             client,
+            strict_content_types: self.strict_content_types,
+            cache_dir: self.cache_dir,
+            observer: self.observer,
+            accept_encoding: self.accept_encoding,
+            mirrors: self
+                .mirrors
+                .iter()
+                .map(|mirror| crate::mirrors::mirror_base_url(mirror))
+                .collect(),
         };
         Ok(c)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn credentials(index: &str, vars: &[(&str, &str)]) -> EnvCredentials {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        env_credentials("GHREGISTRY_", index, |name| {
+            vars.get(name).map(|value| value.to_string())
+        })
+    }
+
+    fn user(username: Option<&str>, password: &str) -> EnvCredentials {
+        EnvCredentials {
+            username: username.map(String::from),
+            password: Some(password.into()),
+            token: None,
+        }
+    }
+
+    #[test]
+    fn default_env_prefix() {
+        let config = Config::default().credentials_from_env();
+        assert_eq!(config.env_prefix.as_deref(), Some("GHREGISTRY_"));
+        let config = Config::default().credentials_from_env_prefixed("CI_");
+        assert_eq!(config.env_prefix.as_deref(), Some("CI_"));
+    }
+
+    #[test]
+    fn github_token_for_ghcr() {
+        let actions = [("GITHUB_ACTOR", "octocat"), ("GITHUB_TOKEN", "ghs_token")];
+        assert_eq!(
+            credentials("ghcr.io", &actions),
+            user(Some("octocat"), "ghs_token")
+        );
+        assert_eq!(
+            credentials("ghcr.io", &[("CR_PAT", "ghp_pat")]),
+            user(None, "ghp_pat")
+        );
+        for index in ["GHCR.IO", "ghcr.io:443"] {
+            assert_eq!(
+                credentials(index, &actions),
+                user(Some("octocat"), "ghs_token"),
+                "{}",
+                index
+            );
+        }
+        assert_eq!(
+            credentials("registry-1.docker.io", &actions),
+            EnvCredentials::default()
+        );
+    }
+
+    #[test]
+    fn prefixed_variables_before_github_token() {
+        let vars = [("GITHUB_TOKEN", "ghs_token"), ("GHREGISTRY_TOKEN", "own")];
+        assert_eq!(
+            credentials("ghcr.io", &vars),
+            EnvCredentials {
+                token: Some("own".into()),
+                ..Default::default()
+            }
+        );
+        // The actor alone is no credential
+        assert_eq!(
+            credentials("ghcr.io", &[("GITHUB_ACTOR", "octocat")]),
+            EnvCredentials::default()
+        );
+    }
+}
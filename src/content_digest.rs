@@ -1,26 +1,41 @@
 /// Implements types and methods for content verification
-use sha2::{self, Digest, Sha256};
+use sha2::{self, Digest, Sha256, Sha512};
 
-/// ContentDigest stores a digest and its DigestAlgorithm
-#[derive(Clone, Debug, PartialEq)]
+/// ContentDigest stores a validated digest like `sha256:<hex>`
+///
+/// It is parsed with `FromStr` or `TryFrom`, and formats back to its
+/// canonical lowercase form with `Display` or `AsRef<str>`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ContentDigest {
     digest: String,
     algorithm: DigestAlgorithm,
 }
 
 /// DigestAlgorithm declares the supported algorithms
-#[derive(Display, Clone, Debug, PartialEq, EnumString)]
+#[derive(Display, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, EnumString)]
 pub enum DigestAlgorithm {
     #[strum(to_string = "sha256")]
     Sha256,
+    #[strum(to_string = "sha512")]
+    Sha512,
+}
+
+/// Incremental hasher for one of the supported algorithms.
+///
+/// Created by `ContentDigest::start_hash`, fed with `update` or through
+/// `std::io::Write`, and checked with `ContentDigest::try_verify_hash`.
+#[derive(Clone)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ContentDigestError {
     #[error("digest {0} does not have algorithm prefix")]
     BadDigest(String),
-    #[error("unknown algorithm")]
-    AlgorithmUnknown(#[from] <DigestAlgorithm as std::str::FromStr>::Err),
+    #[error("unsupported digest algorithm {0:?}")]
+    UnsupportedAlgorithm(String),
     #[error("verification failed: expected '{expected}', got '{got}'")]
     Verify {
         expected: ContentDigest,
@@ -31,25 +46,82 @@ pub enum ContentDigestError {
 impl ContentDigest {
     /// try_new attempts to parse the digest string and create a ContentDigest instance from it
     ///
+    /// Surrounding whitespace is trimmed and the digest is lowercased, so
+    /// `SHA256:ABC...` and `sha256:abc...` are the same digest.
+    ///
     /// Success depends on
     /// - the string having a "algorithm:" prefix
     /// - the algorithm being supported by DigestAlgorithm
+    /// - the encoded part being hex of the algorithm's length
     pub fn try_new(digest: String) -> std::result::Result<Self, ContentDigestError> {
-        let digest_split = digest.split(':').collect::<Vec<&str>>();
+        let canonical = digest.trim().to_ascii_lowercase();
+        let (algorithm, encoded) = match canonical.split_once(':') {
+            Some(split) => split,
+            None => return Err(ContentDigestError::BadDigest(digest)),
+        };
 
-        if digest_split.len() != 2 {
+        let algorithm: DigestAlgorithm = std::str::FromStr::from_str(algorithm)
+            .map_err(|_| ContentDigestError::UnsupportedAlgorithm(algorithm.to_string()))?;
+        let valid =
+            encoded.len() == algorithm.hex_len() && encoded.bytes().all(|b| b.is_ascii_hexdigit());
+        if !valid {
             return Err(ContentDigestError::BadDigest(digest));
         }
 
-        let algorithm = std::str::FromStr::from_str(digest_split[0])?;
         Ok(ContentDigest {
-            digest: digest_split[1].to_string(),
+            digest: canonical,
             algorithm,
         })
     }
 
-    pub fn start_hash(&self) -> Sha256 {
-        self.algorithm.new()
+    /// Compute the sha256 digest of the given content.
+    pub fn from_bytes(input: &[u8]) -> Self {
+        ContentDigest {
+            digest: format!("sha256:{:x}", sha2::Sha256::digest(input)),
+            algorithm: DigestAlgorithm::Sha256,
+        }
+    }
+
+    /// Compute the sha256 digest of a file.
+    ///
+    /// The file is streamed through the hasher with a large buffer.
+    pub fn for_file(path: &std::path::Path) -> std::io::Result<Self> {
+        Ok(hash_file(path, DigestAlgorithm::Sha256)?.finalize())
+    }
+
+    /// Check the content of a file against this digest.
+    ///
+    /// Errors reading the file are returned as such, not as a mismatch.
+    pub fn verify_file(&self, path: &std::path::Path) -> crate::errors::Result<()> {
+        let hasher = hash_file(path, self.algorithm)?;
+        Ok(self.try_verify_hash(&hasher)?)
+    }
+
+    /// The algorithm of the digest.
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    /// The hex encoded hash, without the algorithm prefix.
+    pub fn hex(&self) -> &str {
+        &self.digest[self.algorithm.to_string().len() + 1..]
+    }
+
+    /// Relative path under which content with this digest is stored, as `<algorithm>/<hex>`.
+    pub(crate) fn relative_path(&self) -> std::path::PathBuf {
+        std::path::Path::new(&self.algorithm.to_string()).join(self.hex())
+    }
+
+    /// File name for content with this digest, as `<algorithm>-<hex>`.
+    ///
+    /// Unlike the digest itself, this is a valid file name on Windows.
+    pub(crate) fn file_name(&self) -> String {
+        format!("{}-{}", self.algorithm, self.hex())
+    }
+
+    /// Start hashing content with the algorithm of this digest.
+    pub fn start_hash(&self) -> Hasher {
+        self.algorithm.hasher()
     }
 
     /// try_verify hashes the input slice and compares it with the digest stored in this instance
@@ -73,9 +145,8 @@ impl ContentDigest {
     /// try_verify hashes the input slice and compares it with the digest stored in this instance
     ///
     /// Success depends on the result of the comparison
-    pub fn try_verify_hash(&self, input: &Sha256) -> std::result::Result<(), ContentDigestError> {
-        let hash = self.algorithm.finalize(input);
-        let layer_digest = Self::try_new(hash)?;
+    pub fn try_verify_hash(&self, input: &Hasher) -> std::result::Result<(), ContentDigestError> {
+        let layer_digest = input.clone().finalize();
 
         if self != &layer_digest {
             return Err(ContentDigestError::Verify {
@@ -91,40 +162,236 @@ impl ContentDigest {
 
 impl std::fmt::Display for ContentDigest {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}:{}", self.algorithm, self.digest)
+        f.write_str(&self.digest)
+    }
+}
+
+impl AsRef<str> for ContentDigest {
+    fn as_ref(&self) -> &str {
+        &self.digest
+    }
+}
+
+impl std::str::FromStr for ContentDigest {
+    type Err = ContentDigestError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::try_new(s.to_string())
+    }
+}
+
+impl std::convert::TryFrom<&str> for ContentDigest {
+    type Error = ContentDigestError;
+
+    fn try_from(s: &str) -> std::result::Result<Self, Self::Error> {
+        Self::try_new(s.to_string())
+    }
+}
+
+impl std::convert::TryFrom<String> for ContentDigest {
+    type Error = ContentDigestError;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        Self::try_new(s)
+    }
+}
+
+/// Buffer size used to hash files, larger reads are much faster for big layers.
+const FILE_BUFFER_SIZE: usize = 1 << 20;
+
+fn hash_file(path: &std::path::Path, algorithm: DigestAlgorithm) -> std::io::Result<Hasher> {
+    let file = std::fs::File::open(path)?;
+    let mut reader = std::io::BufReader::with_capacity(FILE_BUFFER_SIZE, file);
+    let mut hasher = algorithm.hasher();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher)
+}
+
+/// Reader hashing all data read through it, for verification at the end.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use ghregistry::{ContentDigest, DigestReader};
+/// use std::io::Read;
+///
+/// let digest: ContentDigest = "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".parse()?;
+/// let mut reader = DigestReader::new(std::fs::File::open("blob")?, digest);
+/// let mut content = Vec::new();
+/// reader.read_to_end(&mut content)?;
+/// reader.verify()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DigestReader<R> {
+    inner: R,
+    hasher: Hasher,
+    expected: ContentDigest,
+}
+
+impl<R: std::io::Read> DigestReader<R> {
+    /// Wrap a reader whose content should match `expected`.
+    pub fn new(inner: R, expected: ContentDigest) -> Self {
+        DigestReader {
+            inner,
+            hasher: expected.start_hash(),
+            expected,
+        }
+    }
+
+    /// Check the data read so far against the expected digest.
+    pub fn verify(self) -> std::result::Result<(), ContentDigestError> {
+        self.expected.try_verify_hash(&self.hasher)
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Writer hashing all data written through it, for verification at the end.
+#[derive(Debug)]
+pub struct DigestWriter<W> {
+    inner: W,
+    hasher: Hasher,
+    expected: ContentDigest,
+}
+
+impl<W: std::io::Write> DigestWriter<W> {
+    /// Wrap a writer whose content should match `expected`.
+    pub fn new(inner: W, expected: ContentDigest) -> Self {
+        DigestWriter {
+            inner,
+            hasher: expected.start_hash(),
+            expected,
+        }
+    }
+
+    /// Continue writing after `existing`, which has been written before.
+    ///
+    /// The existing content is hashed but not written again, as when
+    /// resuming a download into a partial file.
+    pub fn resume<E: std::io::Read>(
+        inner: W,
+        expected: ContentDigest,
+        mut existing: E,
+    ) -> std::io::Result<Self> {
+        let mut hasher = expected.start_hash();
+        std::io::copy(&mut existing, &mut hasher)?;
+        Ok(DigestWriter {
+            inner,
+            hasher,
+            expected,
+        })
+    }
+
+    /// Check the data written so far against the expected digest.
+    pub fn verify(self) -> std::result::Result<(), ContentDigestError> {
+        self.expected.try_verify_hash(&self.hasher)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
     }
 }
 
 impl DigestAlgorithm {
+    /// Length of the hex encoded hash.
+    fn hex_len(&self) -> usize {
+        match self {
+            DigestAlgorithm::Sha256 => 64,
+            DigestAlgorithm::Sha512 => 128,
+        }
+    }
+
     fn hash(&self, input: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(input);
+        hasher.finalize().to_string()
+    }
+
+    fn hasher(&self) -> Hasher {
         match self {
-            DigestAlgorithm::Sha256 => {
-                let hash = sha2::Sha256::digest(input);
-                format!("{}:{:x}", self, hash)
-            }
+            DigestAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            DigestAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
         }
     }
+}
 
-    fn new(&self) -> Sha256 {
+impl Hasher {
+    /// Hash more content.
+    pub fn update(&mut self, data: &[u8]) {
         match self {
-            DigestAlgorithm::Sha256 => sha2::Sha256::new(),
+            Hasher::Sha256(h) => Digest::update(h, data),
+            Hasher::Sha512(h) => Digest::update(h, data),
         }
     }
 
-    fn finalize(&self, hash: &Sha256) -> String {
+    /// The algorithm of this hasher.
+    pub fn algorithm(&self) -> DigestAlgorithm {
         match self {
-            DigestAlgorithm::Sha256 => {
-                let h = hash.clone().finalize();
-                format!(
-                    "{}:{}",
-                    self,
-                    h.iter().map(|b| format!("{:02x}", b)).collect::<String>()
-                )
-            }
+            Hasher::Sha256(_) => DigestAlgorithm::Sha256,
+            Hasher::Sha512(_) => DigestAlgorithm::Sha512,
+        }
+    }
+
+    /// Finish hashing and return the digest of the content.
+    pub fn finalize(self) -> ContentDigest {
+        let algorithm = self.algorithm();
+        let hex = match self {
+            Hasher::Sha256(h) => format!("{:x}", h.finalize()),
+            Hasher::Sha512(h) => format!("{:x}", h.finalize()),
+        };
+        ContentDigest {
+            digest: format!("{}:{}", algorithm, hex),
+            algorithm,
         }
     }
 }
 
+impl From<Sha256> for Hasher {
+    fn from(hasher: Sha256) -> Self {
+        Hasher::Sha256(hasher)
+    }
+}
+
+impl std::io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Hasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Hasher({})", self.algorithm())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,11 +399,9 @@ mod tests {
 
     #[test]
     fn try_new_succeeds_with_correct_digest() -> Fallible<()> {
-        for correct_digest in
-            &["sha256:0000000000000000000000000000000000000000000000000000000000000000"]
-        {
-            ContentDigest::try_new(correct_digest.to_string())?;
-        }
+        let correct_digest =
+            "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+        ContentDigest::try_new(correct_digest.to_string())?;
 
         Ok(())
     }
@@ -147,6 +412,11 @@ mod tests {
             "invalid",
             "invalid:",
             "invalid:0000000000000000000000000000000000000000000000000000000000000000",
+            "sha256:",
+            "sha256:cafe",
+            "sha256:000000000000000000000000000000000000000000000000000000000000000g",
+            "sha256:0000000000000000000000000000000000000000000000000000000000000000:00",
+            "sha256: 0000000000000000000000000000000000000000000000000000000000000000",
         ] {
             if ContentDigest::try_new(incorrect_digest.to_string()).is_ok() {
                 panic!(
@@ -157,13 +427,50 @@ mod tests {
         }
     }
 
+    #[test]
+    fn try_new_canonicalizes() -> Fallible<()> {
+        let digest = ContentDigest::from_bytes(b"somecontent");
+        for variant in &[
+            digest.to_string().to_uppercase(),
+            format!(" {}\r\n", digest),
+            format!("Sha256:{}", digest.hex().to_uppercase()),
+        ] {
+            let parsed = ContentDigest::try_new(variant.clone())?;
+            assert_eq!(parsed, digest);
+            assert_eq!(parsed.to_string(), digest.to_string());
+            parsed.try_verify(b"somecontent")?;
+        }
+        Ok(())
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn valid_digests_round_trip(hex in "[0-9a-fA-F]{64}", upper in proptest::bool::ANY) {
+            let algorithm = if upper { "SHA256" } else { "sha256" };
+            let digest = ContentDigest::try_new(format!("{}:{}", algorithm, hex)).unwrap();
+            proptest::prop_assert_eq!(digest.hex(), hex.to_lowercase());
+            let reparsed: ContentDigest = digest.to_string().parse().unwrap();
+            proptest::prop_assert_eq!(reparsed, digest);
+        }
+
+        #[test]
+        fn corrupted_digests_fail(hex in "[0-9a-f]{64}", index in 0..64usize, bad in "[g-z:/ ]") {
+            let mut corrupted = hex.clone();
+            corrupted.replace_range(index..index + 1, &bad);
+            for digest in &[corrupted, hex[1..].to_string(), format!("{}0", hex)] {
+                let result = ContentDigest::try_new(format!("sha256:{}", digest));
+                proptest::prop_assert!(result.is_err(), "accepted {:?}", digest);
+            }
+        }
+    }
+
     #[test]
     fn try_verify_succeeds_with_same_content() -> Fallible<()> {
         let blob: &[u8] = b"somecontent";
-        let digest = DigestAlgorithm::Sha256.hash(&blob);
+        let digest = DigestAlgorithm::Sha256.hash(blob);
 
         ContentDigest::try_new(digest)?
-            .try_verify(&blob)
+            .try_verify(blob)
             .map_err(Into::into)
     }
 
@@ -171,14 +478,107 @@ mod tests {
     fn try_verify_fails_with_different_content() -> Fallible<()> {
         let blob: &[u8] = b"somecontent";
         let different_blob: &[u8] = b"someothercontent";
-        let digest = DigestAlgorithm::Sha256.hash(&blob);
+        let digest = DigestAlgorithm::Sha256.hash(blob);
 
         if ContentDigest::try_new(digest)?
-            .try_verify(&different_blob)
+            .try_verify(different_blob)
             .is_ok()
         {
             panic!("expected try_verify to fail for a different blob");
         }
         Ok(())
     }
+
+    #[test]
+    fn parse_and_format_round_trip() -> Fallible<()> {
+        use std::convert::TryFrom;
+
+        let digest = ContentDigest::from_bytes(b"somecontent");
+        let parsed: ContentDigest = digest.to_string().parse()?;
+        assert_eq!(parsed, digest);
+        assert_eq!(ContentDigest::try_from(digest.as_ref())?, digest);
+        assert_eq!(parsed.algorithm(), DigestAlgorithm::Sha256);
+        assert_eq!(format!("sha256:{}", parsed.hex()), digest.to_string());
+
+        let mut cache = std::collections::HashMap::new();
+        cache.insert(parsed, "blob");
+        assert_eq!(cache.get(&digest), Some(&"blob"));
+        Ok(())
+    }
+
+    #[test]
+    fn sha512_digests_verify() -> Fallible<()> {
+        let blob: &[u8] = b"somecontent";
+        let digest = format!("sha512:{:x}", Sha512::digest(blob));
+
+        let digest = ContentDigest::try_new(digest)?;
+        assert_eq!(digest.algorithm(), DigestAlgorithm::Sha512);
+        digest.try_verify(blob)?;
+
+        let mut hasher = digest.start_hash();
+        std::io::Write::write_all(&mut hasher, blob)?;
+        digest.try_verify_hash(&hasher)?;
+        assert!(digest.try_verify(b"someothercontent").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_algorithm_is_unsupported() {
+        match ContentDigest::try_new(format!("md5:{}", "0".repeat(32))) {
+            Err(ContentDigestError::UnsupportedAlgorithm(name)) => assert_eq!(name, "md5"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn digest_reader_and_writer_verify() -> Fallible<()> {
+        use std::io::{Read, Write};
+
+        let blob: &[u8] = b"somecontent";
+        let digest = ContentDigest::from_bytes(blob);
+
+        let mut reader = DigestReader::new(blob, digest.clone());
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        reader.verify()?;
+
+        let mut writer = DigestWriter::new(Vec::new(), digest.clone());
+        writer.write_all(blob)?;
+        assert_eq!(writer.get_ref().as_slice(), blob);
+        writer.verify()?;
+
+        let mut writer = DigestWriter::resume(Vec::new(), digest.clone(), &blob[..4])?;
+        writer.write_all(&blob[4..])?;
+        assert_eq!(writer.get_ref().as_slice(), &blob[4..]);
+        writer.verify()?;
+
+        let mut reader = DigestReader::new(&blob[..4], digest);
+        std::io::copy(&mut reader, &mut std::io::sink())?;
+        assert!(reader.verify().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn file_digests() -> Fallible<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("blob");
+        std::fs::write(&path, b"somecontent")?;
+
+        let digest = ContentDigest::for_file(&path)?;
+        assert_eq!(digest, ContentDigest::from_bytes(b"somecontent"));
+        digest.verify_file(&path)?;
+
+        std::fs::write(&path, b"someothercontent")?;
+        assert!(matches!(
+            digest.verify_file(&path),
+            Err(crate::Error::ContentDigestParse(
+                ContentDigestError::Verify { .. }
+            ))
+        ));
+        assert!(matches!(
+            digest.verify_file(&dir.path().join("missing")),
+            Err(crate::Error::IO(_))
+        ));
+        Ok(())
+    }
 }
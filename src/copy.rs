@@ -0,0 +1,68 @@
+//! Plan copying images between repositories.
+
+use crate::errors::Result;
+use crate::manifest::Descriptor;
+use crate::Client;
+
+/// What copying an image to another repository involves, see `Client::plan_copy`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CopyPlan {
+    /// Blobs to download from the source and upload to the destination.
+    pub to_upload: Vec<Descriptor>,
+    /// Blobs to mount from the source repository, on the same registry.
+    pub to_mount: Vec<Descriptor>,
+    /// Blobs the destination already has.
+    pub already_present: Vec<Descriptor>,
+    /// Size of the blobs to upload, the bytes actually moved.
+    pub total_bytes: u64,
+}
+
+impl Client {
+    /// Plan copying an image to `dest_name` on the registry of `destination`.
+    ///
+    /// Nothing is transferred: the manifest is resolved like for
+    /// `pull_image` and the destination is asked which of the config and
+    /// layers it has, like `which_blobs_missing`. Missing blobs are mounted
+    /// when both repositories are on the same registry, and uploaded
+    /// otherwise. Foreign layers are left out, as registries don't store them.
+    pub fn plan_copy(
+        &self,
+        name: &str,
+        reference: &str,
+        destination: &Client,
+        dest_name: &str,
+    ) -> Result<CopyPlan> {
+        let manifest = self.get_image_manifest(name, reference)?;
+        let config = manifest.config();
+        let mut blobs = vec![Descriptor {
+            media_type: config.media_type.clone(),
+            digest: config.digest.clone(),
+            size: config.size,
+            ..Default::default()
+        }];
+        blobs.extend(
+            manifest
+                .layers()
+                .iter()
+                .filter(|l| !l.is_foreign())
+                .cloned(),
+        );
+
+        let digests = blobs.iter().map(|b| b.digest.as_str()).collect::<Vec<_>>();
+        let missing = destination.which_blobs_missing(dest_name, &digests)?;
+        let same_registry = self.index == destination.index;
+
+        let mut plan = CopyPlan::default();
+        for blob in blobs {
+            if !missing.contains(&blob.digest) {
+                plan.already_present.push(blob);
+            } else if same_registry {
+                plan.to_mount.push(blob);
+            } else {
+                plan.total_bytes += blob.size;
+                plan.to_upload.push(blob);
+            }
+        }
+        Ok(plan)
+    }
+}
@@ -0,0 +1,124 @@
+//! Docker credential helpers, like `docker-credential-osxkeychain`.
+//!
+//! The protocol is described at https://github.com/docker/docker-credential-helpers.
+
+use crate::errors::{Error, Result};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Message printed by helpers which have no credentials for a server.
+const NOT_FOUND_MESSAGE: &str = "credentials not found";
+
+/// Response of the `get` command.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+/// Ask `docker-credential-<name>` for the user name and secret of a server.
+///
+/// Returns `None` if the helper has no credentials for the server.
+pub(crate) fn get(name: &str, server_url: &str) -> Result<Option<(String, String)>> {
+    run(
+        OsStr::new(&format!("docker-credential-{}", name)),
+        server_url,
+    )
+}
+
+fn run(program: &OsStr, server_url: &str) -> Result<Option<(String, String)>> {
+    let failed = |reason: String| Error::CredentialHelper {
+        helper: program.to_string_lossy().into_owned(),
+        reason,
+    };
+    let mut child = Command::new(program)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| failed(e.to_string()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Helpers may exit without reading the request, report their output instead
+        match stdin.write_all(server_url.as_bytes()) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        // Helpers report errors on stdout, but be lenient
+        let message = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        if message.contains(NOT_FOUND_MESSAGE) {
+            trace!("credential helper has no credentials for {}", server_url);
+            return Ok(None);
+        }
+        return Err(failed(message.trim().to_string()));
+    }
+    let creds: HelperCredentials = serde_json::from_slice(&output.stdout)?;
+    Ok(Some((creds.username, creds.secret)))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn helper(dir: &std::path::Path, name: &str, script: &str) -> std::path::PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// Run a freshly written helper.
+    ///
+    /// Processes spawned by concurrent tests may briefly inherit the write
+    /// handle of the script, making exec fail with `ETXTBSY`.
+    fn run_script(path: &std::path::Path) -> Result<Option<(String, String)>> {
+        for _ in 0..50 {
+            match run(path.as_os_str(), "ghcr.io") {
+                Err(Error::CredentialHelper { reason, .. }) if reason.contains("busy") => {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                result => return result,
+            }
+        }
+        run(path.as_os_str(), "ghcr.io")
+    }
+
+    #[test]
+    fn run_helpers() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let found = helper(
+            dir.path(),
+            "found",
+            r#"read url; echo "{\"ServerURL\": \"$url\", \"Username\": \"user\", \"Secret\": \"$url-secret\"}""#,
+        );
+        assert_eq!(
+            run_script(&found).unwrap(),
+            Some(("user".to_string(), "ghcr.io-secret".to_string()))
+        );
+
+        let missing = helper(
+            dir.path(),
+            "missing",
+            "echo 'credentials not found in native keychain'; exit 1",
+        );
+        assert_eq!(run_script(&missing).unwrap(), None);
+
+        let broken = helper(dir.path(), "broken", "echo 'keychain locked'; exit 1");
+        match run_script(&broken) {
+            Err(Error::CredentialHelper { reason, .. }) => assert_eq!(reason, "keychain locked"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
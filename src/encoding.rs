@@ -0,0 +1,47 @@
+//! Content encoding negotiation for registry responses.
+//!
+//! Blobs are always fetched as-is, so their bytes hash against the digest.
+//! Small JSON documents may be compressed in transit, see
+//! `Config::accept_encoding`, and are decoded here.
+
+use crate::errors::Result;
+use crate::Client;
+use libflate::gzip;
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING};
+use std::io::Read;
+
+/// Headers for blob requests, asking proxies not to re-encode the content.
+pub(crate) fn blob_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+    headers
+}
+
+impl Client {
+    /// `Accept-Encoding` value for JSON documents like manifests and tag lists.
+    pub(crate) fn document_encoding(&self) -> HeaderValue {
+        if self.accept_encoding {
+            HeaderValue::from_static("gzip")
+        } else {
+            HeaderValue::from_static("identity")
+        }
+    }
+}
+
+/// Read a response body, undoing a gzip `Content-Encoding`.
+pub(crate) fn decoded_body(res: Response) -> Result<Vec<u8>> {
+    let gzipped = res
+        .headers()
+        .get(CONTENT_ENCODING)
+        .is_some_and(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"gzip"));
+    let body = res.bytes()?;
+    if !gzipped {
+        return Ok(body.to_vec());
+    }
+
+    trace!("decoding gzip response of {} bytes", body.len());
+    let mut decoded = Vec::new();
+    gzip::Decoder::new(&body[..])?.read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
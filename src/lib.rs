@@ -20,74 +20,477 @@ use serde::{Deserialize, Serialize};
 
 pub use crate::config::Config;
 
-// mod catalog;
+mod catalog;
 
 mod auth;
-mod tags;
+pub mod tags;
 
-pub use auth::WwwHeaderParseError;
+pub use auth::{AuthStatus, Scope, WwwHeaderParseError};
+pub use tags::TagFilter;
+
+pub use observer::Observer;
 
 pub mod manifest;
 
 mod blobs;
 
 mod content_digest;
+mod copy;
+mod credential_helper;
+mod encoding;
+mod mirrors;
+mod observer;
+mod pull;
+mod ratelimit;
+mod referrers;
+pub use blobs::BlobStream;
+pub use copy::CopyPlan;
+pub use pull::ImageSize;
+pub use ratelimit::RateLimit;
 pub mod render;
+mod save;
 
-pub(crate) use self::content_digest::ContentDigest;
-pub use self::content_digest::ContentDigestError;
+pub use self::content_digest::{
+    ContentDigest, ContentDigestError, DigestAlgorithm, DigestReader, DigestWriter, Hasher,
+};
 
+/// Default user-agent of clients, see `Config::user_agent`.
 pub static USER_AGENT: &str = "acheta-ghregistry/0.0";
 
+/// Registry credentials from a docker-client config file.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct DockerCredentials {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// OAuth2 refresh token stored by `docker login` for accounts with 2FA.
+    pub identity_token: Option<String>,
+}
+
+impl std::fmt::Debug for DockerCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DockerCredentials")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field(
+                "identity_token",
+                &self.identity_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
 /// Get registry credentials from a JSON config reader.
 ///
 /// This is a convenience decoder for docker-client credentials
 /// typically stored under `~/.docker/config.json`.
+/// Use `get_docker_credentials` to also get an identity token.
 pub fn get_credentials<T: Read>(
     reader: T,
     index: &str,
+) -> Result<(Option<String>, Option<String>)> {
+    let creds = get_docker_credentials(reader, index)?;
+    Ok((creds.username, creds.password))
+}
+
+/// Get registry credentials, including an identity token, from a JSON config reader.
+///
+/// Entries with an `identitytoken` may leave the `auth` value empty.
+pub fn get_docker_credentials<T: Read>(reader: T, index: &str) -> Result<DockerCredentials> {
+    let map: Auths = serde_json::from_reader(reader)?;
+    let real_index = config_key(index);
+    match find_entry(&map.auths, index) {
+        Some((_, entry)) => decode_auth_entry(entry, index),
+        None => Err(Error::AuthInfoMissing(real_index.to_string())),
+    }
+}
+
+/// Get registry credentials from the data of a Kubernetes image pull secret.
+///
+/// This accepts the `.dockerconfigjson` of `kubernetes.io/dockerconfigjson`
+/// secrets as well as the legacy `.dockercfg` layout, which lacks the
+/// `auths` wrapper. The data may still be base64 encoded, as in the secret's
+/// YAML or JSON representation.
+pub fn get_credentials_from_dockerconfigjson(
+    bytes: &[u8],
+    index: &str,
+) -> Result<(Option<String>, Option<String>)> {
+    let trimmed = std::str::from_utf8(bytes)
+        .map(str::trim)
+        .unwrap_or_default();
+    let decoded;
+    let json = if trimmed.starts_with('{') {
+        trimmed.as_bytes()
+    } else {
+        decoded = base64::decode(trimmed)?;
+        decoded.as_slice()
+    };
+
+    let value: serde_json::Value = serde_json::from_slice(json)?;
+    let map: Auths = if value.get("auths").is_some() {
+        serde_json::from_value(value)?
+    } else {
+        Auths {
+            auths: serde_json::from_value(value)?,
+            ..Default::default()
+        }
+    };
+    let creds = match find_entry(&map.auths, index) {
+        Some((_, entry)) => decode_auth_entry(entry, index)?,
+        None => return Err(Error::AuthInfoMissing(config_key(index).to_string())),
+    };
+    Ok((creds.username, creds.password))
+}
+
+/// Get registry credentials from a JSON config reader, asking credential helpers.
+///
+/// A helper configured for the registry in `credHelpers` is asked first,
+/// then inline `auths` entries are used, then the `credsStore` helper.
+/// Helpers are run as `docker-credential-<name> get`, so this shells out
+/// to external binaries. A helper without credentials for the registry
+/// results in `Error::AuthInfoMissing`.
+///
+/// Identity tokens from helpers are returned with `<token>` as user name,
+/// which `Config::username` and `Config::password` understand.
+pub fn get_credentials_with_helpers<T: Read>(
+    reader: T,
+    index: &str,
 ) -> Result<(Option<String>, Option<String>)> {
     let map: Auths = serde_json::from_reader(reader)?;
-    let real_index = match index {
+    let real_index = config_key(index);
+
+    let helper = match find_entry(&map.cred_helpers, index) {
+        Some(helper) => Some(helper),
+        None => match find_entry(&map.auths, index) {
+            Some((_, entry)) if entry.has_credentials() => {
+                let creds = decode_auth_entry(entry, index)?;
+                return Ok((creds.username, creds.password));
+            }
+            entry => map
+                .creds_store
+                .as_ref()
+                .map(|store| (entry.map_or(real_index, |(key, _)| key), store)),
+        },
+    };
+    let (server_url, helper) = match helper {
+        Some(helper) => helper,
+        None => return Err(Error::AuthInfoMissing(real_index.to_string())),
+    };
+    trace!("Asking credential helper {} for {}", helper, server_url);
+    match credential_helper::get(helper, server_url)? {
+        Some((user, secret)) => Ok((Some(user), Some(secret))),
+        None => Err(Error::AuthInfoMissing(real_index.to_string())),
+    }
+}
+
+/// Key of a registry in a docker config file.
+fn config_key(index: &str) -> &str {
+    match index {
         // docker.io has some special casing in config.json
         "docker.io" | "registry-1.docker.io" => "https://index.docker.io/v1/",
         other => other,
+    }
+}
+
+/// Look up the config entry of a registry.
+fn find_entry<'a, V>(entries: &'a HashMap<String, V>, index: &str) -> Option<(&'a str, &'a V)> {
+    let real_index = config_key(index);
+    let host = normalize_registry(index);
+    entries
+        .get_key_value(real_index)
+        // Keys may be written with a scheme or a path, compare hostnames as a fallback
+        .or_else(|| {
+            entries
+                .iter()
+                .find(|(key, _)| normalize_registry(key) == host)
+        })
+        // Wildcard keys like `*.gcr.io` match any subdomain
+        .or_else(|| {
+            entries.iter().find(|(key, _)| {
+                let key = normalize_registry(key);
+                key.strip_prefix('*')
+                    .is_some_and(|suffix| suffix.starts_with('.') && host.ends_with(suffix))
+            })
+        })
+        .map(|(key, entry)| (key.as_str(), entry))
+}
+
+/// Decode the inline credentials of a config entry.
+fn decode_auth_entry(entry: &AuthObj, index: &str) -> Result<DockerCredentials> {
+    let real_index = config_key(index);
+    let auth = entry.auth.as_str();
+    let identity_token = entry.identitytoken.clone();
+    if entry.username.is_some() || entry.password.is_some() {
+        trace!(
+            "Found credentials for user={:?} on {}",
+            entry.username,
+            index
+        );
+        return Ok(DockerCredentials {
+            username: entry.username.clone(),
+            password: entry.password.clone(),
+            identity_token,
+        });
+    }
+    if auth.trim().is_empty() {
+        if identity_token.is_none() {
+            // Placeholder entries are written when a credential store is used
+            return Err(Error::AuthInfoMissing(real_index.to_string()));
+        }
+        trace!("Found an identity token on {}", index);
+        return Ok(DockerCredentials {
+            identity_token,
+            ..Default::default()
+        });
+    }
+    let malformed = |reason: &str| Error::MalformedCredential {
+        index: real_index.to_string(),
+        reason: reason.to_string(),
     };
-    let auth = match map.auths.get(real_index) {
-        Some(x) => base64::decode(x.auth.as_str())?,
-        None => return Err(Error::AuthInfoMissing(real_index.to_string())),
-    };
-    let s = String::from_utf8(auth)?;
-    let creds: Vec<&str> = s.splitn(2, ':').collect();
-    let up = match (creds.get(0), creds.get(1)) {
-        (Some(&""), Some(p)) => (None, Some(p.to_string())),
-        (Some(u), Some(&"")) => (Some(u.to_string()), None),
-        (Some(u), Some(p)) => (Some(u.to_string()), Some(p.to_string())),
-        (_, _) => (None, None),
+    let auth =
+        base64::decode(auth.trim()).map_err(|_| malformed("the auth value is not base64"))?;
+    let s =
+        String::from_utf8(auth).map_err(|_| malformed("the decoded auth value is not UTF-8"))?;
+    let up = match s.split_once(':') {
+        Some(("", p)) => (None, Some(p.to_string())),
+        Some((u, "")) => (Some(u.to_string()), None),
+        Some((u, p)) => (Some(u.to_string()), Some(p.to_string())),
+        None => {
+            return Err(malformed(
+                "the decoded auth value must have the form 'user:password'",
+            ))
+        }
     };
     trace!("Found credentials for user={:?} on {}", up.0, index);
-    Ok(up)
+    Ok(DockerCredentials {
+        username: up.0,
+        password: up.1,
+        identity_token,
+    })
+}
+
+/// Get registry credentials from the Docker client config file.
+///
+/// The file is `config.json` in `$DOCKER_CONFIG` if set, else in `~/.docker`.
+pub fn get_credentials_from_default_config(
+    index: &str,
+) -> Result<(Option<String>, Option<String>)> {
+    let path = default_docker_config_path(|key| std::env::var_os(key));
+    get_credentials_from_config_file(path, index)
+}
+
+/// Get registry credentials from a Docker config file which must exist.
+fn get_credentials_from_config_file(
+    path: std::path::PathBuf,
+    index: &str,
+) -> Result<(Option<String>, Option<String>)> {
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::DockerConfigNotFound(path))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    get_credentials(std::io::BufReader::new(file), index)
+}
+
+/// Get registry credentials from the first docker or podman auth file with
+/// an entry for the index.
+///
+/// The files are searched in this order, skipping unset variables:
+///
+/// 1. `$DOCKER_CONFIG/config.json`
+/// 2. `~/.docker/config.json`
+/// 3. `$REGISTRY_AUTH_FILE`
+/// 4. `$XDG_RUNTIME_DIR/containers/auth.json`
+/// 5. `~/.config/containers/auth.json`
+///
+/// If no file has an entry, `Error::CredentialsNotFound` lists the paths tried.
+pub fn get_credentials_default(index: &str) -> Result<(Option<String>, Option<String>)> {
+    let paths = credential_search_paths(|key| std::env::var_os(key));
+    get_credentials_from_paths(&paths, index)
+}
+
+/// Get registry credentials from the first file with an entry for the index.
+fn get_credentials_from_paths(
+    paths: &[std::path::PathBuf],
+    index: &str,
+) -> Result<(Option<String>, Option<String>)> {
+    for path in paths {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        match get_credentials(std::io::BufReader::new(file), index) {
+            Err(Error::AuthInfoMissing(_)) => trace!("No credentials for {} in {:?}", index, path),
+            res => return res,
+        }
+    }
+    Err(Error::CredentialsNotFound {
+        index: index.to_string(),
+        paths: paths.to_vec(),
+    })
+}
+
+/// Locations of docker and podman auth files, in search order.
+fn credential_search_paths<F>(var: F) -> Vec<std::path::PathBuf>
+where
+    F: Fn(&str) -> Option<std::ffi::OsString>,
+{
+    let home = var("HOME")
+        .or_else(|| var("USERPROFILE"))
+        .map(std::path::PathBuf::from);
+    let mut paths = Vec::new();
+    if let Some(dir) = var("DOCKER_CONFIG") {
+        paths.push(std::path::PathBuf::from(dir).join("config.json"));
+    }
+    if let Some(home) = &home {
+        paths.push(home.join(".docker").join("config.json"));
+    }
+    if let Some(file) = var("REGISTRY_AUTH_FILE") {
+        paths.push(file.into());
+    }
+    if let Some(dir) = var("XDG_RUNTIME_DIR") {
+        paths.push(std::path::PathBuf::from(dir).join("containers/auth.json"));
+    }
+    if let Some(home) = &home {
+        paths.push(home.join(".config/containers/auth.json"));
+    }
+    paths
+}
+
+/// Location of the Docker client config file.
+fn default_docker_config_path<F>(var: F) -> std::path::PathBuf
+where
+    F: Fn(&str) -> Option<std::ffi::OsString>,
+{
+    let dir = match var("DOCKER_CONFIG") {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => var("HOME")
+            .or_else(|| var("USERPROFILE"))
+            .map(std::path::PathBuf::from)
+            .unwrap_or_default()
+            .join(".docker"),
+    };
+    dir.join("config.json")
+}
+
+/// Reduce a registry reference from a config file to its hostname and port.
+///
+/// This follows Docker, which strips the scheme and any path like `/v1/`,
+/// and maps the Docker Hub aliases to `index.docker.io`. Hostnames are
+/// compared case-insensitively and the default HTTPS port is dropped.
+fn normalize_registry(index: &str) -> String {
+    let host = index
+        .strip_prefix("https://")
+        .or_else(|| index.strip_prefix("http://"))
+        .unwrap_or(index);
+    let host = host.split('/').next().unwrap_or(host).to_ascii_lowercase();
+    match host.strip_suffix(":443").unwrap_or(&host) {
+        "docker.io" | "registry-1.docker.io" => "index.docker.io".to_string(),
+        host => host.to_string(),
+    }
+}
+
+/// Header announcing the distribution API version on `/v2/`.
+const API_VERSION_HEADER: &str = "Docker-Distribution-API-Version";
+
+/// Whether an API version header announces any `registry/2.x` version.
+///
+/// Some registries send several space or comma separated versions.
+fn is_v2_api_version(value: &reqwest::header::HeaderValue) -> bool {
+    let value = match value.to_str() {
+        Ok(value) => value,
+        Err(_) => return false,
+    };
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|version| version.strip_prefix("registry/2"))
+        .any(|minor| {
+            minor.is_empty()
+                || minor
+                    .strip_prefix('.')
+                    .is_some_and(|m| !m.is_empty() && m.chars().all(|c| c.is_ascii_digit()))
+        })
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 struct Auths {
+    #[serde(default)]
     auths: HashMap<String, AuthObj>,
+    #[serde(
+        rename = "credsStore",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    creds_store: Option<String>,
+    #[serde(
+        rename = "credHelpers",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    cred_helpers: HashMap<String, String>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 struct AuthObj {
+    #[serde(default)]
     auth: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identitytoken: Option<String>,
+}
+
+impl AuthObj {
+    /// Whether the entry holds credentials instead of being a placeholder.
+    fn has_credentials(&self) -> bool {
+        !self.auth.trim().is_empty() || self.username.is_some() || self.password.is_some()
+    }
+}
+
+/// Outcome of probing a registry with `Client::v2_support`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum V2Support {
+    /// The host is a v2 registry and the client is authorized.
+    Supported,
+    /// The host is a v2 registry but rejected the client.
+    Unauthorized,
+    /// The host answered but is not a v2 registry.
+    NotV2,
+    /// The host couldn't be reached, with the reason.
+    Unreachable(String),
 }
 
 /// A Client to make outgoing API requests to a registry.
+///
+/// Clones are cheap and share the authentication state, so a client can be
+/// cloned or put in an `Arc` for use by many threads. When a token expires
+/// or is rejected, one thread acquires a new one while the others wait and
+/// reuse it.
 #[derive(Clone, Debug)]
 pub struct Client {
     base_url: String,
     credentials: Option<(String, String)>,
     index: String,
     user_agent: Option<String>,
-    auth: Option<auth::Auth>,
+    /// Authentication shared with all clones, so refreshed tokens benefit them.
+    auth: std::sync::Arc<std::sync::RwLock<Option<auth::Auth>>>,
+    /// Held while refreshing `auth`, so concurrent requests refresh only once.
+    auth_refresh: std::sync::Arc<std::sync::Mutex<()>>,
     client: reqwest::blocking::Client,
+    strict_content_types: bool,
+    cache_dir: Option<std::path::PathBuf>,
+    /// Whether `auth` holds a token set through `Config::bearer_token`.
+    static_token: bool,
+    observer: Option<std::sync::Arc<dyn Observer>>,
+    /// Base URLs of pull-through mirrors, asked in order before `base_url`.
+    mirrors: Vec<String>,
+    accept_encoding: bool,
 }
 
 impl Client {
@@ -95,12 +498,53 @@ impl Client {
         Config::default()
     }
 
+    /// Create a client for a mock registry at `base_url`, like `http://127.0.0.1:1234`.
+    ///
+    /// The optional token is sent as bearer token with every request, as
+    /// with `Config::bearer_token`, so tests don't need to mock the
+    /// authentication flow. The base URL may carry a path prefix.
+    pub fn new_for_test(base_url: String, token: Option<String>) -> Result<Self> {
+        let url = reqwest::Url::parse(&base_url)?;
+        let index = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(Error::Uri(url::ParseError::EmptyHost)),
+        };
+        let mut config = Config::default()
+            .registry(&index)
+            .insecure_registry(url.scheme() == "http");
+        if let Some(token) = token {
+            config = config.bearer_token(token);
+        }
+        let mut client = config.build()?;
+        client.base_url = base_url.trim_end_matches('/').to_string();
+        Ok(client)
+    }
+
+    /// Return a client sending a different user-agent, e.g. to identify a subsystem.
+    ///
+    /// The returned client shares authentication and connections with this
+    /// one, so it is cheap to create for a single call:
+    /// `client.with_user_agent("mytool-sync/1.0").get_tags(name, None)`.
+    pub fn with_user_agent(&self, user_agent: impl Into<String>) -> Self {
+        Client {
+            user_agent: Some(user_agent.into()),
+            ..self.clone()
+        }
+    }
+
     /// Ensure remote registry supports v2 API.
     pub fn ensure_v2_registry(self) -> Result<Self> {
+        self.ensure_v2_registry_ref()?;
+        Ok(self)
+    }
+
+    /// Ensure remote registry supports v2 API, without consuming the client.
+    pub fn ensure_v2_registry_ref(&self) -> Result<()> {
         if !self.is_v2_supported()? {
             Err(Error::V2NotSupported)
         } else {
-            Ok(self)
+            Ok(())
         }
     }
 
@@ -108,17 +552,47 @@ impl Client {
     pub fn is_v2_supported(&self) -> Result<bool> {
         match self.is_v2_supported_and_authorized() {
             Ok((v2_supported, _)) => Ok(v2_supported),
-            Err(crate::Error::UnexpectedHttpStatus(_)) => Ok(false),
+            Err(e) if matches!(e.inner(), crate::Error::UnexpectedHttpStatus(_)) => Ok(false),
             Err(e) => Err(e),
         }
     }
 
+    /// Probe the `/v2/` endpoint and report what the remote answered.
+    ///
+    /// Unlike `is_v2_supported`, this tells apart hosts that can't be reached,
+    /// hosts which aren't v2 registries, and registries rejecting the client.
+    pub fn v2_support(&self) -> V2Support {
+        let response = reqwest::Url::parse(&format!("{}/v2/", self.base_url))
+            .map_err(Error::from)
+            .and_then(|url| {
+                let request = self.build_reqwest(reqwest::Method::GET, url).build()?;
+                self.execute(request)
+            });
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => return V2Support::Unreachable(e.to_string()),
+        };
+
+        let is_v2 = response
+            .headers()
+            .get(API_VERSION_HEADER)
+            .is_some_and(is_v2_api_version);
+        match (response.status(), is_v2) {
+            (reqwest::StatusCode::OK, true) => V2Support::Supported,
+            (reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN, true) => {
+                V2Support::Unauthorized
+            }
+            (status, _) => {
+                trace!("v2 probe got status {}, v2 header: {}", status, is_v2);
+                V2Support::NotV2
+            }
+        }
+    }
+
     /// Check whether remote registry supports v2 API and `self` is authorized.
-    /// Authorized means to successfully GET the `/v2` endpoint on the remote registry.
+    /// Authorized means to successfully GET the `/v2` endpoint on the remote registry,
+    /// anonymously or not. Use `auth_status` to tell both apart.
     pub fn is_v2_supported_and_authorized(&self) -> Result<(bool, bool)> {
-        let api_header = "Docker-Distribution-API-Version";
-        let api_version = "registry/2.0";
-
         // GET request to bare v2 endpoint.
         let v2_endpoint = format!("{}/v2/", self.base_url);
         let request = reqwest::Url::parse(&v2_endpoint).map(|url| {
@@ -126,14 +600,18 @@ impl Client {
             self.build_reqwest(reqwest::Method::GET, url)
         })?;
 
-        let response = request.send()?;
+        let response = self.execute(request.build()?)?;
 
-        let b = match (response.status(), response.headers().get(api_header)) {
-            (reqwest::StatusCode::OK, Some(x)) => Ok((x == api_version, true)),
-            (reqwest::StatusCode::UNAUTHORIZED, Some(x)) => Ok((x == api_version, false)),
+        let b = match (
+            response.status(),
+            response.headers().get(API_VERSION_HEADER),
+        ) {
+            (reqwest::StatusCode::OK, Some(x)) => Ok((is_v2_api_version(x), true)),
+            (reqwest::StatusCode::UNAUTHORIZED, Some(x)) => Ok((is_v2_api_version(x), false)),
             (s, v) => {
                 trace!("Got unexpected status {}, header version {:?}", s, v);
-                return Err(crate::Error::UnexpectedHttpStatus(s));
+                return Err(crate::Error::from_status(s, response.headers())
+                    .with_request(&reqwest::Method::GET, response.url()));
             }
         };
 
@@ -148,7 +626,8 @@ impl Client {
     ) -> reqwest::blocking::RequestBuilder {
         let mut builder = self.client.request(method, url);
 
-        if let Some(auth) = &self.auth {
+        self.refresh_expiring_token();
+        if let Some(auth) = self.current_auth() {
             builder = auth.add_auth_headers(builder);
         };
 
@@ -160,22 +639,280 @@ impl Client {
     }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct ApiError {
-    code: String,
-    message: String,
-    detail: String,
-}
-
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct Errors {
-    errors: Vec<ApiError>,
-}
-
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use test_case::test_case;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn test_client_bounds() {
+        fn check_bounds<T: Send + Sync + Clone + 'static>() {}
+        check_bounds::<Client>();
+    }
+
+    #[test_case("registry/2.0", true; "exact")]
+    #[test_case("registry/2.1", true; "minor")]
+    #[test_case("registry/2", true; "major only")]
+    #[test_case("registry/1.0, registry/2.0", true; "list")]
+    #[test_case("registry/1.0", false; "v1")]
+    #[test_case("registry/20", false; "other major")]
+    #[test_case("registry/2.x", false; "not a number")]
+    #[test_case("", false; "empty")]
+    fn test_is_v2_api_version(value: &str, expected: bool) {
+        let value = reqwest::header::HeaderValue::from_str(value).unwrap();
+        assert_eq!(is_v2_api_version(&value), expected);
+    }
+
+    fn config(key: &str) -> String {
+        format!(
+            r#"{{"auths": {{"{}": {{"auth": "{}"}}}}}}"#,
+            key,
+            base64::encode("user:secret")
+        )
+    }
+
+    #[test_case("ghcr.io", "ghcr.io"; "bare host")]
+    #[test_case("ghcr.io", "https://ghcr.io"; "index with scheme")]
+    #[test_case("https://ghcr.io/", "ghcr.io/"; "trailing slashes")]
+    #[test_case("registry.example.com:5000", "registry.example.com:5000"; "port")]
+    #[test_case("http://registry.example.com:5000/v2/", "registry.example.com:5000"; "key with port and path")]
+    #[test_case("https://index.docker.io/v1/", "docker.io"; "docker hub")]
+    #[test_case("index.docker.io", "registry-1.docker.io"; "docker hub alias")]
+    #[test_case("https://ghcr.io:443", "ghcr.io"; "default port")]
+    #[test_case("ghcr.io/v2/", "ghcr.io"; "key with api path")]
+    #[test_case("https://GHCR.io", "ghcr.io"; "uppercase key")]
+    #[test_case("quay.io", "https://Quay.io/v1/"; "uppercase index with path")]
+    #[test_case("https://registry.local:5000/v2/", "registry.local:5000"; "private registry with port")]
+    #[test_case("*.gcr.io", "eu.gcr.io"; "wildcard helper key")]
+    fn get_credentials_normalizes_registry(key: &str, index: &str) {
+        assert!(
+            get_credentials(config("registry.local:5001").as_bytes(), index).is_err(),
+            "other ports must not match"
+        );
+        let creds = get_credentials(config(key).as_bytes(), index).unwrap();
+        assert_eq!(
+            creds,
+            (Some("user".to_string()), Some("secret".to_string()))
+        );
+    }
+
+    #[test]
+    fn get_docker_credentials_identity_token() {
+        let config = format!(
+            r#"{{"auths": {{"ghcr.io": {{"auth": "{}", "identitytoken": "refresh"}}, "docker.io": {{"identitytoken": "other"}}}}}}"#,
+            base64::encode("user:")
+        );
+        let creds = get_docker_credentials(config.as_bytes(), "ghcr.io").unwrap();
+        assert_eq!(creds.username.as_deref(), Some("user"));
+        assert_eq!(creds.password, None);
+        assert_eq!(creds.identity_token.as_deref(), Some("refresh"));
+
+        let creds = get_docker_credentials(config.as_bytes(), "docker.io").unwrap();
+        assert_eq!(creds.username, None);
+        assert_eq!(creds.identity_token.as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn get_credentials_with_helpers_prefers_inline_auths() {
+        let config = format!(
+            r#"{{"auths": {{"ghcr.io": {{"auth": "{}"}}, "quay.io": {{}}}}, "credsStore": "ghregistry-missing"}}"#,
+            base64::encode("user:secret")
+        );
+        let creds = get_credentials_with_helpers(config.as_bytes(), "ghcr.io").unwrap();
+        assert_eq!(creds.1.as_deref(), Some("secret"));
+
+        match get_credentials_with_helpers(config.as_bytes(), "quay.io") {
+            Err(Error::CredentialHelper { helper, .. }) => {
+                assert_eq!(helper, "docker-credential-ghregistry-missing")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match get_credentials_with_helpers(r#"{"auths": {}}"#.as_bytes(), "quay.io") {
+            Err(Error::AuthInfoMissing(index)) => assert_eq!(index, "quay.io"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    const DOCKER_CONFIG: &str = r#"{
+        "auths": {
+            "https://index.docker.io/v1/": {},
+            "ghcr.io": {
+                "auth": "dXNlcjpzZWNyZXQ="
+            }
+        },
+        "credsStore": "desktop",
+        "currentContext": "default"
+    }"#;
+
+    const PODMAN_AUTH: &str = r#"{
+        "auths": {
+            "quay.io": {
+                "auth": "dXNlcjpzZWNyZXQ="
+            },
+            "registry.example.com": {
+                "username": "robot",
+                "password": "hunter2",
+                "email": "robot@example.com"
+            }
+        }
+    }"#;
+
+    const GCLOUD_CONFIG: &str = r#"{
+        "credHelpers": {
+            "gcr.io": "gcloud",
+            "us-docker.pkg.dev": "gcloud"
+        }
+    }"#;
+
+    #[test_case(DOCKER_CONFIG, "ghcr.io", Some(("user", "secret")); "docker inline auth")]
+    #[test_case(DOCKER_CONFIG, "docker.io", None; "docker credential store placeholder")]
+    #[test_case(PODMAN_AUTH, "quay.io", Some(("user", "secret")); "podman inline auth")]
+    #[test_case(PODMAN_AUTH, "registry.example.com", Some(("robot", "hunter2")); "explicit username and password")]
+    #[test_case(GCLOUD_CONFIG, "gcr.io", None; "gcloud credential helpers")]
+    fn get_credentials_config_samples(config: &str, index: &str, expected: Option<(&str, &str)>) {
+        // Serializing the parsed config must keep the credentials intact
+        let parsed: Auths = serde_json::from_str(config).unwrap();
+        let round_trip = serde_json::to_string(&parsed).unwrap();
+
+        for config in &[config, round_trip.as_str()] {
+            match (get_credentials(config.as_bytes(), index), expected) {
+                (Ok((user, password)), Some((u, p))) => {
+                    assert_eq!(user.as_deref(), Some(u));
+                    assert_eq!(password.as_deref(), Some(p));
+                }
+                (Err(Error::AuthInfoMissing(_)), None) => {}
+                (other, _) => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn get_credentials_from_pull_secrets() {
+        let wrapped = config("ghcr.io");
+        let legacy = format!(
+            r#"{{"ghcr.io": {{"auth": "{}", "email": "user@example.com"}}}}"#,
+            base64::encode("user:secret")
+        );
+        for data in &[
+            wrapped.clone(),
+            legacy.clone(),
+            base64::encode(&wrapped),
+            format!("{}\n", base64::encode(&legacy)),
+        ] {
+            let creds = get_credentials_from_dockerconfigjson(data.as_bytes(), "ghcr.io").unwrap();
+            assert_eq!(
+                creds,
+                (Some("user".to_string()), Some("secret".to_string()))
+            );
+        }
+
+        match get_credentials_from_dockerconfigjson(wrapped.as_bytes(), "quay.io") {
+            Err(Error::AuthInfoMissing(index)) => assert_eq!(index, "quay.io"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn credential_search_order() {
+        let env = |key: &str| match key {
+            "HOME" => Some("/home/user".into()),
+            "REGISTRY_AUTH_FILE" => Some("/etc/auth.json".into()),
+            "XDG_RUNTIME_DIR" => Some("/run/user/1000".into()),
+            _ => None,
+        };
+        let paths = credential_search_paths(env);
+        assert_eq!(
+            paths,
+            vec![
+                std::path::PathBuf::from("/home/user/.docker/config.json"),
+                "/etc/auth.json".into(),
+                "/run/user/1000/containers/auth.json".into(),
+                "/home/user/.config/containers/auth.json".into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_credentials_from_first_matching_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let docker = dir.path().join("config.json");
+        let podman = dir.path().join("auth.json");
+        let paths = vec![
+            dir.path().join("missing.json"),
+            docker.clone(),
+            podman.clone(),
+        ];
+        std::fs::write(&docker, config("quay.io")).unwrap();
+        std::fs::write(&podman, config("https://ghcr.io")).unwrap();
+
+        let creds = get_credentials_from_paths(&paths, "ghcr.io").unwrap();
+        assert_eq!(creds.0.as_deref(), Some("user"));
+
+        match get_credentials_from_paths(&paths, "docker.io") {
+            Err(Error::CredentialsNotFound {
+                index,
+                paths: tried,
+            }) => {
+                assert_eq!(index, "docker.io");
+                assert_eq!(tried, paths);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_credentials_from_docker_config_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = default_docker_config_path(|key| match key {
+            "DOCKER_CONFIG" => Some(dir.path().into()),
+            "HOME" => Some("/home/user".into()),
+            _ => None,
+        });
+        assert_eq!(path, dir.path().join("config.json"));
+
+        match get_credentials_from_config_file(path.clone(), "ghcr.io") {
+            Err(Error::DockerConfigNotFound(missing)) => assert_eq!(missing, path),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        std::fs::write(&path, config("ghcr.io")).unwrap();
+        let creds = get_credentials_from_config_file(path, "https://ghcr.io").unwrap();
+        assert_eq!(creds.0.as_deref(), Some("user"));
+    }
+
+    #[test]
+    fn docker_config_path_defaults_to_home() {
+        let path = default_docker_config_path(|key| match key {
+            "USERPROFILE" => Some("C:\\Users\\user".into()),
+            _ => None,
+        });
+        assert_eq!(
+            path,
+            std::path::Path::new("C:\\Users\\user")
+                .join(".docker")
+                .join("config.json")
+        );
+    }
+
+    #[test_case("not base64!"; "invalid base64")]
+    #[test_case("dXNlcm5hbWU="; "missing colon")]
+    fn get_credentials_rejects_malformed_auth(auth: &str) {
+        let config = format!(r#"{{"auths": {{"ghcr.io": {{"auth": "{}"}}}}}}"#, auth);
+        match get_credentials(config.as_bytes(), "ghcr.io") {
+            Err(Error::MalformedCredential { index, .. }) => assert_eq!(index, "ghcr.io"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_credentials_keeps_ports_apart() {
+        let config = config("registry.example.com:5000");
+        assert!(get_credentials(config.as_bytes(), "registry.example.com").is_err());
+        assert!(get_credentials(config.as_bytes(), "registry.example.com:5001").is_err());
+    }
 }
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+/// Content descriptor, as defined by the OCI image spec.
+///
+/// Specification is at https://github.com/opencontainers/image-spec/blob/main/descriptor.md.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Descriptor {
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    /// Type of an artifact, for manifests describing one.
+    #[serde(
+        rename = "artifactType",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub artifact_type: Option<String>,
+    /// Locations the content may be downloaded from instead of the registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub urls: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+impl Descriptor {
+    /// Whether this describes a foreign layer, which registries may not serve.
+    ///
+    /// Such layers, like the base layers of Windows images, are downloaded
+    /// from their `urls` instead.
+    pub fn is_foreign(&self) -> bool {
+        self.media_type
+            .parse::<crate::mediatypes::MediaTypes>()
+            .is_ok_and(|m| m.is_foreign_layer())
+    }
+}
+
+/// OCI image index, as returned by the referrers API.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct ImageIndex {
+    #[serde(rename = "schemaVersion")]
+    pub(crate) schema_version: u16,
+    #[serde(rename = "mediaType", default)]
+    pub(crate) media_type: Option<String>,
+    #[serde(default)]
+    pub(crate) manifests: Vec<Descriptor>,
+}
@@ -0,0 +1,188 @@
+use crate::errors::Result;
+use std::collections::HashMap;
+
+/// Image configuration (application/vnd.docker.container.image.v1+json).
+///
+/// Specification is at https://github.com/opencontainers/image-spec/blob/v1.0.2/config.md.
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct ImageConfig {
+    #[serde(default)]
+    pub architecture: String,
+    #[serde(default)]
+    pub os: String,
+    /// Execution parameters used as defaults when running a container.
+    #[serde(default)]
+    pub config: Option<ContainerConfig>,
+    #[serde(default)]
+    pub rootfs: RootFs,
+    /// Build steps of the image, oldest first.
+    #[serde(default)]
+    pub history: Vec<HistoryEntry>,
+}
+
+impl ImageConfig {
+    /// Pair the history entries which created a layer with the layer digests.
+    ///
+    /// Entries with `empty_layer` set, like `ENV` or `CMD` steps, are skipped.
+    /// The layer digests are taken in manifest order, lowest layer first.
+    pub fn layer_history<'a, S: AsRef<str>>(
+        &'a self,
+        layer_digests: &'a [S],
+    ) -> Vec<(&'a HistoryEntry, &'a str)> {
+        self.history
+            .iter()
+            .filter(|entry| !entry.empty_layer)
+            .zip(layer_digests.iter().map(AsRef::as_ref))
+            .collect()
+    }
+}
+
+/// A build step from the history of an image.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// Creation time, formatted as RFC 3339.
+    #[serde(default)]
+    pub created: Option<String>,
+    /// The command which created the layer.
+    #[serde(default)]
+    pub created_by: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// Whether the step didn't create a layer.
+    #[serde(default)]
+    pub empty_layer: bool,
+}
+
+/// Execution parameters of an image.
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct ContainerConfig {
+    #[serde(rename = "Env", default)]
+    pub env: Option<Vec<String>>,
+    #[serde(rename = "Entrypoint", default)]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(rename = "Cmd", default)]
+    pub cmd: Option<Vec<String>>,
+    #[serde(rename = "WorkingDir", default)]
+    pub working_dir: Option<String>,
+    #[serde(rename = "Labels", default)]
+    pub labels: Option<HashMap<String, String>>,
+    /// Ports in the `<port>/<protocol>` form, e.g. `8080/tcp`.
+    #[serde(rename = "ExposedPorts", default, with = "port_set")]
+    pub exposed_ports: Vec<String>,
+}
+
+/// Layer content addresses of an image.
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct RootFs {
+    #[serde(rename = "type", default)]
+    pub fs_type: String,
+    /// Digests of the uncompressed layer tarballs, lowest layer first.
+    #[serde(default)]
+    pub diff_ids: Vec<String>,
+}
+
+/// The spec encodes sets as objects with empty values.
+mod port_set {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+        let ports: Option<BTreeMap<String, serde_json::Value>> = Option::deserialize(d)?;
+        Ok(ports.map(|p| p.into_keys().collect()).unwrap_or_default())
+    }
+
+    pub fn serialize<S: Serializer>(ports: &[String], s: S) -> Result<S::Ok, S::Error> {
+        ports
+            .iter()
+            .map(|p| (p.as_str(), serde_json::Map::new()))
+            .collect::<BTreeMap<_, _>>()
+            .serialize(s)
+    }
+}
+
+impl crate::Client {
+    /// Fetch and parse the configuration blob of an image.
+    ///
+    /// The config digest is found in the image manifest.
+    pub fn get_image_config(&self, name: &str, config_digest: &str) -> Result<ImageConfig> {
+        let blob = self.get_blob(name, config_digest)?;
+        Ok(serde_json::from_slice(&blob)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_config_parses_correctly() -> Result<()> {
+        let raw = r#"{
+            "architecture": "amd64",
+            "os": "linux",
+            "config": {
+                "Env": ["PATH=/usr/bin"],
+                "Entrypoint": ["/entrypoint.sh"],
+                "Cmd": ["serve"],
+                "WorkingDir": "/srv",
+                "Labels": {"org.opencontainers.image.version": "1.2.3"},
+                "ExposedPorts": {"8080/tcp": {}, "53/udp": {}}
+            },
+            "rootfs": {"type": "layers", "diff_ids": ["sha256:aa", "sha256:bb"]}
+        }"#;
+
+        let config: ImageConfig = serde_json::from_str(raw)?;
+        let container = config.config.expect("config section is present");
+
+        assert_eq!(config.architecture, "amd64");
+        assert_eq!(config.os, "linux");
+        assert_eq!(container.entrypoint, Some(vec!["/entrypoint.sh".into()]));
+        assert_eq!(container.working_dir.as_deref(), Some("/srv"));
+        assert_eq!(
+            container.labels.unwrap()["org.opencontainers.image.version"],
+            "1.2.3"
+        );
+        assert_eq!(container.exposed_ports, vec!["53/udp", "8080/tcp"]);
+        assert_eq!(config.rootfs.diff_ids, vec!["sha256:aa", "sha256:bb"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn layer_history_skips_empty_layers() -> Result<()> {
+        let raw = r#"{
+            "history": [
+                {"created": "2023-01-01T00:00:00Z", "created_by": "/bin/sh -c #(nop) ADD file:abc in /"},
+                {"created_by": "/bin/sh -c #(nop) CMD [\"sh\"]", "empty_layer": true},
+                {"created_by": "RUN apk add curl", "comment": "buildkit.dockerfile.v0"}
+            ]
+        }"#;
+        let config: ImageConfig = serde_json::from_str(raw)?;
+        let layers = vec!["sha256:base".to_string(), "sha256:curl".to_string()];
+
+        let history = config.layer_history(&layers);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].1, "sha256:base");
+        assert_eq!(
+            history[0].0.created.as_deref(),
+            Some("2023-01-01T00:00:00Z")
+        );
+        assert_eq!(history[1].1, "sha256:curl");
+        assert_eq!(history[1].0.created_by.as_deref(), Some("RUN apk add curl"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn image_config_tolerates_missing_sections() -> Result<()> {
+        let config: ImageConfig =
+            serde_json::from_str(r#"{"architecture": "arm64", "os": "linux"}"#)?;
+
+        assert!(config.config.is_none());
+        assert!(config.rootfs.diff_ids.is_empty());
+        assert!(config.history.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use super::Descriptor;
 use crate::errors::{Error, Result};
 use reqwest::Method;
 
@@ -11,7 +12,7 @@ pub struct ManifestSchema2Spec {
     #[serde(rename = "mediaType")]
     media_type: String,
     config: Config,
-    layers: Vec<S2Layer>,
+    layers: Vec<Descriptor>,
 }
 
 /// Super-type for combining a ManifestSchema2 with a ConfigBlob.
@@ -39,15 +40,6 @@ pub struct ConfigBlob {
     architecture: String,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct S2Layer {
-    #[serde(rename = "mediaType")]
-    media_type: String,
-    size: u64,
-    digest: String,
-    urls: Option<Vec<String>>,
-}
-
 /// Manifest List.
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ManifestList {
@@ -69,7 +61,7 @@ pub struct ManifestObj {
 }
 
 /// Platform-related manifest entries.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Platform {
     pub architecture: String,
     pub os: String,
@@ -81,33 +73,105 @@ pub struct Platform {
     pub features: Option<Vec<String>>,
 }
 
+impl Platform {
+    /// The platform of the running host, using the GOARCH/GOOS names of the image spec.
+    ///
+    /// Non-Windows hosts default to Linux images.
+    pub fn host() -> Self {
+        let architecture = goarch(std::env::consts::ARCH, cfg!(target_endian = "little"));
+        let os = if cfg!(windows) { "windows" } else { "linux" };
+        Platform {
+            architecture: architecture.to_string(),
+            os: os.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Map a Rust architecture name to its GOARCH name.
+///
+/// Rust uses the same name for both byte orders of some architectures,
+/// which GOARCH tells apart.
+fn goarch(arch: &str, little_endian: bool) -> &str {
+    match arch {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "powerpc64" if little_endian => "ppc64le",
+        "powerpc64" => "ppc64",
+        "mips64" if little_endian => "mips64le",
+        "mips" if little_endian => "mipsle",
+        other => other,
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
+impl ManifestList {
+    /// Find the manifest matching the operating system and architecture of a platform.
+    ///
+    /// The variant is only compared when the platform sets one.
+    pub fn find_platform(&self, platform: &Platform) -> Option<&ManifestObj> {
+        self.manifests.iter().find(|m| {
+            m.platform.os == platform.os
+                && m.platform.architecture == platform.architecture
+                && (platform.variant.is_none() || m.platform.variant == platform.variant)
+        })
+    }
+}
+
 impl ManifestSchema2Spec {
     /// Get `Config` object referenced by this manifest.
     pub fn config(&self) -> &Config {
         &self.config
     }
 
+    /// List digests and sizes of all layers referenced by this manifest.
+    ///
+    /// The returned layers list is ordered starting with the base image first.
+    pub fn get_layers(&self) -> Vec<(String, u64)> {
+        self.layers
+            .iter()
+            .map(|l| (l.digest.clone(), l.size))
+            .collect()
+    }
+
+    /// Descriptors of all layers, ordered starting with the base image first.
+    pub(crate) fn layers(&self) -> &[Descriptor] {
+        &self.layers
+    }
+
     /// Fetch the config blob for this manifest
     pub(crate) fn fetch_config_blob(
         self,
         client: crate::Client,
         repo: String,
     ) -> Result<ManifestSchema2> {
-        let url = {
-            let ep = format!(
-                "{}/v2/{}/blobs/{}",
-                client.base_url, repo, self.config.digest
-            );
-            reqwest::Url::parse(&ep)?
-        };
-
-        let r = client.build_reqwest(Method::GET, url.clone()).send()?;
+        let r = client.send_mirrored(
+            Method::GET,
+            &format!("{}/blobs/{}", repo, self.config.digest),
+            crate::encoding::blob_headers(),
+        )?;
 
         let status = r.status();
-        trace!("GET {:?}: {}", url, &status);
+        trace!("GET {:?}: {}", r.url(), &status);
 
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Err(Error::NotFound {
+                name: repo,
+                reference: self.config.digest,
+            });
+        }
         if !status.is_success() {
-            return Err(Error::UnexpectedHttpStatus(status));
+            return Err(Error::from_response(&Method::GET, r));
         }
 
         let config_blob = r.json::<ConfigBlob>()?;
@@ -145,3 +209,18 @@ impl ManifestSchema2 {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goarch_byte_order() {
+        assert_eq!(goarch("x86_64", true), "amd64");
+        assert_eq!(goarch("powerpc64", true), "ppc64le");
+        assert_eq!(goarch("powerpc64", false), "ppc64");
+        assert_eq!(goarch("mips64", true), "mips64le");
+        assert_eq!(goarch("mips64", false), "mips64");
+        assert_eq!(goarch("s390x", false), "s390x");
+    }
+}
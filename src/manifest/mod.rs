@@ -13,6 +13,14 @@ mod manifest_schema2;
 
 pub use self::manifest_schema2::*;
 
+mod image_config;
+
+pub use self::image_config::*;
+
+mod descriptor;
+
+pub use self::descriptor::*;
+
 impl Client {
     /// Fetch an image manifest.
     ///
@@ -27,32 +35,187 @@ impl Client {
     ///
     /// The name and reference parameters identify the image.
     /// The reference may be either a tag or digest.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = reference))
+    )]
     pub fn get_manifest_and_ref(
         &self,
         name: &str,
         reference: &str,
     ) -> Result<(Manifest, Option<String>)> {
         let url = self.build_url(name, reference)?;
+        let (body, content_type, content_digest) = self.get_raw_manifest(name, reference)?;
+
+        let header_content_type = header::HeaderValue::from_str(&content_type).ok();
+        let media_type = evaluate_media_type(header_content_type.as_ref(), &url)?;
+
+        trace!(
+            "content-type: {:?}, media-type: {:?}",
+            header_content_type,
+            media_type
+        );
+
+        let manifest = match media_type {
+            mediatypes::MediaTypes::ManifestV2S1Signed => {
+                serde_json::from_slice::<ManifestSchema1Signed>(&body).map(Manifest::S1Signed)?
+            }
+            mediatypes::MediaTypes::ManifestV2S2 => {
+                let m = serde_json::from_slice::<ManifestSchema2Spec>(&body)?;
+                m.fetch_config_blob(self.clone(), name.to_string())
+                    .map(Manifest::S2)?
+            }
+            mediatypes::MediaTypes::ManifestList => {
+                serde_json::from_slice::<ManifestList>(&body).map(Manifest::ML)?
+            }
+            unsupported => return Err(Error::UnsupportedMediaType(unsupported)),
+        };
 
-        let accept_headers = build_accept_headers(&self.index);
+        Ok((manifest, Some(content_digest)))
+    }
 
-        let client_spare0 = self.clone();
+    /// Fetch an image manifest without parsing it.
+    ///
+    /// Returns the manifest body exactly as sent by the registry, together with
+    /// the `Content-Type` it was served with and its canonical digest. Pushing
+    /// these bytes unchanged elsewhere preserves the manifest digest.
+    pub fn get_raw_manifest(
+        &self,
+        name: &str,
+        reference: &str,
+    ) -> Result<(Vec<u8>, String, String)> {
+        self.fetch_raw_manifest(name, reference, build_accept_headers(&self.index, false))
+    }
 
-        let res = self
-            .build_reqwest(reqwest::Method::GET, url.clone())
-            .headers(accept_headers)
-            .send()?;
+    /// Fetch an image manifest unless it still matches a previous `ETag`.
+    ///
+    /// Sends `If-None-Match` with the given ETag and returns `None` on
+    /// `304 Not Modified`. Otherwise the raw manifest is returned with its
+    /// new ETag, which is the quoted digest if the registry sends none.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = reference))
+    )]
+    pub fn get_manifest_if_modified(
+        &self,
+        name: &str,
+        reference: &str,
+        etag: Option<&str>,
+    ) -> Result<Option<(Vec<u8>, String)>> {
+        let (reference, expected) = parse_reference(reference)?;
+        let mut headers = build_accept_headers(&self.index, false);
+        headers.insert(header::ACCEPT_ENCODING, self.document_encoding());
+        if let Some(etag) = etag {
+            headers.insert(header::IF_NONE_MATCH, header::HeaderValue::from_str(etag)?);
+        }
 
+        let res = self.send_mirrored(
+            reqwest::Method::GET,
+            &format!("{}/manifests/{}", name, reference),
+            headers,
+        )?;
         let status = res.status();
         trace!("GET '{}' status: {:?}", res.url(), status);
 
         match status {
             StatusCode::OK => {}
-            _ => return Err(Error::UnexpectedHttpStatus(status)),
+            StatusCode::NOT_MODIFIED => return Ok(None),
+            StatusCode::NOT_FOUND => {
+                return Err(Error::NotFound {
+                    name: name.to_string(),
+                    reference: reference.to_string(),
+                })
+            }
+            _ => return Err(Error::from_response(&reqwest::Method::GET, res)),
+        }
+
+        let etag = match res.headers().get(header::ETAG) {
+            Some(etag) => Some(etag.to_str()?.to_string()),
+            None => None,
+        };
+        let body = crate::encoding::decoded_body(res)?;
+        let content_digest = crate::ContentDigest::from_bytes(&body);
+        verify_reference(expected, &content_digest)?;
+        let etag = etag.unwrap_or_else(|| format!("\"{}\"", content_digest));
+        Ok(Some((body, etag)))
+    }
+
+    /// Fetch an image manifest, resolving manifest lists to the host platform.
+    ///
+    /// The returned manifest is always an image manifest, never a list.
+    pub(crate) fn get_image_manifest(
+        &self,
+        name: &str,
+        reference: &str,
+    ) -> Result<ManifestSchema2Spec> {
+        let (mut body, mut content_type, _) =
+            self.fetch_raw_manifest(name, reference, build_accept_headers(&self.index, true))?;
+        let mut url = self.build_url(name, reference)?;
+
+        let header_content_type = header::HeaderValue::from_str(&content_type).ok();
+        if evaluate_media_type(header_content_type.as_ref(), &url)?
+            == mediatypes::MediaTypes::ManifestList
+        {
+            let list = serde_json::from_slice::<ManifestList>(&body)?;
+            let platform = Platform::host();
+            let entry = list
+                .find_platform(&platform)
+                .ok_or_else(|| ManifestError::PlatformNotFound(platform.to_string()))?;
+            trace!("resolved {}:{} to {}", name, reference, entry.digest);
+
+            let (b, ct, _) = self.fetch_raw_manifest(
+                name,
+                &entry.digest,
+                build_accept_headers(&self.index, false),
+            )?;
+            body = b;
+            content_type = ct;
+            url = self.build_url(name, &entry.digest)?;
+        }
+
+        let header_content_type = header::HeaderValue::from_str(&content_type).ok();
+        match evaluate_media_type(header_content_type.as_ref(), &url)? {
+            mediatypes::MediaTypes::ManifestV2S2 => Ok(serde_json::from_slice(&body)?),
+            unsupported => Err(Error::UnsupportedMediaType(unsupported)),
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = reference))
+    )]
+    pub(crate) fn fetch_raw_manifest(
+        &self,
+        name: &str,
+        reference: &str,
+        mut accept_headers: header::HeaderMap,
+    ) -> Result<(Vec<u8>, String, String)> {
+        let (reference, expected) = parse_reference(reference)?;
+        let url = self.build_url(name, reference)?;
+        accept_headers.insert(header::ACCEPT_ENCODING, self.document_encoding());
+
+        let res = self.send_mirrored(
+            reqwest::Method::GET,
+            &format!("{}/manifests/{}", name, reference),
+            accept_headers,
+        )?;
+
+        let status = res.status();
+        trace!("GET '{}' status: {:?}", res.url(), status);
+
+        match status {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => {
+                return Err(Error::NotFound {
+                    name: name.to_string(),
+                    reference: reference.to_string(),
+                })
+            }
+            _ => return Err(Error::from_response(&reqwest::Method::GET, res)),
         }
 
         let headers = res.headers();
-        let content_digest = match headers.get("docker-content-digest") {
+        let header_digest = match headers.get("docker-content-digest") {
             Some(content_digest_value) => Some(content_digest_value.to_str()?.to_string()),
             None => {
                 debug!("cannot find manifestref in headers");
@@ -60,35 +223,27 @@ impl Client {
             }
         };
 
-        let header_content_type = headers.get(header::CONTENT_TYPE);
-        let media_type = evaluate_media_type(header_content_type, &url)?;
-
-        trace!(
-            "content-type: {:?}, media-type: {:?}",
-            header_content_type,
-            media_type
-        );
+        let content_type = match headers.get(header::CONTENT_TYPE) {
+            Some(ct) => ct.to_str()?.to_string(),
+            // Pulp-based registries may omit the header, use the sniffed type instead
+            None => evaluate_media_type(None, &url)?.to_string(),
+        };
 
-        match media_type {
-            mediatypes::MediaTypes::ManifestV2S1Signed => Ok((
-                res.json::<ManifestSchema1Signed>()
-                    .map(Manifest::S1Signed)?,
-                content_digest,
-            )),
-            mediatypes::MediaTypes::ManifestV2S2 => {
-                let m = res.json::<ManifestSchema2Spec>()?;
-                Ok((
-                    m.fetch_config_blob(client_spare0, name.to_string())
-                        .map(Manifest::S2)?,
-                    content_digest,
-                ))
+        let body = crate::encoding::decoded_body(res)?;
+        let content_digest = crate::ContentDigest::from_bytes(&body);
+        verify_reference(expected, &content_digest)?;
+        let content_digest = content_digest.to_string();
+
+        if let Some(header_digest) = header_digest {
+            if header_digest != content_digest {
+                debug!(
+                    "registry reported digest {} but content hashes to {}",
+                    header_digest, content_digest
+                );
             }
-            mediatypes::MediaTypes::ManifestList => Ok((
-                res.json::<ManifestList>().map(Manifest::ML)?,
-                content_digest,
-            )),
-            unsupported => Err(Error::UnsupportedMediaType(unsupported)),
         }
+
+        Ok((body, content_type, content_digest))
     }
 
     fn build_url(&self, name: &str, reference: &str) -> Result<Url> {
@@ -102,22 +257,35 @@ impl Client {
     }
 
     /// Fetch content digest for a particular tag.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = reference))
+    )]
     pub fn get_manifestref(&self, name: &str, reference: &str) -> Result<Option<String>> {
-        let url = self.build_url(name, reference)?;
-
-        let accept_headers = build_accept_headers(&self.index);
+        let (reference, _) = parse_reference(reference)?;
+        let accept_headers = build_accept_headers(&self.index, false);
 
-        let res = self
-            .build_reqwest(reqwest::Method::HEAD, url)
-            .headers(accept_headers)
-            .send()?;
+        let res = self.send_mirrored(
+            reqwest::Method::HEAD,
+            &format!("{}/manifests/{}", name, reference),
+            accept_headers,
+        )?;
 
         let status = res.status();
         trace!("HEAD '{}' status: {:?}", res.url(), status);
 
         match status {
             StatusCode::OK => {}
-            _ => return Err(Error::UnexpectedHttpStatus(status)),
+            StatusCode::NOT_FOUND => {
+                return Err(Error::NotFound {
+                    name: name.to_string(),
+                    reference: reference.to_string(),
+                })
+            }
+            _ => {
+                return Err(Error::from_status(status, res.headers())
+                    .with_request(&reqwest::Method::HEAD, res.url()))
+            }
         }
 
         let headers = res.headers();
@@ -131,16 +299,72 @@ impl Client {
         Ok(content_digest)
     }
 
+    /// Delete the manifest a tag points to.
+    ///
+    /// Most registries reject deleting by tag, so the tag is first resolved
+    /// to its digest with a `HEAD` request, then the manifest is deleted by
+    /// digest. This removes the manifest itself: any other tag pointing to
+    /// the same digest is untagged as well.
+    ///
+    /// Registries which don't allow deletion, like a `distribution` registry
+    /// without `REGISTRY_STORAGE_DELETE_ENABLED`, result in
+    /// `Error::DeleteNotSupported`.
+    pub fn delete_tag(&self, name: &str, tag: &str) -> Result<()> {
+        let url = self.build_url(name, tag)?;
+        let res = self.send_reqwest(
+            self.build_reqwest(reqwest::Method::HEAD, url)
+                .headers(build_accept_headers(&self.index, true)),
+        )?;
+        let status = res.status();
+        trace!("HEAD '{}' status: {:?}", res.url(), status);
+        match status {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => {
+                return Err(Error::NotFound {
+                    name: name.to_string(),
+                    reference: tag.to_string(),
+                })
+            }
+            _ => {
+                return Err(Error::from_status(status, res.headers())
+                    .with_request(&reqwest::Method::HEAD, res.url()))
+            }
+        }
+        let digest = match res.headers().get("docker-content-digest") {
+            Some(digest) => crate::ContentDigest::try_new(digest.to_str()?.to_string())?,
+            None => return Err(Error::MissingHeader("Docker-Content-Digest".to_string())),
+        };
+
+        let url = self.build_url(name, digest.as_ref())?;
+        let res = self.send_reqwest(self.build_reqwest(reqwest::Method::DELETE, url))?;
+        let status = res.status();
+        trace!("DELETE '{}' status: {:?}", res.url(), status);
+        match status {
+            StatusCode::ACCEPTED | StatusCode::OK => Ok(()),
+            StatusCode::NOT_FOUND => Err(Error::NotFound {
+                name: name.to_string(),
+                reference: digest.to_string(),
+            }),
+            StatusCode::METHOD_NOT_ALLOWED => Err(Error::DeleteNotSupported),
+            _ => Err(Error::from_response(&reqwest::Method::DELETE, res)),
+        }
+    }
+
     /// Check if an image manifest exists.
     ///
     /// The name and reference parameters identify the image.
     /// The reference may be either a tag or digest.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = reference))
+    )]
     pub fn has_manifest(
         &self,
         name: &str,
         reference: &str,
         mediatypes: Option<&[&str]>,
     ) -> Result<Option<mediatypes::MediaTypes>> {
+        let (reference, _) = parse_reference(reference)?;
         let url = self.build_url(name, reference)?;
         let accept_types = match mediatypes {
             None => {
@@ -152,18 +376,18 @@ impl Client {
 
         let mut accept_headers = header::HeaderMap::with_capacity(accept_types.len());
         for accept_type in accept_types {
-            let header_value = header::HeaderValue::from_str(&accept_type.to_string())
+            let header_value = header::HeaderValue::from_str(accept_type.as_ref())
                 .expect("mime type is always valid header value");
             accept_headers.insert(header::ACCEPT, header_value);
         }
 
         trace!("HEAD {:?}", url);
 
-        let r = self
-            .build_reqwest(reqwest::Method::HEAD, url)
-            .headers(accept_headers)
-            .send()
-            .map_err(Error::from)?;
+        let r = self.send_mirrored(
+            reqwest::Method::HEAD,
+            &format!("{}/manifests/{}", name, reference),
+            accept_headers,
+        )?;
 
         let status = r.status();
 
@@ -184,11 +408,41 @@ impl Client {
                 Ok(Some(media_type))
             }
             StatusCode::NOT_FOUND => Ok(None),
-            _ => Err(Error::UnexpectedHttpStatus(status)),
+            _ => Err(Error::from_status(status, r.headers())
+                .with_request(&reqwest::Method::HEAD, r.url())),
         }
     }
 }
 
+/// Split a manifest reference into its path segment and, for digest
+/// references, the digest the content must match.
+///
+/// Digest references may keep the `@` of `name@sha256:...`. Tags can't
+/// contain a colon, so anything with one must be a valid digest.
+fn parse_reference(reference: &str) -> Result<(&str, Option<crate::ContentDigest>)> {
+    let reference = reference.strip_prefix('@').unwrap_or(reference);
+    if !reference.contains(':') {
+        return Ok((reference, None));
+    }
+    let digest = crate::ContentDigest::try_new(reference.to_string())?;
+    Ok((reference, Some(digest)))
+}
+
+/// Check content fetched by a digest reference against that digest.
+fn verify_reference(
+    expected: Option<crate::ContentDigest>,
+    got: &crate::ContentDigest,
+) -> Result<()> {
+    match expected {
+        Some(expected) if &expected != got => Err(crate::ContentDigestError::Verify {
+            expected,
+            got: got.clone(),
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
 fn to_mimes(v: &[&str]) -> Vec<mime::Mime> {
     let res = v
         .iter()
@@ -245,27 +499,29 @@ fn evaluate_media_type(
     }
 }
 
-fn build_accept_headers(registry: &str) -> header::HeaderMap {
+fn build_accept_headers(registry: &str, manifest_list: bool) -> header::HeaderMap {
     // GCR incorrectly parses `q` parameters, so we use special Accept for it.
     // Bug: https://issuetracker.google.com/issues/159827510.
     // TODO: when bug is fixed, this workaround should be removed.
     let no_q = registry == "gcr.io" || registry.ends_with(".gcr.io");
 
-    let accepted_types = vec![
+    let mut accepted_types = vec![
         // accept header types and their q value, as documented in
         // https://tools.ietf.org/html/rfc7231#section-5.3.2
         (mediatypes::MediaTypes::ManifestV2S2, 0.5),
         (mediatypes::MediaTypes::ManifestV2S1Signed, 0.4),
-        // TODO: uncomment this when all the Manifest methods work for it
-        // mediatypes::MediaTypes::ManifestList,
     ];
+    // TODO: always accept lists when all the Manifest methods work for it
+    if manifest_list {
+        accepted_types.insert(0, (mediatypes::MediaTypes::ManifestList, 0.6));
+    }
 
     let accepted_types_string = accepted_types
         .into_iter()
         .map(|(ty, q)| {
             format!(
                 "{}{}",
-                ty.to_string(),
+                ty,
                 if no_q {
                     String::default()
                 } else {
@@ -304,6 +560,10 @@ pub enum ManifestError {
     LayerSizeUnsupported(String),
     #[error("manifest {0} does not support the 'architecture' method")]
     ArchitectureNotSupported(String),
+    #[error("manifest {0} does not support the 'save_image' method")]
+    SaveUnsupported(String),
+    #[error("no manifest for platform {0}")]
+    PlatformNotFound(String),
 }
 
 impl Manifest {
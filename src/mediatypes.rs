@@ -33,10 +33,50 @@ pub enum MediaTypes {
     #[strum(serialize = "application/vnd.docker.image.rootfs.diff.tar.gzip")]
     #[strum(props(Sub = "vnd.docker.image.rootfs.diff.tar.gzip"))]
     ImageLayerTgz,
+    /// Foreign image layer, as a gzip-compressed tar served from its `urls`.
+    #[strum(serialize = "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip")]
+    #[strum(props(Sub = "vnd.docker.image.rootfs.foreign.diff.tar.gzip"))]
+    ImageLayerForeignTgz,
+    /// Image layer, as an uncompressed tar.
+    #[strum(serialize = "application/vnd.docker.image.rootfs.diff.tar")]
+    #[strum(props(Sub = "vnd.docker.image.rootfs.diff.tar"))]
+    ImageLayerTar,
     /// Configuration object for a container.
     #[strum(serialize = "application/vnd.docker.container.image.v1+json")]
     #[strum(props(Sub = "vnd.docker.container.image.v1+json"))]
     ContainerConfigV1,
+    /// OCI image manifest.
+    #[strum(serialize = "application/vnd.oci.image.manifest.v1+json")]
+    #[strum(props(Sub = "vnd.oci.image.manifest.v1+json"))]
+    OciImageManifest,
+    /// OCI image index, the OCI equivalent of a manifest list.
+    #[strum(serialize = "application/vnd.oci.image.index.v1+json")]
+    #[strum(props(Sub = "vnd.oci.image.index.v1+json"))]
+    OciImageIndex,
+    /// OCI image configuration.
+    #[strum(serialize = "application/vnd.oci.image.config.v1+json")]
+    #[strum(props(Sub = "vnd.oci.image.config.v1+json"))]
+    OciImageConfig,
+    /// OCI image layer, as an uncompressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.v1.tar")]
+    #[strum(props(Sub = "vnd.oci.image.layer.v1.tar"))]
+    OciImageLayerTar,
+    /// OCI image layer, as a gzip-compressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.v1.tar+gzip")]
+    #[strum(props(Sub = "vnd.oci.image.layer.v1.tar+gzip"))]
+    OciImageLayerTgz,
+    /// OCI image layer, as a zstd-compressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.v1.tar+zstd")]
+    #[strum(props(Sub = "vnd.oci.image.layer.v1.tar+zstd"))]
+    OciImageLayerTzst,
+    /// Non-distributable OCI image layer, as a gzip-compressed tar.
+    #[strum(serialize = "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip")]
+    #[strum(props(Sub = "vnd.oci.image.layer.nondistributable.v1.tar+gzip"))]
+    OciImageLayerNondistributableTgz,
+    /// OCI empty descriptor, used as config of artifacts.
+    #[strum(serialize = "application/vnd.oci.empty.v1+json")]
+    #[strum(props(Sub = "vnd.oci.empty.v1+json"))]
+    OciEmpty,
     /// Generic JSON
     #[strum(serialize = "application/json")]
     #[strum(props(Sub = "json"))]
@@ -44,29 +84,45 @@ pub enum MediaTypes {
 }
 
 impl MediaTypes {
-    // TODO: proper error types
     pub fn from_mime(mtype: &mime::Mime) -> Result<Self> {
         match (mtype.type_(), mtype.subtype(), mtype.suffix()) {
             (mime::APPLICATION, mime::JSON, _) => Ok(MediaTypes::ApplicationJson),
-            (mime::APPLICATION, subt, Some(suff)) => {
-                match (subt.to_string().as_str(), suff.to_string().as_str()) {
-                    ("vnd.docker.distribution.manifest.v1", "json") => Ok(MediaTypes::ManifestV2S1),
-                    ("vnd.docker.distribution.manifest.v1", "prettyjws") => {
-                        Ok(MediaTypes::ManifestV2S1Signed)
-                    }
-                    ("vnd.docker.distribution.manifest.v2", "json") => Ok(MediaTypes::ManifestV2S2),
-                    ("vnd.docker.distribution.manifest.list.v2", "json") => {
-                        Ok(MediaTypes::ManifestList)
-                    }
-                    ("vnd.docker.image.rootfs.diff.tar.gzip", _) => Ok(MediaTypes::ImageLayerTgz),
-                    ("vnd.docker.container.image.v1", "json") => Ok(MediaTypes::ContainerConfigV1),
-                    _ => Err(crate::Error::UnknownMimeType(mtype.clone())),
-                }
+            (mime::APPLICATION, _, _) => {
+                // Parameters like the charset don't change the media type
+                std::str::FromStr::from_str(mtype.essence_str())
+                    .map_err(|_| crate::Error::UnknownMimeType(mtype.clone()))
             }
             _ => Err(crate::Error::UnknownMimeType(mtype.clone())),
         }
     }
 
+    /// Whether this is a list of manifests for several platforms.
+    pub fn is_manifest_list(&self) -> bool {
+        matches!(self, MediaTypes::ManifestList | MediaTypes::OciImageIndex)
+    }
+
+    /// Whether this is the media type of an image layer.
+    pub fn is_layer(&self) -> bool {
+        matches!(
+            self,
+            MediaTypes::ImageLayerTgz
+                | MediaTypes::ImageLayerForeignTgz
+                | MediaTypes::ImageLayerTar
+                | MediaTypes::OciImageLayerTar
+                | MediaTypes::OciImageLayerTgz
+                | MediaTypes::OciImageLayerTzst
+                | MediaTypes::OciImageLayerNondistributableTgz
+        )
+    }
+
+    /// Whether this is a layer which registries may not serve, like Windows base layers.
+    pub fn is_foreign_layer(&self) -> bool {
+        matches!(
+            self,
+            MediaTypes::ImageLayerForeignTgz | MediaTypes::OciImageLayerNondistributableTgz
+        )
+    }
+
     pub fn to_mime(&self) -> mime::Mime {
         match self {
             &MediaTypes::ApplicationJson => Ok(mime::APPLICATION_JSON),
@@ -81,3 +137,41 @@ impl MediaTypes {
         .expect("to_mime should be always successful")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use test_case::test_case;
+
+    #[test_case(MediaTypes::ManifestV2S2; "docker manifest")]
+    #[test_case(MediaTypes::ManifestList; "docker manifest list")]
+    #[test_case(MediaTypes::ImageLayerTar; "docker uncompressed layer")]
+    #[test_case(MediaTypes::OciImageManifest; "oci manifest")]
+    #[test_case(MediaTypes::OciImageIndex; "oci index")]
+    #[test_case(MediaTypes::OciImageLayerTgz; "oci gzip layer")]
+    #[test_case(MediaTypes::OciImageLayerTzst; "oci zstd layer")]
+    #[test_case(MediaTypes::OciEmpty; "oci empty")]
+    #[test_case(MediaTypes::ImageLayerForeignTgz; "docker foreign layer")]
+    #[test_case(MediaTypes::OciImageLayerNondistributableTgz; "oci nondistributable layer")]
+    fn media_type_round_trips(media_type: MediaTypes) {
+        let mime = media_type.to_mime();
+        assert_eq!(mime.essence_str(), media_type.to_string());
+        assert_eq!(MediaTypes::from_mime(&mime).unwrap(), media_type);
+        assert_eq!(
+            MediaTypes::from_str(&media_type.to_string()).unwrap(),
+            media_type
+        );
+    }
+
+    #[test]
+    fn from_mime_ignores_parameters() {
+        let mime = "application/vnd.oci.image.index.v1+json; charset=utf-8"
+            .parse()
+            .unwrap();
+        let media_type = MediaTypes::from_mime(&mime).unwrap();
+        assert!(media_type.is_manifest_list());
+        assert!(!media_type.is_layer());
+        assert!(MediaTypes::OciImageLayerTzst.is_layer());
+    }
+}
@@ -0,0 +1,73 @@
+//! Pull-through mirrors tried before the registry itself.
+
+use crate::errors::Result;
+use crate::Client;
+use reqwest::blocking::Response;
+use reqwest::header::HeaderMap;
+use reqwest::Method;
+
+/// Turn a mirror from the configuration into a base URL.
+///
+/// Mirrors without a scheme use HTTPS.
+pub(crate) fn mirror_base_url(mirror: &str) -> String {
+    let mirror = mirror.trim_end_matches('/');
+    if mirror.contains("://") {
+        mirror.to_string()
+    } else {
+        format!("https://{}", mirror)
+    }
+}
+
+impl Client {
+    /// Send a request for registry content, asking the mirrors first.
+    ///
+    /// `path` is relative to the `/v2/` endpoint, like `<name>/blobs/<digest>`.
+    /// Mirrors don't get the registry's credentials. Any response other than
+    /// a success, and any transport error, moves on to the next mirror and
+    /// finally to the registry.
+    pub(crate) fn send_mirrored(
+        &self,
+        method: Method,
+        path: &str,
+        headers: HeaderMap,
+    ) -> Result<Response> {
+        for mirror in &self.mirrors {
+            let url = reqwest::Url::parse(&format!("{}/v2/{}", mirror, path))?;
+            let mut builder = self
+                .client
+                .request(method.clone(), url)
+                .headers(headers.clone());
+            if let Some(ua) = &self.user_agent {
+                builder = builder.header(reqwest::header::USER_AGENT, ua.as_str());
+            }
+            match self.execute(builder.build()?) {
+                Ok(res) if res.status().is_success() => {
+                    trace!("{} {} served by mirror", method, res.url());
+                    return Ok(res);
+                }
+                Ok(res) => debug!("mirror {} returned {} for {}", mirror, res.status(), path),
+                Err(e) => debug!("mirror {} failed for {}: {}", mirror, path, e),
+            }
+        }
+
+        let url = reqwest::Url::parse(&format!("{}/v2/{}", self.base_url, path))?;
+        self.send_reqwest(self.build_reqwest(method, url).headers(headers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirror_urls() {
+        assert_eq!(
+            mirror_base_url("mirror.local:5000"),
+            "https://mirror.local:5000"
+        );
+        assert_eq!(
+            mirror_base_url("http://mirror.local/"),
+            "http://mirror.local"
+        );
+    }
+}
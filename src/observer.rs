@@ -0,0 +1,55 @@
+//! Hooks for recording metrics about registry requests.
+
+use crate::errors::{Error, Result};
+use crate::Client;
+use reqwest::blocking::{Request, Response};
+use reqwest::{Method, StatusCode, Url};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Receives a notification for every HTTP request made by a `Client`.
+///
+/// Set with `Config::observer`. This covers registry and token endpoint
+/// requests, including retries after a token refresh.
+pub trait Observer: Send + Sync {
+    /// Called before a request is sent.
+    fn on_request(&self, _method: &Method, _url: &Url) {}
+
+    /// Called when the response headers arrived.
+    ///
+    /// `bytes` is the `Content-Length` of the response, if announced, and
+    /// `elapsed` the time since the request was sent. Requests failing
+    /// without a response are not reported here.
+    fn on_response(&self, _status: StatusCode, _bytes: Option<u64>, _elapsed: Duration) {}
+}
+
+impl fmt::Debug for dyn Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+impl Client {
+    /// Send a request, reporting it to the observer if one is set.
+    pub(crate) fn execute(&self, request: Request) -> Result<Response> {
+        let method = request.method().clone();
+        let url = request.url().clone();
+        let failed = |e: reqwest::Error| {
+            Error::from_transport(e.without_url(), &url).with_request(&method, &url)
+        };
+        let observer = match &self.observer {
+            Some(observer) => observer,
+            None => return self.client.execute(request).map_err(failed),
+        };
+
+        observer.on_request(&method, &url);
+        let start = Instant::now();
+        let response = self.client.execute(request).map_err(failed)?;
+        observer.on_response(
+            response.status(),
+            response.content_length(),
+            start.elapsed(),
+        );
+        Ok(response)
+    }
+}
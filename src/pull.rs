@@ -0,0 +1,184 @@
+//! Download images.
+
+use crate::blobs::ProgressReader;
+use crate::errors::{Error, Result};
+use crate::manifest::{Descriptor, ImageConfig};
+use crate::render::{self, UnpackOptions};
+use crate::{Client, ContentDigest, DigestReader};
+use reqwest::blocking::Response;
+use reqwest::Method;
+use std::io::Read;
+use std::iter;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+/// Download size of an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImageSize {
+    /// Size of the config and all layers as stored in the registry.
+    pub total_compressed_bytes: u64,
+    /// Number of layers.
+    pub layer_count: usize,
+}
+
+impl Client {
+    /// Compute the download size of an image from its manifest.
+    ///
+    /// Manifest lists are resolved to the host platform, like for `pull_image`.
+    pub fn image_size(&self, name: &str, reference: &str) -> Result<ImageSize> {
+        let manifest = self.get_image_manifest(name, reference)?;
+        let layers = manifest.get_layers();
+
+        Ok(ImageSize {
+            total_compressed_bytes: manifest.config().size
+                + layers.iter().map(|(_, size)| size).sum::<u64>(),
+            layer_count: layers.len(),
+        })
+    }
+
+    /// List the layers of an image without downloading them.
+    ///
+    /// Manifest lists are resolved to the host platform, like for
+    /// `pull_image`. Layers are returned lowest first.
+    pub fn get_layer_descriptors(&self, name: &str, reference: &str) -> Result<Vec<Descriptor>> {
+        Ok(self.get_image_manifest(name, reference)?.layers().to_vec())
+    }
+
+    /// Download the config and all layers of an image.
+    ///
+    /// Manifest lists are resolved to the host platform. Layers are returned
+    /// lowest first, ready to be passed to `render::unpack`.
+    ///
+    /// Foreign layers, which registries don't serve, are downloaded from the
+    /// URLs of their descriptor. `Error::NonDistributableLayer` is returned
+    /// when none of them works.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = reference))
+    )]
+    pub fn pull_image(&self, name: &str, reference: &str) -> Result<(ImageConfig, Vec<Vec<u8>>)> {
+        let manifest = self.get_image_manifest(name, reference)?;
+        let config = self.get_image_config(name, &manifest.config().digest)?;
+
+        let layers = manifest
+            .layers()
+            .iter()
+            .map(|layer| self.get_layer(name, layer))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok((config, layers))
+    }
+
+    /// Download an image and unpack its layers into `target_dir`.
+    ///
+    /// Unlike `pull_image` followed by `render::unpack`, layers are never
+    /// held in memory: each one is decompressed and extracted as it is
+    /// received, and checked against its digest once fully read. A layer
+    /// failing verification has already been extracted by then, so the
+    /// target should be discarded on error.
+    ///
+    /// The number of compressed bytes received is sent to `progress`, like
+    /// for `get_blob_with_progress`. Returns the config of the image.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, reference = reference))
+    )]
+    pub fn pull_and_unpack(
+        &self,
+        name: &str,
+        reference: &str,
+        target_dir: &Path,
+        progress: Option<Sender<u64>>,
+    ) -> Result<ImageConfig> {
+        let manifest = self.get_image_manifest(name, reference)?;
+        let config = self.get_image_config(name, &manifest.config().digest)?;
+
+        let options = UnpackOptions::default();
+        for layer in manifest.layers() {
+            let digest = ContentDigest::try_new(layer.digest.clone())?;
+            let reader = ProgressReader::new(self.open_layer(name, layer)?, progress.clone());
+            let mut reader = DigestReader::new(reader, digest);
+            render::unpack_stream_with_options(iter::once(&mut reader), target_dir, &options)
+                .map_err(|source| Error::Unpack {
+                    digest: layer.digest.clone(),
+                    source,
+                })?;
+            reader.verify()?;
+        }
+
+        Ok(config)
+    }
+
+    /// Download a layer from the registry, or from its URLs for foreign layers.
+    fn get_layer(&self, name: &str, layer: &Descriptor) -> Result<Vec<u8>> {
+        if !layer.is_foreign() {
+            return self.get_blob(name, &layer.digest);
+        }
+
+        let urls = layer.urls.clone().unwrap_or_default();
+        let digest = ContentDigest::try_new(layer.digest.clone())?;
+        for url in &urls {
+            match self.get_foreign_layer(url, &digest) {
+                Ok(blob) => return Ok(blob),
+                Err(e) => warn!("failed to download foreign layer {}: {}", digest, e),
+            }
+        }
+        Err(Error::NonDistributableLayer {
+            digest: layer.digest.clone(),
+            urls,
+        })
+    }
+
+    /// Open a layer for reading as it is received, without verifying it.
+    ///
+    /// Foreign layers are opened from the first of their URLs which responds.
+    fn open_layer(&self, name: &str, layer: &Descriptor) -> Result<Box<dyn Read + Send>> {
+        if !layer.is_foreign() {
+            let digest = ContentDigest::try_new(layer.digest.clone())?;
+            return self.open_blob(name, &digest);
+        }
+
+        let urls = layer.urls.clone().unwrap_or_default();
+        for url in &urls {
+            match self.open_foreign_layer(url) {
+                Ok(res) => return Ok(Box::new(res)),
+                Err(e) => warn!("failed to download foreign layer {}: {}", layer.digest, e),
+            }
+        }
+        Err(Error::NonDistributableLayer {
+            digest: layer.digest.clone(),
+            urls,
+        })
+    }
+
+    /// Download a foreign layer from one of its URLs.
+    fn get_foreign_layer(&self, url: &str, digest: &ContentDigest) -> Result<Vec<u8>> {
+        let res = self.open_foreign_layer(url)?;
+        let mut reader = DigestReader::new(res, digest.clone());
+        let mut blob = Vec::new();
+        reader.read_to_end(&mut blob)?;
+        reader.verify()?;
+        Ok(blob)
+    }
+
+    /// Send a `GET` request for a foreign layer.
+    ///
+    /// The URLs point outside of the registry, so no credentials are sent.
+    fn open_foreign_layer(&self, url: &str) -> Result<Response> {
+        let url = reqwest::Url::parse(url)?;
+        let mut builder = self.client.request(Method::GET, url);
+        if let Some(ua) = &self.user_agent {
+            builder = builder.header(reqwest::header::USER_AGENT, ua.as_str());
+        }
+        let res = self.execute(builder.build()?)?;
+        trace!(
+            "GET foreign layer '{}' status: {:?}",
+            res.url(),
+            res.status()
+        );
+        if !res.status().is_success() {
+            return Err(Error::from_response(&Method::GET, res));
+        }
+        Ok(res)
+    }
+}
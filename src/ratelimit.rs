@@ -0,0 +1,103 @@
+//! Rate limit information reported by registries.
+
+use crate::errors::{Error, Result};
+use crate::Client;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use std::time::{Duration, SystemTime};
+
+/// Quota reported by the `ratelimit-*` headers of Docker Hub.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Number of pulls allowed in the current window.
+    pub limit: Option<u64>,
+    /// Number of pulls left in the current window.
+    pub remaining: Option<u64>,
+    /// How long to wait before sending more requests, from `Retry-After`.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Read the rate limit headers of a response.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        RateLimit {
+            limit: quota(headers, "ratelimit-limit"),
+            remaining: quota(headers, "ratelimit-remaining"),
+            retry_after: retry_after(headers),
+        }
+    }
+}
+
+/// Parse a quota header like `100;w=21600`, ignoring the window.
+fn quota(headers: &HeaderMap, name: &str) -> Option<u64> {
+    let value = headers.get(name)?.to_str().ok()?;
+    value.split(';').next()?.trim().parse().ok()
+}
+
+/// Parse `Retry-After`, given either in seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+impl Client {
+    /// Read the pull quota of the client without consuming a pull.
+    ///
+    /// Docker Hub reports the quota on `HEAD` requests for manifests, which
+    /// don't count as pulls. `name` is any repository the client may pull,
+    /// Docker suggests `ratelimitpreview/test`. Registries without rate
+    /// limits return an empty `RateLimit`.
+    pub fn rate_limit_status(&self, name: &str) -> Result<RateLimit> {
+        let url = reqwest::Url::parse(&format!("{}/v2/{}/manifests/latest", self.base_url, name))?;
+        let res = self.send_reqwest(self.build_reqwest(Method::HEAD, url))?;
+        trace!("HEAD '{}' status: {:?}", res.url(), res.status());
+
+        match res.status() {
+            status if status.is_success() || status == StatusCode::TOO_MANY_REQUESTS => {
+                Ok(RateLimit::from_headers(res.headers()))
+            }
+            status => {
+                Err(Error::from_status(status, res.headers())
+                    .with_request(&Method::HEAD, res.url()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use test_case::test_case;
+
+    #[test_case(&[("ratelimit-limit", "100;w=21600"), ("ratelimit-remaining", "76;w=21600")], Some(100), Some(76), None; "docker hub")]
+    #[test_case(&[("retry-after", "120")], None, None, Some(Duration::from_secs(120)); "retry after seconds")]
+    #[test_case(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")], None, None, Some(Duration::ZERO); "retry after past date")]
+    #[test_case(&[("ratelimit-limit", "lots"), ("retry-after", "soon")], None, None, None; "malformed")]
+    fn parse_headers(
+        headers: &[(&'static str, &'static str)],
+        limit: Option<u64>,
+        remaining: Option<u64>,
+        retry_after: Option<Duration>,
+    ) {
+        let headers = headers
+            .iter()
+            .map(|(k, v)| (k.parse().unwrap(), HeaderValue::from_static(v)))
+            .collect();
+        assert_eq!(
+            RateLimit::from_headers(&headers),
+            RateLimit {
+                limit,
+                remaining,
+                retry_after
+            }
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Discovery of artifacts, like signatures and SBOMs, referring to an image.
+
+use crate::errors::{Error, Result};
+use crate::manifest::{Descriptor, ImageIndex};
+use crate::mediatypes::MediaTypes;
+use crate::Client;
+use reqwest::{self, header, StatusCode, Url};
+use std::iter::FromIterator;
+
+/// Tag suffixes used by cosign for artifacts attached to an image.
+const COSIGN_TAG_SUFFIXES: &[&str] = &["sig", "att", "sbom"];
+
+/// Subset of an image manifest used to describe an artifact.
+#[derive(Debug, Default, Deserialize)]
+struct ArtifactManifest {
+    #[serde(rename = "mediaType")]
+    media_type: Option<String>,
+    #[serde(rename = "artifactType")]
+    artifact_type: Option<String>,
+    config: Option<Descriptor>,
+    annotations: Option<std::collections::HashMap<String, String>>,
+}
+
+impl Client {
+    /// List the manifests referring to the manifest with the given digest.
+    ///
+    /// Registries which don't implement the referrers API are queried with
+    /// the tag schema instead: the `<alg>-<hex>` index tag and the cosign
+    /// `<alg>-<hex>.sig`, `.att` and `.sbom` tags. If `artifact_type` is set,
+    /// only referrers of that type are returned.
+    pub fn get_referrers(
+        &self,
+        name: &str,
+        digest: &str,
+        artifact_type: Option<&str>,
+    ) -> Result<Vec<Descriptor>> {
+        let digest = crate::ContentDigest::try_new(digest.to_string())?;
+        let mut url = Url::parse(&format!(
+            "{}/v2/{}/referrers/{}",
+            self.base_url, name, digest
+        ))?;
+        if let Some(artifact_type) = artifact_type {
+            url.query_pairs_mut()
+                .append_pair("artifactType", artifact_type);
+        }
+
+        let resp = self.send_reqwest(
+            self.build_reqwest(reqwest::Method::GET, url)
+                .header(header::ACCEPT, MediaTypes::OciImageIndex.to_string()),
+        )?;
+
+        let status = resp.status();
+        trace!("GET '{}' status: {:?}", resp.url(), status);
+        let referrers = match status {
+            StatusCode::OK => {
+                let filtered = resp.headers().contains_key("OCI-Filters-Applied");
+                let index = resp.json::<ImageIndex>()?;
+                if filtered {
+                    return Ok(index.manifests);
+                }
+                index.manifests
+            }
+            StatusCode::NOT_FOUND => {
+                debug!("referrers API not supported, falling back to the tag schema");
+                self.get_referrers_by_tag(name, &digest)?
+            }
+            _ => return Err(Error::from_response(&reqwest::Method::GET, resp)),
+        };
+
+        Ok(referrers
+            .into_iter()
+            .filter(|r| artifact_type.is_none() || r.artifact_type.as_deref() == artifact_type)
+            .collect())
+    }
+
+    /// Collect referrers published with the tag schema.
+    fn get_referrers_by_tag(
+        &self,
+        name: &str,
+        digest: &crate::ContentDigest,
+    ) -> Result<Vec<Descriptor>> {
+        let tag = digest.to_string().replace(':', "-");
+        let mut referrers = match self.get_artifact_manifest(name, &tag)? {
+            Some((body, _)) => serde_json::from_slice::<ImageIndex>(&body)?.manifests,
+            None => Vec::new(),
+        };
+
+        for suffix in COSIGN_TAG_SUFFIXES {
+            let tag = format!("{}.{}", tag, suffix);
+            if let Some((body, content_type)) = self.get_artifact_manifest(name, &tag)? {
+                let manifest = serde_json::from_slice::<ArtifactManifest>(&body)?;
+                let config = manifest.config;
+                referrers.push(Descriptor {
+                    media_type: manifest.media_type.unwrap_or(content_type),
+                    digest: crate::ContentDigest::from_bytes(&body).to_string(),
+                    size: body.len() as u64,
+                    artifact_type: manifest
+                        .artifact_type
+                        .or_else(|| config.map(|c| c.media_type)),
+                    urls: None,
+                    annotations: manifest.annotations,
+                });
+            }
+        }
+
+        Ok(referrers)
+    }
+
+    /// Fetch a manifest of an artifact, returning `None` if the tag doesn't exist.
+    fn get_artifact_manifest(&self, name: &str, tag: &str) -> Result<Option<(Vec<u8>, String)>> {
+        let accept = [
+            MediaTypes::OciImageIndex,
+            MediaTypes::OciImageManifest,
+            MediaTypes::ManifestV2S2,
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+        let accept_headers = header::HeaderMap::from_iter(vec![(
+            header::ACCEPT,
+            header::HeaderValue::from_str(&accept).expect("media types are valid header values"),
+        )]);
+
+        match self.fetch_raw_manifest(name, tag, accept_headers) {
+            Ok((body, content_type, _)) => Ok(Some((body, content_type))),
+            Err(Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
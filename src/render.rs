@@ -3,135 +3,1547 @@
 // Docker image format is specified at
 // https://github.com/moby/moby/blob/v17.05.0-ce/image/spec/v1.md
 
+use crate::{ContentDigest, ContentDigestError, Hasher};
 use libflate::gzip;
-use std::io::{BufReader, Read};
+use ruzstd::decoding::errors::{FrameDecoderError, ReadFrameHeaderError};
+use ruzstd::decoding::{BlockDecodingStrategy, FrameDecoder};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
 use std::{fs, path};
 use tar;
 
+mod extract;
+mod flatten;
+mod list;
+pub use self::extract::extract_paths;
+pub use self::flatten::flatten_to_tar;
+use self::list::entry_info;
+pub use self::list::{
+    list_entries, list_entries_stream, list_image_entries, list_image_entries_stream, EntryInfo,
+    EntryType,
+};
+
 #[derive(Debug, thiserror::Error)]
 pub enum RenderError {
     #[error("wrong target path {}: must be absolute path to existing directory", _0.display())]
     WrongTargetPath(path::PathBuf),
     #[error("io error")]
     Io(#[from] std::io::Error),
+    #[error("symlink {} points outside of the target directory: {}", path.display(), target.display())]
+    SymlinkEscape {
+        path: path::PathBuf,
+        target: path::PathBuf,
+    },
+    #[error("entry {} points outside of the target directory", _0.display())]
+    PathEscape(path::PathBuf),
+    #[error("entry {} already exists in the target directory", _0.display())]
+    AlreadyExists(path::PathBuf),
+    #[error("hardlink {} points at {}, which is not a file of the layers", path.display(), target.display())]
+    HardlinkTarget {
+        path: path::PathBuf,
+        target: path::PathBuf,
+    },
+    #[error("unable to set the owner of {}", path.display())]
+    Ownership {
+        path: path::PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid diff_id")]
+    DiffId(#[from] ContentDigestError),
+    #[error("got {diff_ids} diff_ids for {layers} layers")]
+    DiffIdCount { layers: usize, diff_ids: usize },
+    #[error("layer {layer} has diff_id {got}, expected {expected}")]
+    DiffIdMismatch {
+        layer: usize,
+        expected: String,
+        got: String,
+    },
+}
+
+/// Options controlling how layers are extracted.
+///
+/// The defaults preserve permissions and extended attributes and overwrite
+/// existing paths, which usually requires running as root. Ownership is not
+/// preserved, everything belongs to the user unpacking. Entries which would
+/// be written outside of the target directory are an error.
+#[derive(Clone, Debug)]
+pub struct UnpackOptions {
+    preserve_permissions: bool,
+    unpack_xattrs: bool,
+    overwrite: OverwritePolicy,
+    preserve_ownership: bool,
+    uid_map: Option<IdMap>,
+    gid_map: Option<IdMap>,
+    ignore_ownership_errors: bool,
+    unsafe_entries: UnsafeEntryPolicy,
+    compression: Option<Compression>,
+    diff_ids: Option<Vec<String>>,
+}
+
+/// Former name of `UnpackOptions`.
+#[deprecated(note = "renamed to `UnpackOptions`")]
+pub type RenderOptions = UnpackOptions;
+
+/// How to handle entries colliding with paths of the target directory.
+///
+/// This only concerns paths which existed before unpacking: entries of upper
+/// layers always replace the ones of lower layers. Directory entries are
+/// merged into existing directories rather than colliding with them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Replace the existing path, whatever its type.
+    Overwrite,
+    /// Keep the existing path and skip the entry, with the entries below it.
+    /// Whiteouts still remove existing paths.
+    Skip,
+    /// Fail with `RenderError::AlreadyExists`.
+    Error,
+}
+
+/// What to do with an entry, as decided by the filter of `unpack_filtered`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryAction {
+    /// Extract the entry at its path.
+    Extract,
+    /// Leave the entry out.
+    Skip,
+    /// Extract the entry at another path, relative to the target directory.
+    ExtractTo(path::PathBuf),
+}
+
+/// What happened to existing paths while unpacking.
+///
+/// Paths are relative to the target directory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnpackSummary {
+    /// Existing paths which were replaced by an entry.
+    pub overwritten: Vec<path::PathBuf>,
+    /// Entries which weren't extracted to keep existing paths.
+    pub skipped: Vec<path::PathBuf>,
+    /// Digests of the uncompressed layers, in order, to compare with the
+    /// `rootfs.diff_ids` of the image config.
+    pub diff_ids: Vec<String>,
+}
+
+/// Paths handled so far while unpacking a list of layers.
+#[derive(Default)]
+struct UnpackState {
+    summary: UnpackSummary,
+    /// Index of the layer being unpacked.
+    layer: usize,
+    /// Paths extracted by any layer, with their parents.
+    unpacked: HashSet<path::PathBuf>,
+    skipped: HashSet<path::PathBuf>,
+    /// Where entries were extracted with a filter, by their path in the image.
+    extracted: BTreeMap<path::PathBuf, path::PathBuf>,
+}
+
+impl UnpackState {
+    fn mark_unpacked(&mut self, path: &Path) {
+        for p in path.ancestors() {
+            if !self.unpacked.insert(p.to_path_buf()) {
+                break;
+            }
+        }
+    }
+
+    fn skip(&mut self, path: &Path) {
+        self.skipped.insert(path.to_path_buf());
+        self.summary.skipped.push(path.to_path_buf());
+    }
+
+    /// Record the diff_id of the layer just unpacked, verifying it if the options say so.
+    fn add_diff_id(&mut self, hash: Sha256, options: &UnpackOptions) -> Result<(), RenderError> {
+        let layer = self.summary.diff_ids.len();
+        let got = Hasher::from(hash).finalize();
+        if let Some(diff_ids) = &options.diff_ids {
+            let diff_id = diff_ids.get(layer).ok_or(RenderError::DiffIdCount {
+                layers: layer + 1,
+                diff_ids: diff_ids.len(),
+            })?;
+            let expected = ContentDigest::try_new(diff_id.clone())?;
+            if expected != got {
+                return Err(RenderError::DiffIdMismatch {
+                    layer,
+                    expected: expected.to_string(),
+                    got: got.to_string(),
+                });
+            }
+        }
+        self.summary.diff_ids.push(got.to_string());
+        Ok(())
+    }
+
+    /// Return the summary once all layers are unpacked.
+    fn finish(self, options: &UnpackOptions) -> Result<UnpackSummary, RenderError> {
+        options.check_diff_id_count(self.summary.diff_ids.len())?;
+        Ok(self.summary)
+    }
+}
+
+/// How to handle layer entries escaping the target directory.
+///
+/// These are entries with `..` components leading above the root, and
+/// symlinks or hardlinks pointing above it. Paths going through symlinks
+/// are always resolved within the target, like inside a container.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsafeEntryPolicy {
+    /// Fail with `RenderError::PathEscape` or `RenderError::SymlinkEscape`.
+    Error,
+    /// Log a warning and skip the entry.
+    Skip,
+}
+
+impl Default for UnpackOptions {
+    fn default() -> Self {
+        Self {
+            preserve_permissions: true,
+            unpack_xattrs: true,
+            overwrite: OverwritePolicy::Overwrite,
+            preserve_ownership: false,
+            uid_map: None,
+            gid_map: None,
+            ignore_ownership_errors: false,
+            unsafe_entries: UnsafeEntryPolicy::Error,
+            compression: None,
+            diff_ids: None,
+        }
+    }
+}
+
+impl UnpackOptions {
+    /// Set whether file permissions from the layers are applied (Unix only).
+    pub fn preserve_permissions(mut self, preserve: bool) -> Self {
+        self.preserve_permissions = preserve;
+        self
+    }
+
+    /// Set whether extended attributes from the layers are applied (Unix only).
+    pub fn unpack_xattrs(mut self, unpack: bool) -> Self {
+        self.unpack_xattrs = unpack;
+        self
+    }
+
+    /// Set whether existing files in the target directory are overwritten.
+    ///
+    /// Not overwriting them is the same as `OverwritePolicy::Error`.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = if overwrite {
+            OverwritePolicy::Overwrite
+        } else {
+            OverwritePolicy::Error
+        };
+        self
+    }
+
+    /// Set how entries colliding with existing paths are handled.
+    pub fn overwrite_policy(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite = policy;
+        self
+    }
+
+    /// Set whether the user and group ids of the entries are applied (Unix only).
+    ///
+    /// Changing the owner of files usually requires running as root.
+    pub fn preserve_ownership(mut self, preserve: bool) -> Self {
+        self.preserve_ownership = preserve;
+        self
+    }
+
+    /// Map the user ids of the entries to ids on the host, for rootless use.
+    ///
+    /// Only used when preserving ownership. Entries with unmapped ids fail
+    /// like a failure to change their owner.
+    pub fn uid_map(mut self, map: IdMap) -> Self {
+        self.uid_map = Some(map);
+        self
+    }
+
+    /// Map the group ids of the entries to ids on the host, like `uid_map`.
+    pub fn gid_map(mut self, map: IdMap) -> Self {
+        self.gid_map = Some(map);
+        self
+    }
+
+    /// Set whether failures to change the owner of entries only log a warning.
+    ///
+    /// Otherwise they fail with `RenderError::Ownership`, as when unpacking
+    /// without the privileges to change owners.
+    pub fn ignore_ownership_errors(mut self, ignore: bool) -> Self {
+        self.ignore_ownership_errors = ignore;
+        self
+    }
+
+    /// Set how entries escaping the target directory are handled.
+    pub fn unsafe_entries(mut self, policy: UnsafeEntryPolicy) -> Self {
+        self.unsafe_entries = policy;
+        self
+    }
+
+    /// Set the compression of the layers instead of detecting it.
+    ///
+    /// Useful when the media type of the layers is known from the manifest,
+    /// see `Compression::from_media_type`.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Check the layers against their diff_ids, in order.
+    ///
+    /// The diff_ids are the digests of the uncompressed layer tarballs, as
+    /// listed in `rootfs.diff_ids` of the image config. A layer is checked
+    /// once it has been extracted, so a mismatching layer will have been
+    /// written to the target. Unpacking fails with `RenderError::DiffIdMismatch`
+    /// or `RenderError::DiffIdCount`.
+    pub fn verify_diff_ids(mut self, diff_ids: Vec<String>) -> Self {
+        self.diff_ids = Some(diff_ids);
+        self
+    }
+
+    /// Fail if the number of layers doesn't match the diff_ids to verify.
+    fn check_diff_id_count(&self, layers: usize) -> Result<(), RenderError> {
+        match &self.diff_ids {
+            Some(diff_ids) if diff_ids.len() != layers => Err(RenderError::DiffIdCount {
+                layers,
+                diff_ids: diff_ids.len(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail or skip an unsafe entry according to the policy.
+    fn reject(&self, error: RenderError) -> Result<(), RenderError> {
+        match self.unsafe_entries {
+            UnsafeEntryPolicy::Error => Err(error),
+            UnsafeEntryPolicy::Skip => {
+                warn!("skipping unsafe layer entry: {}", error);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Mapping of user or group ids in layers to ids on the host.
+///
+/// This works like the `uid_map` of a user namespace: ranges of ids in the
+/// layers are shifted to ranges of ids on the host, other ids are unmapped.
+///
+/// ```
+/// use ghregistry::render::IdMap;
+///
+/// // Subordinate ids of a rootless user, as in `/etc/subuid`
+/// let map = IdMap::new().range(0, 100_000, 65_536);
+/// assert_eq!(map.map(999), Some(100_999));
+/// assert_eq!(map.map(70_000), None);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdMap {
+    ranges: Vec<IdRange>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct IdRange {
+    container: u32,
+    host: u32,
+    len: u32,
+}
+
+impl IdMap {
+    /// A map without ranges, mapping no id.
+    pub fn new() -> Self {
+        IdMap { ranges: Vec::new() }
+    }
+
+    /// A map adding `offset` to every id which stays in range.
+    pub fn offset(offset: u32) -> Self {
+        Self::new().range(0, offset, u32::MAX - offset)
+    }
+
+    /// Map `len` ids starting at `container` to ids starting at `host`.
+    pub fn range(mut self, container: u32, host: u32, len: u32) -> Self {
+        self.ranges.push(IdRange {
+            container,
+            host,
+            len,
+        });
+        self
+    }
+
+    /// The host id of an id in a layer, `None` if it is not mapped.
+    pub fn map(&self, id: u32) -> Option<u32> {
+        self.ranges.iter().find_map(|range| {
+            let offset = id.checked_sub(range.container)?;
+            if offset < range.len {
+                range.host.checked_add(offset)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl Default for IdMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compression of a layer tarball.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    /// A plain tar archive.
+    None,
+}
+
+impl Compression {
+    /// Detect the compression from the magic bytes at the start of a layer.
+    ///
+    /// Anything which is neither gzip nor zstd is assumed to be a plain tar.
+    pub fn sniff(start: &[u8]) -> Self {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// The compression of a layer with the given media type, if it tells.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        use crate::mediatypes::MediaTypes;
+
+        match media_type.parse().ok()? {
+            MediaTypes::ImageLayerTgz
+            | MediaTypes::ImageLayerForeignTgz
+            | MediaTypes::OciImageLayerTgz
+            | MediaTypes::OciImageLayerNondistributableTgz => Some(Compression::Gzip),
+            MediaTypes::OciImageLayerTzst => Some(Compression::Zstd),
+            MediaTypes::ImageLayerTar | MediaTypes::OciImageLayerTar => Some(Compression::None),
+            _ => None,
+        }
+    }
 }
 
 /// Unpack an ordered list of layers to a target directory.
 ///
-/// Layers must be provided as gzip-compressed tar archives, with lower layers
-/// coming first. Target directory must be an existing absolute path.
+/// Layers are tar archives, gzip or zstd compressed or not, with lower layers
+/// coming first. The compression is detected unless set in the options.
+/// Target directory must be an existing absolute path.
 pub fn unpack(layers: &[Vec<u8>], target_dir: &path::Path) -> Result<(), RenderError> {
+    unpack_with_options(layers, target_dir, &UnpackOptions::default())?;
+    Ok(())
+}
+
+/// Unpack an ordered list of layers to a target directory using the given options.
+///
+/// Returns what happened to the paths which existed in the target before.
+pub fn unpack_with_options(
+    layers: &[Vec<u8>],
+    target_dir: &path::Path,
+    options: &UnpackOptions,
+) -> Result<UnpackSummary, RenderError> {
     if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
         return Err(RenderError::WrongTargetPath(target_dir.to_path_buf()));
     }
+    options.check_diff_id_count(layers.len())?;
+    let mut state = UnpackState::default();
     for l in layers {
-        // Unpack layers
-        let mut input = std::io::BufReader::new(l.as_slice());
-        let gz_dec = gzip::Decoder::new(&mut input)?;
-        let mut archive = tar::Archive::new(gz_dec);
-        archive.set_preserve_permissions(true);
-        archive.set_unpack_xattrs(true);
-        archive.unpack(target_dir)?;
+        unpack_reader(l.as_slice(), target_dir, options, None, &mut state)?;
+    }
+    state.finish(options)
+}
+
+/// Unpack an ordered list of layers, verifying each against its diff_id.
+///
+/// See `UnpackOptions::verify_diff_ids`, which also applies to the other
+/// ways of unpacking.
+pub fn unpack_with_diff_ids(
+    layers: &[Vec<u8>],
+    diff_ids: &[String],
+    target_dir: &path::Path,
+) -> Result<(), RenderError> {
+    let options = UnpackOptions::default().verify_diff_ids(diff_ids.to_vec());
+    unpack_with_options(layers, target_dir, &options)?;
+    Ok(())
+}
+
+/// Unpack an ordered list of layers read from streams to a target directory.
+///
+/// Each layer is decompressed and extracted as it is read, in a single pass,
+/// so layers don't need to fit in memory. Readers are read to their end,
+/// which lets a `BlobStream` from `Client::get_blob_stream` verify its blob.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let client = ghregistry::Client::configure().build()?;
+/// # let digests: Vec<String> = vec![];
+/// let layers = digests
+///     .iter()
+///     .map(|digest| client.get_blob_stream("library/alpine", digest))
+///     .collect::<Result<Vec<_>, _>>()?;
+/// ghregistry::render::unpack_stream(layers, std::path::Path::new("/tmp/rootfs"))?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn unpack_stream<R: Read>(
+    layers: impl IntoIterator<Item = R>,
+    target_dir: &path::Path,
+) -> Result<(), RenderError> {
+    unpack_stream_with_options(layers, target_dir, &UnpackOptions::default())?;
+    Ok(())
+}
+
+/// Unpack an ordered list of layers read from streams using the given options.
+pub fn unpack_stream_with_options<R: Read>(
+    layers: impl IntoIterator<Item = R>,
+    target_dir: &path::Path,
+    options: &UnpackOptions,
+) -> Result<UnpackSummary, RenderError> {
+    if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
+        return Err(RenderError::WrongTargetPath(target_dir.to_path_buf()));
+    }
+    let mut state = UnpackState::default();
+    for mut l in layers {
+        unpack_reader(&mut l, target_dir, options, None, &mut state)?;
+        // Read anything the decompressor didn't consume
+        std::io::copy(&mut l, &mut std::io::sink())?;
+    }
+    state.finish(options)
+}
+
+/// Unpack a layer and record the digest of its uncompressed tar in `state`.
+///
+/// Whiteouts are collected while extracting and applied once the layer has
+/// been written, so hardlinks in this layer can still point at files it
+/// hides. Paths written by the layer itself are never removed by its whiteouts.
+///
+/// With a `filter`, only the entries it selects are extracted, to the path
+/// it chooses. Whiteouts aren't passed to the filter and remove what earlier
+/// layers extracted for the paths they hide.
+///
+/// Paths existing in the target which weren't unpacked by an earlier layer,
+/// as recorded in `state`, are handled according to the overwrite policy.
+///
+/// Entries are written below `target_dir` only: paths and links escaping it
+/// are handled according to the options, and symlinks in the target are
+/// resolved as if it was the root directory.
+fn unpack_reader<R: Read>(
+    input: R,
+    target_dir: &path::Path,
+    options: &UnpackOptions,
+    mut filter: Option<&mut Filter<'_, '_>>,
+    state: &mut UnpackState,
+) -> Result<(), RenderError> {
+    let decoder = decompress(input, options.compression)?;
+    let mut archive = tar::Archive::new(HashingReader::new(decoder));
+    configure_archive(&mut archive, options);
+
+    let mut written = HashSet::new();
+    let mut whiteouts = Vec::new();
+    let mut directories = Vec::new();
+    for (index, entry) in archive.entries()?.enumerate() {
+        let mut entry = entry?;
+        let path = match clean(&entry.path()?) {
+            Some(path) => path,
+            None => {
+                options.reject(RenderError::PathEscape(entry.path()?.into_owned()))?;
+                continue;
+            }
+        };
+        // The root itself is never modified
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            if name.starts_with(".wh.") {
+                whiteouts.push(path);
+                continue;
+            }
+        }
+
+        let image_path = path;
+        let path = match filter.as_mut() {
+            Some(f) => match (f.select)(&entry_info(&entry, image_path.clone())?) {
+                EntryAction::Extract => image_path.clone(),
+                EntryAction::Skip => continue,
+                EntryAction::ExtractTo(to) => match clean(&to) {
+                    Some(path) if !path.as_os_str().is_empty() => path,
+                    Some(_) => continue,
+                    None => {
+                        options.reject(RenderError::PathEscape(to))?;
+                        continue;
+                    }
+                },
+            },
+            None => image_path.clone(),
+        };
+
+        let mut link_source = None;
+        let mut link_copy = None;
+        match entry.header().entry_type() {
+            tar::EntryType::Symlink => {
+                if let Some(link) = entry.link_name()? {
+                    if let Err(e) = check_symlink(&path, &link) {
+                        options.reject(e)?;
+                        continue;
+                    }
+                }
+            }
+            tar::EntryType::Link => {
+                let link = match entry.link_name()? {
+                    Some(link) => link,
+                    None => continue,
+                };
+                let source = match clean(&link) {
+                    Some(source) => source,
+                    None => {
+                        options.reject(RenderError::PathEscape(link.into_owned()))?;
+                        continue;
+                    }
+                };
+                match &filter {
+                    Some(f) => match state.extracted.get(&source) {
+                        Some(extracted) => link_source = Some(extracted.clone()),
+                        // The target isn't extracted, copy its content instead
+                        None => match link_content(f.reopen, state.layer, index, &source)? {
+                            Some(content) => link_copy = Some(content),
+                            None => {
+                                return Err(RenderError::HardlinkTarget {
+                                    path,
+                                    target: source,
+                                })
+                            }
+                        },
+                    },
+                    None => link_source = Some(source),
+                }
+            }
+            _ => {}
+        }
+
+        if !make_way(
+            target_dir,
+            &path,
+            entry.header().entry_type(),
+            options,
+            state,
+        )? {
+            // Skipped entries still protect existing paths from the layer's whiteouts
+            written.insert(image_path);
+            continue;
+        }
+        if filter.is_some() {
+            state.extracted.insert(image_path.clone(), path.clone());
+        }
+
+        // Directories are applied last so their permissions don't prevent
+        // extracting their content
+        if entry.header().entry_type() == tar::EntryType::Directory {
+            directories.push((entry, path.clone()));
+        } else if let Some(content) = link_copy {
+            unpack_copy(entry.header(), target_dir, &path, &content, options)?;
+        } else {
+            unpack_entry(
+                &mut entry,
+                target_dir,
+                &path,
+                link_source.as_deref(),
+                options,
+            )?;
+        }
+        written.insert(image_path);
+    }
+    directories.sort_by(|(_, a), (_, b)| b.cmp(a));
+    for (mut dir, path) in directories {
+        unpack_entry(&mut dir, target_dir, &path, None, options)?;
+    }
+
+    for wh in whiteouts {
+        if filter.is_some() {
+            apply_filtered_whiteout(target_dir, &wh, &written, &mut state.extracted)?;
+        } else {
+            apply_whiteout(target_dir, &wh, &written)?;
+        }
+    }
+
+    // Hash the end-of-archive padding which tar doesn't consume
+    let mut reader = archive.into_inner();
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    state.add_diff_id(reader.hasher, options)
+}
+
+/// Opens a layer again by index.
+type Reopen<'a> = dyn Fn(usize) -> std::io::Result<Box<dyn Read + 'a>> + 'a;
+
+/// Open in-memory layers again by index.
+fn reopen_memory<'a>(
+    layers: &'a [Vec<u8>],
+) -> impl Fn(usize) -> std::io::Result<Box<dyn Read + 'a>> + 'a {
+    move |i| Ok(Box::new(&layers[i][..]))
+}
+
+/// Entries of the layers to extract.
+struct Filter<'r, 'a> {
+    select: &'r mut dyn FnMut(&EntryInfo) -> EntryAction,
+    /// Reads the layers again, to copy hardlink targets which aren't extracted.
+    reopen: &'r Reopen<'a>,
+}
+
+/// Select the entries below `prefix`, relative to it with `strip_prefix`.
+fn prefix_filter(prefix: &str, strip_prefix: bool) -> impl FnMut(&EntryInfo) -> EntryAction {
+    let prefix = clean(Path::new(prefix)).unwrap_or_default();
+    move |entry| match entry.path.strip_prefix(&prefix) {
+        Ok(relative) if strip_prefix => EntryAction::ExtractTo(relative.to_path_buf()),
+        Ok(_) => EntryAction::Extract,
+        Err(_) => EntryAction::Skip,
+    }
+}
+
+/// Clear the way for an entry at `path`, returning false if it is skipped.
+///
+/// Anything at `path` is removed, unless both it and the entry are
+/// directories. Paths which weren't unpacked by an earlier entry existed
+/// before unpacking and are subject to the overwrite policy, as are entries
+/// below skipped ones.
+fn make_way(
+    target_dir: &Path,
+    path: &Path,
+    entry_type: tar::EntryType,
+    options: &UnpackOptions,
+    state: &mut UnpackState,
+) -> Result<bool, RenderError> {
+    if path.ancestors().skip(1).any(|p| state.skipped.contains(p)) {
+        state.skip(path);
+        return Ok(false);
+    }
+
+    let dst = target_dir.join(resolve_parent_in_root(target_dir, path)?);
+    let existing = match fs::symlink_metadata(&dst) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            state.mark_unpacked(path);
+            return Ok(true);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // Directories are merged, also through symlinks to directories
+    if entry_type == tar::EntryType::Directory {
+        let resolved = target_dir.join(resolve_in_root(target_dir, path)?);
+        if fs::symlink_metadata(resolved).is_ok_and(|m| m.is_dir()) {
+            state.mark_unpacked(path);
+            return Ok(true);
+        }
+    }
+
+    if !state.unpacked.contains(path) {
+        match options.overwrite {
+            OverwritePolicy::Overwrite => state.summary.overwritten.push(path.to_path_buf()),
+            OverwritePolicy::Skip => {
+                debug!("keeping existing {:?}", path);
+                state.skip(path);
+                return Ok(false);
+            }
+            OverwritePolicy::Error => return Err(RenderError::AlreadyExists(path.to_path_buf())),
+        }
+    }
+    // Symlinks are removed themselves, not what they point to
+    if existing.is_dir() {
+        fs::remove_dir_all(&dst)?;
+    } else {
+        fs::remove_file(&dst)?;
+    }
+    state.mark_unpacked(path);
+    Ok(true)
+}
+
+/// Unpack an entry to `path` below the target directory.
+///
+/// Hardlinks are created to `link_source`, also relative to the target.
+/// Symlinks on the way are resolved within the target, so the entry can't be
+/// written outside of it through a symlink of an earlier entry or layer.
+fn unpack_entry<R: Read>(
+    entry: &mut tar::Entry<R>,
+    target_dir: &Path,
+    path: &Path,
+    link_source: Option<&Path>,
+    options: &UnpackOptions,
+) -> Result<(), RenderError> {
+    // Directories are followed when they already exist as a symlink,
+    // everything else replaces the last component
+    let dst = if entry.header().entry_type() == tar::EntryType::Directory {
+        target_dir.join(resolve_in_root(target_dir, path)?)
+    } else {
+        target_dir.join(resolve_parent_in_root(target_dir, path)?)
+    };
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match link_source {
+        Some(source) => {
+            let abs_source = target_dir.join(resolve_parent_in_root(target_dir, source)?);
+            match fs::hard_link(abs_source, &dst) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(RenderError::HardlinkTarget {
+                        path: path.to_path_buf(),
+                        target: source.to_path_buf(),
+                    })
+                }
+                result => result?,
+            }
+        }
+        None => {
+            entry.unpack(&dst)?;
+            preserve_ownership(entry.header(), &dst, path, options)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the content of a hardlink's target as a copy at `path`.
+///
+/// Used for hardlinks whose target isn't extracted. The copy gets the mode
+/// and owner of the hardlink entry.
+fn unpack_copy(
+    header: &tar::Header,
+    target_dir: &Path,
+    path: &Path,
+    content: &[u8],
+    options: &UnpackOptions,
+) -> Result<(), RenderError> {
+    let dst = target_dir.join(resolve_parent_in_root(target_dir, path)?);
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&dst, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if options.preserve_permissions {
+            fs::set_permissions(&dst, fs::Permissions::from_mode(header.mode()? & 0o7777))?;
+        }
+    }
+    preserve_ownership(header, &dst, path, options)
+}
+
+/// Apply the owner of an entry to `dst` if the options say so.
+fn preserve_ownership(
+    header: &tar::Header,
+    dst: &Path,
+    path: &Path,
+    options: &UnpackOptions,
+) -> Result<(), RenderError> {
+    if !options.preserve_ownership {
+        return Ok(());
+    }
+    if let Err(source) = apply_ownership(header, dst, options) {
+        if !options.ignore_ownership_errors {
+            return Err(RenderError::Ownership {
+                path: path.to_path_buf(),
+                source,
+            });
+        }
+        warn!("unable to set the owner of {:?}: {}", path, source);
+    }
+    Ok(())
+}
+
+/// An entry found at a hardlink's target.
+enum LinkTarget {
+    File(Vec<u8>),
+    /// Another hardlink, at the given index of its layer.
+    Link(usize, path::PathBuf),
+    Other,
+}
+
+/// Read the content of the file a hardlink points at.
+///
+/// The hardlink is entry `index` of `layer`. Its target is the last entry
+/// at `target` before it, in the same layer or else the layers below, which
+/// are read again with `reopen`. Hardlinks to hardlinks are followed.
+/// Returns `None` if the target isn't a regular file or is missing.
+fn link_content(
+    reopen: &Reopen<'_>,
+    layer: usize,
+    index: usize,
+    target: &Path,
+) -> Result<Option<Vec<u8>>, RenderError> {
+    for current in (0..=layer).rev() {
+        let mut archive = tar::Archive::new(decompress(reopen(current)?, None)?);
+        let mut found = None;
+        for (i, entry) in archive.entries()?.enumerate() {
+            if current == layer && i >= index {
+                break;
+            }
+            let mut entry = entry?;
+            if clean(&entry.path()?).as_deref() != Some(target) {
+                continue;
+            }
+            found = Some(match entry.header().entry_type() {
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    let mut content = Vec::new();
+                    entry.read_to_end(&mut content)?;
+                    LinkTarget::File(content)
+                }
+                tar::EntryType::Link => match entry.link_name()?.and_then(|l| clean(&l)) {
+                    Some(next) => LinkTarget::Link(i, next),
+                    None => LinkTarget::Other,
+                },
+                _ => LinkTarget::Other,
+            });
+        }
+        match found {
+            Some(LinkTarget::File(content)) => return Ok(Some(content)),
+            Some(LinkTarget::Link(i, next)) => return link_content(reopen, current, i, &next),
+            Some(LinkTarget::Other) => return Ok(None),
+            None => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Change the owner of an unpacked entry to its mapped user and group ids.
+#[cfg(unix)]
+fn apply_ownership(
+    header: &tar::Header,
+    dst: &Path,
+    options: &UnpackOptions,
+) -> std::io::Result<()> {
+    use std::convert::TryFrom;
+    use std::os::unix::fs::PermissionsExt;
+
+    let map = |id: u64, map: Option<&IdMap>, kind: &str| {
+        let mapped = u32::try_from(id)
+            .ok()
+            .and_then(|id| map.map_or(Some(id), |map| map.map(id)));
+        mapped.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} {} is not mapped", kind, id),
+            )
+        })
+    };
+    let uid = map(header.uid()?, options.uid_map.as_ref(), "uid")?;
+    let gid = map(header.gid()?, options.gid_map.as_ref(), "gid")?;
+    std::os::unix::fs::lchown(dst, Some(uid), Some(gid))?;
+
+    // Changing the owner clears the setuid and setgid bits
+    let mode = header.mode()?;
+    if options.preserve_permissions
+        && mode & 0o6000 != 0
+        && header.entry_type() != tar::EntryType::Symlink
+    {
+        fs::set_permissions(dst, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn apply_ownership(
+    _header: &tar::Header,
+    _dst: &Path,
+    _options: &UnpackOptions,
+) -> std::io::Result<()> {
+    debug!("ownership is not preserved on this platform");
+    Ok(())
+}
+
+/// Normalize an entry path to a relative path without `.` and `..` components.
+///
+/// Returns `None` if the path leads above its root.
+fn clean(path: &Path) -> Option<path::PathBuf> {
+    let mut cleaned = path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            path::Component::Normal(name) => cleaned.push(name),
+            path::Component::ParentDir if !cleaned.pop() => return None,
+            _ => {}
+        }
+    }
+    Some(cleaned)
+}
+
+/// Maximum number of symlinks followed when resolving a path, like `MAXSYMLINKS` on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolve the symlinks of a relative path as if `root` was the root directory.
+///
+/// Absolute symlink targets start at `root` and `..` stops at it, like inside
+/// a container, so the resolved path always lies below `root`.
+fn resolve_in_root(root: &Path, path: &Path) -> std::io::Result<path::PathBuf> {
+    // Components left to resolve, the next one last
+    let mut pending: Vec<path::PathBuf> = Vec::new();
+    let push_components = |pending: &mut Vec<path::PathBuf>, path: &Path| {
+        for component in path.components().rev() {
+            match component {
+                path::Component::Normal(_) | path::Component::ParentDir => {
+                    pending.push(component.as_os_str().into())
+                }
+                _ => {}
+            }
+        }
+    };
+    push_components(&mut pending, path);
+
+    let mut resolved = path::PathBuf::new();
+    let mut links = 0;
+    while let Some(component) = pending.pop() {
+        if component.as_os_str() == ".." {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&component);
+        match fs::symlink_metadata(root.join(&candidate)) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    return Err(std::io::Error::other(format!(
+                        "too many levels of symbolic links in {:?}",
+                        path
+                    )));
+                }
+                let target = fs::read_link(root.join(&candidate))?;
+                if target.is_absolute() {
+                    resolved.clear();
+                }
+                push_components(&mut pending, &target);
+            }
+            _ => resolved = candidate,
+        }
+    }
+    Ok(resolved)
+}
+
+/// Resolve the parent of a relative path within `root`, keeping its last component.
+fn resolve_parent_in_root(root: &Path, path: &Path) -> std::io::Result<path::PathBuf> {
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let resolved = resolve_in_root(root, parent)?;
+    Ok(match path.file_name() {
+        Some(name) => resolved.join(name),
+        None => resolved,
+    })
+}
 
-        // Clean whiteouts
-        clean_whiteouts(target_dir, std::io::BufReader::new(l.as_slice()))?;
+/// Ensure a relative symlink target doesn't point above the root of the image.
+///
+/// Absolute targets are interpreted relative to the image root, as they would
+/// be inside a container.
+fn check_symlink(path: &Path, link: &Path) -> Result<(), RenderError> {
+    if link.is_absolute() {
+        return Ok(());
+    }
+    let mut depth = path.parent().map_or(0, |p| p.components().count());
+    for component in link.components() {
+        match component {
+            path::Component::ParentDir if depth == 0 => {
+                return Err(RenderError::SymlinkEscape {
+                    path: path.to_path_buf(),
+                    target: link.to_path_buf(),
+                });
+            }
+            path::Component::ParentDir => depth -= 1,
+            path::Component::Normal(_) => depth += 1,
+            _ => {}
+        }
     }
     Ok(())
 }
 
+/// Decoder reading every member of a concatenated gzip stream.
+///
+/// Some tools write layers as several gzip members, which a plain decoder
+/// would stop after the first of. Data following the last member which
+/// doesn't start another one, like zero padding, is ignored.
+struct GzipMembers<R: BufRead> {
+    decoder: Option<gzip::Decoder<R>>,
+}
+
+impl<R: BufRead> GzipMembers<R> {
+    fn new(inner: R) -> std::io::Result<Self> {
+        Ok(GzipMembers {
+            decoder: Some(gzip::Decoder::new(inner)?),
+        })
+    }
+}
+
+impl<R: BufRead> Read for GzipMembers<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let decoder = match self.decoder.as_mut() {
+                Some(decoder) => decoder,
+                None => return Ok(0),
+            };
+            let n = decoder.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            // The member ended, continue with the next one if there is one
+            let mut inner = self.decoder.take().expect("decoder is set").into_inner();
+            let next = inner.fill_buf()?;
+            if next.first() == Some(&0x1f) {
+                self.decoder = Some(gzip::Decoder::new(inner)?);
+            } else if !next.is_empty() {
+                debug!("ignoring trailing data after the last gzip member");
+            }
+        }
+    }
+}
+
+/// Decompress a layer with the given compression, or the one detected.
+pub(crate) fn decompress<R: Read>(
+    input: R,
+    compression: Option<Compression>,
+) -> std::io::Result<impl Read> {
+    let mut input = BufReader::new(input);
+    let compression = match compression {
+        Some(compression) => compression,
+        None => Compression::sniff(input.fill_buf()?),
+    };
+    Ok(match compression {
+        Compression::Gzip => Decoder::Gzip(GzipMembers::new(input)?),
+        Compression::Zstd => Decoder::Zstd(ZstdFrames::new(input)),
+        Compression::None => Decoder::Plain(input),
+    })
+}
+
+/// Decoder of a layer with any supported compression.
+enum Decoder<R: BufRead> {
+    Gzip(GzipMembers<R>),
+    Zstd(ZstdFrames<R>),
+    Plain(R),
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.read(buf),
+            Decoder::Zstd(decoder) => decoder.read(buf),
+            Decoder::Plain(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Decoder reading every frame of a zstd stream.
+///
+/// Skippable frames, which carry metadata in formats like `zstd:chunked`,
+/// are ignored.
+struct ZstdFrames<R: BufRead> {
+    inner: R,
+    decoder: Box<FrameDecoder>,
+    /// Whether `decoder` is in the middle of a frame.
+    in_frame: bool,
+}
+
+impl<R: BufRead> ZstdFrames<R> {
+    fn new(inner: R) -> Self {
+        ZstdFrames {
+            inner,
+            decoder: Box::new(FrameDecoder::new()),
+            in_frame: false,
+        }
+    }
+
+    /// Start decoding the next frame, returning false at the end of the stream.
+    fn next_frame(&mut self) -> std::io::Result<bool> {
+        loop {
+            if self.inner.fill_buf()?.is_empty() {
+                return Ok(false);
+            }
+            match self.decoder.reset(&mut self.inner) {
+                Ok(()) => return Ok(true),
+                Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame {
+                    length,
+                    ..
+                })) => {
+                    let mut skipped = (&mut self.inner).take(length.into());
+                    std::io::copy(&mut skipped, &mut std::io::sink())?;
+                }
+                Err(e) => return Err(invalid_zstd(e)),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for ZstdFrames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if !self.in_frame {
+                if !self.next_frame()? {
+                    return Ok(0);
+                }
+                self.in_frame = true;
+            }
+            while self.decoder.can_collect() < buf.len() && !self.decoder.is_finished() {
+                let needed = buf.len() - self.decoder.can_collect();
+                self.decoder
+                    .decode_blocks(&mut self.inner, BlockDecodingStrategy::UptoBytes(needed))
+                    .map_err(invalid_zstd)?;
+            }
+            let n = self.decoder.read(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            // The frame is finished and fully read
+            self.in_frame = false;
+        }
+    }
+}
+
+fn invalid_zstd(e: FrameDecoderError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+/// Reader computing the sha256 of all data read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Unpack an ordered list of layer files to a target directory.
+///
+/// Files which can't be opened are skipped.
 pub fn unpack_files(files: Vec<String>, target_dir: &path::Path) -> Result<(), RenderError> {
+    unpack_files_with_options(files, target_dir, &UnpackOptions::default())?;
+    Ok(())
+}
+
+/// Unpack an ordered list of layer files to a target directory using the given options.
+pub fn unpack_files_with_options(
+    files: Vec<String>,
+    target_dir: &path::Path,
+    options: &UnpackOptions,
+) -> Result<UnpackSummary, RenderError> {
     if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
         return Err(RenderError::WrongTargetPath(target_dir.to_path_buf()));
     }
+    let mut state = UnpackState::default();
     for file in files {
         // Unpack layers
         let path = Path::new(&file);
         if let Ok(f) = std::fs::OpenOptions::new().read(true).open(path) {
-            let mut input = std::io::BufReader::new(&f);
+            unpack_reader(f, target_dir, options, None, &mut state)?;
+        };
+    }
+    state.finish(options)
+}
 
-            let gz_dec = gzip::Decoder::new(&mut input)?;
-            let mut archive = tar::Archive::new(gz_dec);
-            archive.set_preserve_permissions(true);
-            archive.set_unpack_xattrs(true);
-            archive.unpack(target_dir)?;
+/// Unpack the entries of an ordered list of layers selected by `filter`.
+///
+/// The filter is called with the metadata of each entry and decides whether
+/// and where it is extracted. Whiteouts aren't passed to it: they remove what
+/// was extracted for the paths they hide, wherever that went. Hardlinks to
+/// entries which weren't extracted are extracted as copies of their target.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use ghregistry::render::{self, EntryAction, EntryType};
+/// # let layers: Vec<Vec<u8>> = vec![];
+/// render::unpack_filtered(&layers, std::path::Path::new("/tmp/rootfs"), |entry| {
+///     if entry.path.starts_with("usr/share/doc") {
+///         EntryAction::Skip
+///     } else if entry.entry_type == EntryType::File && entry.size > 1 << 30 {
+///         EntryAction::Skip
+///     } else {
+///         EntryAction::Extract
+///     }
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn unpack_filtered(
+    layers: &[Vec<u8>],
+    target_dir: &path::Path,
+    filter: impl FnMut(&EntryInfo) -> EntryAction,
+) -> Result<(), RenderError> {
+    unpack_filtered_with_options(layers, target_dir, filter, &UnpackOptions::default())?;
+    Ok(())
+}
 
-            // Clean whiteouts
-            clean_whiteouts(target_dir, std::io::BufReader::new(f))?;
-        };
+/// Unpack the entries of layers selected by `filter` using the given options.
+pub fn unpack_filtered_with_options(
+    layers: &[Vec<u8>],
+    target_dir: &path::Path,
+    mut filter: impl FnMut(&EntryInfo) -> EntryAction,
+    options: &UnpackOptions,
+) -> Result<UnpackSummary, RenderError> {
+    if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
+        return Err(RenderError::WrongTargetPath(target_dir.to_path_buf()));
     }
+    options.check_diff_id_count(layers.len())?;
+    let reopen = reopen_memory(layers);
+    let mut filter = Filter {
+        select: &mut filter,
+        reopen: &reopen,
+    };
+    let mut state = UnpackState::default();
+    for (i, l) in layers.iter().enumerate() {
+        state.layer = i;
+        unpack_reader(
+            l.as_slice(),
+            target_dir,
+            options,
+            Some(&mut filter),
+            &mut state,
+        )?;
+    }
+    state.finish(options)
+}
+
+/// Unpack the entries below `filter` from an ordered list of layers.
+///
+/// With `strip_prefix`, entries are extracted relative to `filter`, so
+/// `usr/share/doc/README` with filter `usr/share` lands at `doc/README`.
+/// Otherwise they keep their full path. Whiteouts only apply within the
+/// filtered subtree, and hardlinks to entries outside of it are extracted
+/// as copies of their target.
+pub fn unpack_partial(
+    layers: &[Vec<u8>],
+    target_dir: &path::Path,
+    filter: &str,
+    strip_prefix: bool,
+) -> Result<(), RenderError> {
+    unpack_partial_with_options(
+        layers,
+        target_dir,
+        filter,
+        strip_prefix,
+        &UnpackOptions::default(),
+    )?;
     Ok(())
 }
 
+/// Unpack the entries below `filter` from an ordered list of layers using the given options.
+pub fn unpack_partial_with_options(
+    layers: &[Vec<u8>],
+    target_dir: &path::Path,
+    filter: &str,
+    strip_prefix: bool,
+    options: &UnpackOptions,
+) -> Result<UnpackSummary, RenderError> {
+    unpack_filtered_with_options(
+        layers,
+        target_dir,
+        prefix_filter(filter, strip_prefix),
+        options,
+    )
+}
+
+/// Unpack the entries below `filter` from layer files, with the prefix stripped.
+///
+/// Files which can't be opened are skipped.
 pub fn unpack_partial_files(
     files: Vec<String>,
     target_dir: &path::Path,
     filter: &str,
 ) -> Result<(), RenderError> {
+    unpack_partial_files_with_options(files, target_dir, filter, &UnpackOptions::default())?;
+    Ok(())
+}
+
+/// Unpack the entries below `filter` from layer files using the given options.
+pub fn unpack_partial_files_with_options(
+    files: Vec<String>,
+    target_dir: &path::Path,
+    filter: &str,
+    options: &UnpackOptions,
+) -> Result<UnpackSummary, RenderError> {
     if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
         return Err(RenderError::WrongTargetPath(target_dir.to_path_buf()));
     }
-    for f in files {
-        // Unpack layers
-        let path = Path::new(&f);
+    let reopen =
+        |i: usize| -> std::io::Result<Box<dyn Read>> { Ok(Box::new(fs::File::open(&files[i])?)) };
+    let mut select = prefix_filter(filter, true);
+    let mut filter = Filter {
+        select: &mut select,
+        reopen: &reopen,
+    };
+    let mut state = UnpackState::default();
+    for (i, file) in files.iter().enumerate() {
+        state.layer = i;
+        let path = Path::new(file);
         if let Ok(f) = std::fs::OpenOptions::new().read(true).open(path) {
-            let mut input = std::io::BufReader::new(&f);
-
-            let gz_dec = gzip::Decoder::new(&mut input)?;
-            let mut archive = tar::Archive::new(gz_dec);
-            archive.set_preserve_permissions(true);
-            archive.set_unpack_xattrs(true);
-            for file in archive.entries().unwrap() {
-                let mut t = target_dir.to_path_buf();
-                let mut f = file.unwrap();
-                if let Ok(path) = f.path().unwrap().strip_prefix(filter) {
-                    let parent = path.parent().unwrap_or_else(|| path::Path::new("/"));
-                    clean_whiteouts_in_path(target_dir, path, parent)?;
-                    t.push(path);
-                    std::fs::create_dir_all(t.parent().unwrap()).unwrap_or_default();
-                    if let Err(e) = f.unpack(&t) {
-                        error!("Unable to unpack: {}", e);
-                    };
-                }
-            }
+            unpack_reader(f, target_dir, options, Some(&mut filter), &mut state)?;
+        };
+    }
+    state.finish(options)
+}
+
+/// Apply the extraction settings to an archive.
+///
+/// Permissions and extended attributes only carry meaning on Unix targets.
+fn configure_archive<R: Read>(archive: &mut tar::Archive<R>, options: &UnpackOptions) {
+    #[cfg(unix)]
+    {
+        archive.set_preserve_permissions(options.preserve_permissions);
+        archive.set_unpack_xattrs(options.unpack_xattrs);
+    }
+    #[cfg(not(unix))]
+    let _ = (options.preserve_permissions, options.unpack_xattrs);
+}
+
+/// Remove the path hidden by a whiteout entry from the target directory.
+///
+/// An opaque whiteout hides everything its directory held before. Paths
+/// which were written by the same layer as the whiteout are kept, while
+/// what lower layers had below them is still removed.
+fn apply_whiteout(
+    target_dir: &Path,
+    path: &Path,
+    written: &HashSet<path::PathBuf>,
+) -> Result<(), RenderError> {
+    let wh_name = match path.file_name() {
+        Some(fname) => fname.to_string_lossy(),
+        None => return Ok(()),
+    };
+    if wh_name == ".wh..wh..opq" {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        return clear_opaque_dir(target_dir, dir, written);
+    }
+
+    let real_name = match wh_name.strip_prefix(".wh.") {
+        Some(real_name) if !real_name.is_empty() => real_name,
+        _ => return Ok(()),
+    };
+    let rel_real_path = path.with_file_name(real_name);
+    if written.iter().any(|w| w.starts_with(&rel_real_path)) {
+        debug!("whiteout of {:?} shadowed by the same layer", rel_real_path);
+        return clear_opaque_dir(target_dir, &rel_real_path, written);
+    }
+
+    // Remove real file behind whiteout
+    remove_path(target_dir, &rel_real_path)
+}
+
+/// Remove what earlier layers extracted with a filter for the path hidden by a whiteout.
+///
+/// Entries may have been extracted anywhere, so only the paths recorded in
+/// `extracted` for the hidden path and the paths below it are removed.
+/// Paths which were written by the same layer as the whiteout are kept.
+fn apply_filtered_whiteout(
+    target_dir: &Path,
+    path: &Path,
+    written: &HashSet<path::PathBuf>,
+    extracted: &mut BTreeMap<path::PathBuf, path::PathBuf>,
+) -> Result<(), RenderError> {
+    let wh_name = match path.file_name() {
+        Some(fname) => fname.to_string_lossy(),
+        None => return Ok(()),
+    };
+    let (hidden, opaque) = if wh_name == ".wh..wh..opq" {
+        (
+            path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+            true,
+        )
+    } else {
+        match wh_name.strip_prefix(".wh.") {
+            Some(real_name) if !real_name.is_empty() => (path.with_file_name(real_name), false),
+            _ => return Ok(()),
+        }
+    };
+
+    let removed = extracted
+        .range(hidden.clone()..)
+        .take_while(|(image_path, _)| image_path.starts_with(&hidden))
+        .filter(|(image_path, _)| !(opaque && **image_path == hidden))
+        .filter(|(image_path, _)| !written.iter().any(|w| w.starts_with(image_path)))
+        .map(|(image_path, _)| image_path.clone())
+        .collect::<Vec<_>>();
+    for image_path in removed {
+        if let Some(dst) = extracted.remove(&image_path) {
+            remove_path(target_dir, &dst)?;
         }
     }
     Ok(())
 }
 
-fn clean_whiteouts<R: Read>(target_dir: &Path, l: BufReader<R>) -> Result<(), RenderError> {
-    let gz_dec = gzip::Decoder::new(l)?;
-    let mut archive = tar::Archive::new(gz_dec);
-    for entry in archive.entries()? {
-        let file = entry?;
-        let path = file.path()?;
-        let parent = path.parent().unwrap_or_else(|| path::Path::new("/"));
-        clean_whiteouts_in_path(target_dir, &*path, parent)?;
+/// Remove a path below the target directory, if it exists.
+///
+/// Symlinks are removed themselves, not what they point to.
+fn remove_path(target_dir: &Path, path: &Path) -> Result<(), RenderError> {
+    let abs_path = target_dir.join(resolve_parent_in_root(target_dir, path)?);
+    let removed = match fs::symlink_metadata(&abs_path) {
+        Ok(m) if m.is_dir() => fs::remove_dir_all(&abs_path),
+        Ok(_) => fs::remove_file(&abs_path),
+        Err(e) => Err(e),
+    };
+    match removed {
+        // Whiting out paths which don't exist in lower layers is allowed
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+        Ok(()) => Ok(()),
     }
-    Ok(())
 }
 
-fn clean_whiteouts_in_path(
+/// Remove the content of an opaque directory which wasn't written by the current layer.
+///
+/// Directories the layer wrote to are cleared recursively, as the lower
+/// layers' content below them is hidden as well. Paths which aren't
+/// directories are left alone.
+fn clear_opaque_dir(
     target_dir: &Path,
-    path: &Path,
-    parent: &Path,
+    dir: &Path,
+    written: &HashSet<path::PathBuf>,
 ) -> Result<(), RenderError> {
-    if let Some(fname) = path.file_name() {
-        let wh_name = fname.to_string_lossy();
-        if wh_name == ".wh..wh..opq" {
-            //TODO: opaque whiteout, dir removal
-        } else if wh_name.starts_with(".wh.") {
-            let rel_parent = path::PathBuf::from("./".to_string() + &parent.to_string_lossy());
-
-            // Remove real file behind whiteout
-            let real_name = wh_name.trim_start_matches(".wh.");
-            let abs_real_path = target_dir.join(&rel_parent).join(real_name);
-            fs::remove_dir_all(abs_real_path)?;
-
-            // Remove whiteout place-holder
-            let abs_wh_path = target_dir.join(&rel_parent).join(fname);
-            fs::remove_dir_all(abs_wh_path)?;
-        };
+    let entries = match fs::read_dir(target_dir.join(resolve_in_root(target_dir, dir)?)) {
+        Ok(entries) => entries,
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            return Ok(())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let rel_path = dir.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        if written.iter().any(|w| w.starts_with(&rel_path)) {
+            if is_dir {
+                clear_opaque_dir(target_dir, &rel_path, written)?;
+            }
+        } else if is_dir {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
     }
     Ok(())
 }
@@ -0,0 +1,167 @@
+//! Read single files out of layers without unpacking them.
+
+use super::{clean, decompress, link_content, reopen_memory, RenderError, MAX_SYMLINKS};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Paths of the lower layers hidden by an upper one.
+enum Shadow {
+    /// A whiteout hides the path and everything below it.
+    Path(PathBuf),
+    /// An opaque whiteout or a non-directory hides everything below the path.
+    Below(PathBuf),
+}
+
+impl Shadow {
+    fn hides(&self, path: &Path) -> bool {
+        match self {
+            Shadow::Path(hidden) => path.starts_with(hidden),
+            Shadow::Below(dir) => path != dir && path.starts_with(dir),
+        }
+    }
+}
+
+/// Read the files at the given paths from the filesystem made of `layers`.
+///
+/// Layers are ordered lowest first, like for `unpack`, and are scanned from
+/// the top. Each path gets the content of its topmost version, paths which
+/// are whited out, missing or not regular files are left out of the result.
+/// The result is keyed by the paths as given, which may be absolute.
+///
+/// Symlinks at the requested paths are followed within the image, symlinks
+/// of their parent directories are not. Scanning stops as soon as all paths
+/// are found, so lower layers may not be read at all.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let layers: Vec<Vec<u8>> = vec![];
+/// let files = ghregistry::render::extract_paths(&layers, &["/etc/os-release"])?;
+/// if let Some(os_release) = files.get("/etc/os-release") {
+///     println!("{}", String::from_utf8_lossy(os_release));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn extract_paths(
+    layers: &[Vec<u8>],
+    paths: &[&str],
+) -> Result<HashMap<String, Vec<u8>>, RenderError> {
+    let mut found = HashMap::new();
+    // Paths to look up, with the requested paths leading to them
+    let mut pending: HashMap<PathBuf, Vec<&str>> = HashMap::new();
+    for requested in paths {
+        match clean(Path::new(requested)) {
+            Some(path) if !path.as_os_str().is_empty() => {
+                pending.entry(path).or_default().push(requested)
+            }
+            _ => debug!("not extracting {:?}, it isn't a file path", requested),
+        }
+    }
+
+    // Each round follows one more level of symlinks from the top layer
+    for _ in 0..=MAX_SYMLINKS {
+        if pending.is_empty() {
+            break;
+        }
+        let mut links = HashMap::new();
+        let mut shadows = Vec::new();
+        for layer in (0..layers.len()).rev() {
+            if pending.is_empty() {
+                break;
+            }
+            scan_layer(
+                layers,
+                layer,
+                &mut pending,
+                &mut shadows,
+                &mut found,
+                &mut links,
+            )?;
+        }
+        pending = links;
+    }
+    Ok(found)
+}
+
+/// Look up the pending paths in `layers[layer]` below the given shadows.
+///
+/// Files are added to `found` and symlinks to `links`, by the requested
+/// paths leading to them. The shadows of the layer are added for the layers
+/// below.
+fn scan_layer<'a>(
+    layers: &[Vec<u8>],
+    layer: usize,
+    pending: &mut HashMap<PathBuf, Vec<&'a str>>,
+    shadows: &mut Vec<Shadow>,
+    found: &mut HashMap<String, Vec<u8>>,
+    links: &mut HashMap<PathBuf, Vec<&'a str>>,
+) -> Result<(), RenderError> {
+    let mut archive = tar::Archive::new(decompress(&layers[layer][..], None)?);
+    let mut layer_shadows = Vec::new();
+    for (index, entry) in archive.entries()?.enumerate() {
+        let mut entry = entry?;
+        let path = match clean(&entry.path()?) {
+            Some(path) if !path.as_os_str().is_empty() => path,
+            _ => continue,
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name == ".wh..wh..opq" {
+            let dir = path.parent().unwrap_or_else(|| Path::new(""));
+            layer_shadows.push(Shadow::Below(dir.to_path_buf()));
+            continue;
+        }
+        if let Some(real_name) = name.strip_prefix(".wh.") {
+            layer_shadows.push(Shadow::Path(path.with_file_name(real_name)));
+            continue;
+        }
+
+        let entry_type = entry.header().entry_type();
+        if entry_type != tar::EntryType::Directory {
+            layer_shadows.push(Shadow::Below(path.clone()));
+        }
+        let requested = match pending.remove(&path) {
+            Some(requested) => requested,
+            None => continue,
+        };
+        if shadows.iter().any(|s| s.hides(&path)) {
+            continue;
+        }
+
+        let content = match entry_type {
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content)?;
+                Some(content)
+            }
+            tar::EntryType::Link => match entry.link_name()?.and_then(|link| clean(&link)) {
+                Some(source) => link_content(&reopen_memory(layers), layer, index, &source)?,
+                None => None,
+            },
+            tar::EntryType::Symlink => {
+                if let Some(target) = entry.link_name()? {
+                    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+                    match clean(&parent.join(target)) {
+                        Some(target) => links.entry(target).or_default().extend(&requested),
+                        None => debug!("symlink {:?} leads above the root", path),
+                    }
+                }
+                None
+            }
+            // Directories and special files have no content
+            _ => None,
+        };
+        if let Some(content) = content {
+            for requested in requested {
+                found.insert(requested.to_string(), content.clone());
+            }
+        }
+        if pending.is_empty() {
+            break;
+        }
+    }
+
+    shadows.extend(layer_shadows);
+    pending.retain(|path, _| !shadows.iter().any(|s| s.hides(path)));
+    Ok(())
+}
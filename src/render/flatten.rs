@@ -0,0 +1,93 @@
+//! Squash the layers of an image into a single tarball.
+
+use super::list::merge_layer;
+use super::{clean, decompress, link_content, reopen_memory, RenderError};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Position of an entry among the layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    layer: usize,
+    index: usize,
+}
+
+/// Write the filesystem made of `layers` as a single tar archive to `out`.
+///
+/// Layers are ordered lowest first and may be compressed, like for `unpack`.
+/// Whiteouts are resolved and a path in several layers is written once, with
+/// its uppermost entry. Headers are copied from the layers, so modes,
+/// owners, times, symlinks and hardlinks are kept as they are, without
+/// requiring any privileges. This is what `docker export` writes.
+///
+/// Layers are read twice, once to find the entry of each path and once to
+/// copy the entries, so only the headers are held in memory. Hardlinks whose
+/// target is replaced or removed by an upper layer are written as copies of
+/// the file they pointed at.
+pub fn flatten_to_tar<W: Write>(layers: &[Vec<u8>], out: W) -> Result<(), RenderError> {
+    let mut image = BTreeMap::new();
+    for (layer, data) in layers.iter().enumerate() {
+        let mut entries = Vec::new();
+        let mut archive = tar::Archive::new(decompress(&data[..], None)?);
+        for (index, entry) in archive.entries()?.enumerate() {
+            let entry = entry?;
+            match clean(&entry.path()?) {
+                Some(path) if !path.as_os_str().is_empty() => {
+                    let is_dir = entry.header().entry_type() == tar::EntryType::Directory;
+                    entries.push((path, (Position { layer, index }, is_dir)));
+                }
+                _ => debug!("not flattening entry {:?}", entry.path()?),
+            }
+        }
+        merge_layer(&mut image, entries, |(_, is_dir)| *is_dir);
+    }
+
+    let mut builder = tar::Builder::new(out);
+    for (layer, data) in layers.iter().enumerate() {
+        let mut archive = tar::Archive::new(decompress(&data[..], None)?);
+        for (index, entry) in archive.entries()?.enumerate() {
+            let mut entry = entry?;
+            let position = Position { layer, index };
+            let path = match clean(&entry.path()?) {
+                Some(path) if image.get(&path).map(|(p, _)| *p) == Some(position) => path,
+                _ => continue,
+            };
+
+            let mut header = entry.header().clone();
+            match header.entry_type() {
+                tar::EntryType::Symlink => {
+                    let target = entry.link_name()?.unwrap_or_default().into_owned();
+                    builder.append_link(&mut header, &path, target)?;
+                }
+                tar::EntryType::Link => {
+                    let target = match entry.link_name()?.and_then(|link| clean(&link)) {
+                        Some(target) => target,
+                        None => {
+                            warn!("skipping hardlink {:?} leading above the root", path);
+                            continue;
+                        }
+                    };
+                    // The target is written before the link, unless it was
+                    // replaced or removed since the link was made
+                    if image.get(&target).is_some_and(|(p, _)| *p < position) {
+                        builder.append_link(&mut header, &path, &target)?;
+                        continue;
+                    }
+                    let reopen = reopen_memory(layers);
+                    match link_content(&reopen, layer, index, &target)? {
+                        Some(content) => {
+                            header.set_entry_type(tar::EntryType::Regular);
+                            header.as_old_mut().linkname = [0; 100];
+                            header.set_size(content.len() as u64);
+                            builder.append_data(&mut header, &path, &content[..])?;
+                        }
+                        None => warn!("skipping hardlink {:?} to missing {:?}", path, target),
+                    }
+                }
+                _ => builder.append_data(&mut header, &path, &mut entry)?,
+            }
+        }
+    }
+    builder.into_inner()?.flush()?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! List the entries of layers without extracting them.
+
+use super::{clean, decompress, RenderError};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+/// Type of a layer entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink,
+    Hardlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    /// Any other type, like GNU sparse files.
+    Other,
+}
+
+impl From<tar::EntryType> for EntryType {
+    fn from(entry_type: tar::EntryType) -> Self {
+        match entry_type {
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryType::File,
+            tar::EntryType::Directory => EntryType::Directory,
+            tar::EntryType::Symlink => EntryType::Symlink,
+            tar::EntryType::Link => EntryType::Hardlink,
+            tar::EntryType::Char => EntryType::CharDevice,
+            tar::EntryType::Block => EntryType::BlockDevice,
+            tar::EntryType::Fifo => EntryType::Fifo,
+            _ => EntryType::Other,
+        }
+    }
+}
+
+/// Metadata of a layer entry, read from its tar header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// Path relative to the root, without `.` and `..` components.
+    pub path: PathBuf,
+    pub entry_type: EntryType,
+    /// Size of the content, which only regular files have.
+    pub size: u64,
+    /// Permission bits, including the setuid, setgid and sticky bits.
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+    /// Target of symlinks and hardlinks.
+    pub link_target: Option<PathBuf>,
+}
+
+/// List the entries of a layer in archive order, without extracting them.
+///
+/// The compression is detected like for `unpack`. Whiteouts are listed as
+/// the `.wh.` files they are stored as, entries leading above the root are
+/// left out.
+pub fn list_entries(layer: &[u8]) -> Result<Vec<EntryInfo>, RenderError> {
+    list_entries_stream(layer)
+}
+
+/// List the entries of a layer read from a stream, like `list_entries`.
+///
+/// Only the headers are kept, file contents are read past and discarded.
+pub fn list_entries_stream<R: Read>(layer: R) -> Result<Vec<EntryInfo>, RenderError> {
+    let mut archive = tar::Archive::new(decompress(layer, None)?);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = match clean(&entry.path()?) {
+            Some(path) if !path.as_os_str().is_empty() => path,
+            _ => {
+                debug!("not listing entry {:?}", entry.path()?);
+                continue;
+            }
+        };
+        entries.push(entry_info(&entry, path)?);
+    }
+    Ok(entries)
+}
+
+/// Read the metadata of an entry found at `path`.
+pub(super) fn entry_info<R: Read>(
+    entry: &tar::Entry<R>,
+    path: PathBuf,
+) -> Result<EntryInfo, RenderError> {
+    let header = entry.header();
+    // Some tools leave the owner and time fields empty, which unpacking
+    // doesn't mind either
+    Ok(EntryInfo {
+        path,
+        entry_type: header.entry_type().into(),
+        size: entry.size(),
+        mode: header.mode()?,
+        uid: header.uid().unwrap_or_default(),
+        gid: header.gid().unwrap_or_default(),
+        mtime: header.mtime().unwrap_or_default(),
+        link_target: entry.link_name()?.map(Cow::into_owned),
+    })
+}
+
+/// List the entries of the filesystem made of `layers`, lowest first.
+///
+/// Whiteouts of upper layers hide the entries of lower ones as when
+/// unpacking, and aren't listed themselves. Entries are sorted by path, a
+/// path in several layers is listed with its entry of the uppermost one.
+pub fn list_image_entries(layers: &[Vec<u8>]) -> Result<Vec<EntryInfo>, RenderError> {
+    list_image_entries_stream(layers.iter().map(|l| &l[..]))
+}
+
+/// List the entries of the filesystem made of layers read from streams.
+pub fn list_image_entries_stream<R: Read>(
+    layers: impl IntoIterator<Item = R>,
+) -> Result<Vec<EntryInfo>, RenderError> {
+    let mut image = BTreeMap::new();
+    for layer in layers {
+        let entries = list_entries_stream(layer)?
+            .into_iter()
+            .map(|e| (e.path.clone(), e))
+            .collect();
+        merge_layer(&mut image, entries, |e| {
+            e.entry_type == EntryType::Directory
+        });
+    }
+    Ok(image.into_values().collect())
+}
+
+/// Apply the entries of a layer on top of the entries of the layers below.
+///
+/// Entries come with their path, in archive order. Entries for which
+/// `is_dir` is false replace what the lower layers had below their path.
+pub(super) fn merge_layer<T>(
+    image: &mut BTreeMap<PathBuf, T>,
+    entries: Vec<(PathBuf, T)>,
+    is_dir: impl Fn(&T) -> bool,
+) {
+    let (whiteouts, entries): (Vec<_>, Vec<_>) = entries.into_iter().partition(|(path, _)| {
+        path.file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with(".wh."))
+    });
+    let written = entries
+        .iter()
+        .map(|(path, _)| path.as_path())
+        .collect::<HashSet<_>>();
+
+    for (whiteout, _) in &whiteouts {
+        let name = whiteout.file_name().unwrap_or_default().to_string_lossy();
+        let dir = whiteout.parent().unwrap_or_else(|| Path::new(""));
+        if name == ".wh..wh..opq" {
+            image.retain(|path, _| path == dir || !path.starts_with(dir));
+            continue;
+        }
+        let hidden = match name.strip_prefix(".wh.") {
+            Some(real_name) if !real_name.is_empty() => dir.join(real_name),
+            _ => continue,
+        };
+        // Like when unpacking, lower entries are only kept as parents of
+        // the layer's own entries
+        image.retain(|path, _| {
+            !path.starts_with(&hidden) || written.iter().any(|w| w.starts_with(path))
+        });
+    }
+
+    for (path, entry) in entries {
+        if !is_dir(&entry) {
+            // Paths below an entry sort right after it
+            let below = image
+                .range::<Path, _>((Bound::Excluded(path.as_path()), Bound::Unbounded))
+                .map(|(below, _)| below)
+                .take_while(|below| below.starts_with(&path))
+                .cloned()
+                .collect::<Vec<_>>();
+            for below in below {
+                image.remove(&below);
+            }
+        }
+        image.insert(path, entry);
+    }
+}
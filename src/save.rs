@@ -0,0 +1,120 @@
+//! Export an image in the format produced by `docker save`.
+
+// The legacy archive layout is described at
+// https://github.com/moby/moby/blob/v20.10.0/image/spec/v1.2.md#combined-image-json--filesystem-changeset-format
+
+use crate::errors::Result;
+use crate::manifest::{Manifest, ManifestError};
+use crate::render::{decompress, Compression};
+use crate::Client;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// Entry of the `manifest.json` file at the root of the archive.
+#[derive(Debug, Serialize)]
+struct SaveManifest {
+    #[serde(rename = "Config")]
+    config: String,
+    #[serde(rename = "RepoTags")]
+    repo_tags: Vec<String>,
+    #[serde(rename = "Layers")]
+    layers: Vec<String>,
+}
+
+impl Client {
+    /// Write an image as a tar stream that `docker load` understands.
+    ///
+    /// The archive contains the config blob, every layer decompressed as
+    /// `<digest>/layer.tar` (lowest layer first) and the
+    /// `manifest.json`/`repositories` metadata.
+    /// Only schema 2 image manifests can be saved.
+    pub fn save_image<W: Write>(&self, name: &str, reference: &str, out: W) -> Result<()> {
+        let manifest = match self.get_manifest(name, reference)? {
+            Manifest::S2(m) => m,
+            other => {
+                return Err(ManifestError::SaveUnsupported(format!("{:?}", other)).into());
+            }
+        };
+
+        let mut builder = tar::Builder::new(out);
+
+        let config_digest = &manifest.manifest_spec.config().digest;
+        let config_path = format!("{}.json", digest_hex(config_digest));
+        let config = self.get_blob(name, config_digest)?;
+        append_file(&mut builder, &config_path, &config)?;
+
+        let mut layer_paths = Vec::new();
+        for descriptor in manifest.manifest_spec.layers() {
+            let hex = digest_hex(&descriptor.digest);
+            let blob = self.get_blob(name, &descriptor.digest)?;
+            // `docker load` only reads plain tarballs
+            let compression = Compression::from_media_type(&descriptor.media_type);
+            let mut layer = Vec::new();
+            decompress(&blob[..], compression)?.read_to_end(&mut layer)?;
+            append_dir(&mut builder, hex)?;
+            let layer_path = format!("{}/layer.tar", hex);
+            append_file(&mut builder, &layer_path, &layer)?;
+            layer_paths.push(layer_path);
+        }
+
+        // Digest references cannot be expressed as docker tags
+        let is_tag = !reference.contains(':');
+        let repo_tags = if is_tag {
+            vec![format!("{}:{}", name, reference)]
+        } else {
+            vec![]
+        };
+
+        let save_manifest = vec![SaveManifest {
+            config: config_path,
+            repo_tags,
+            layers: layer_paths.clone(),
+        }];
+        append_file(
+            &mut builder,
+            "manifest.json",
+            &serde_json::to_vec(&save_manifest)?,
+        )?;
+
+        let mut repositories: HashMap<String, HashMap<String, String>> = HashMap::new();
+        if let (true, Some(top)) = (is_tag, layer_paths.last()) {
+            let top_id = top.trim_end_matches("/layer.tar").to_string();
+            repositories
+                .entry(name.to_string())
+                .or_default()
+                .insert(reference.to_string(), top_id);
+        }
+        append_file(
+            &mut builder,
+            "repositories",
+            &serde_json::to_vec(&repositories)?,
+        )?;
+
+        builder.finish()?;
+        Ok(())
+    }
+}
+
+/// Strip the algorithm prefix from a digest.
+fn digest_hex(digest: &str) -> &str {
+    digest.split_once(':').map(|(_, hex)| hex).unwrap_or(digest)
+}
+
+fn append_dir<W: Write>(builder: &mut tar::Builder<W>, path: &str) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_size(0);
+    header.set_cksum();
+    builder.append_data(&mut header, path, std::io::empty())?;
+    Ok(())
+}
+
+fn append_file<W: Write>(builder: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
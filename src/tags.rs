@@ -1,67 +1,262 @@
-use crate::errors::Result;
+//! Tag listing and helpers to work with tag names.
+
+use crate::errors::{Error, ErrorCode, Result};
 use crate::Client;
-use reqwest::{self, header, Url};
+use reqwest::blocking::Response;
+use reqwest::{self, header, StatusCode, Url};
+use std::convert::TryFrom;
 use std::fmt::Debug;
 
+/// Length of the body excerpt included in content type errors.
+const BODY_SNIPPET_LEN: usize = 512;
+
 /// A chunk of tags for an image.
 ///
 /// This contains a non-strict subset of the whole list of tags
 /// for an image, depending on pagination option at request time.
 #[derive(Debug, Default, Deserialize, Serialize)]
-struct TagsChunk {
+pub struct TagsChunk {
     /// Image repository name.
-    name: String,
+    pub name: String,
     /// Subset of tags.
-    tags: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+/// Client-side filter for tag listings.
+///
+/// Registries do not support filtering, so every page is still requested and
+/// the filter is applied to the tags as they are received.
+pub enum TagFilter {
+    /// Keep tags starting with the given string.
+    Prefix(String),
+    /// Keep tags ending with the given string.
+    Suffix(String),
+    /// Keep tags matching the regular expression.
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+    /// Keep tags for which the predicate returns `true`.
+    Predicate(Box<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl TagFilter {
+    /// Build a filter from a user-supplied predicate.
+    pub fn predicate<F: Fn(&str) -> bool + Send + Sync + 'static>(f: F) -> Self {
+        TagFilter::Predicate(Box::new(f))
+    }
+
+    /// Whether the tag passes the filter.
+    pub fn matches(&self, tag: &str) -> bool {
+        match self {
+            TagFilter::Prefix(p) => tag.starts_with(p.as_str()),
+            TagFilter::Suffix(s) => tag.ends_with(s.as_str()),
+            #[cfg(feature = "regex")]
+            TagFilter::Regex(re) => re.is_match(tag),
+            TagFilter::Predicate(f) => f(tag),
+        }
+    }
+}
+
+impl Debug for TagFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TagFilter::Prefix(p) => f.debug_tuple("Prefix").field(p).finish(),
+            TagFilter::Suffix(s) => f.debug_tuple("Suffix").field(s).finish(),
+            #[cfg(feature = "regex")]
+            TagFilter::Regex(re) => f.debug_tuple("Regex").field(re).finish(),
+            TagFilter::Predicate(_) => f.write_str("Predicate(..)"),
+        }
+    }
 }
 
 impl Client {
     /// List existing tags for an image.
+    ///
+    /// Pages are requested with `get_tags_page` until the last one.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name))
+    )]
     pub fn get_tags<'a, 'b: 'a, 'c: 'a>(
         &'b self,
         name: &'c str,
         paginate: Option<u32>,
     ) -> Result<Vec<String>> {
-        let base_url = format!("{}/v2/{}/tags/list", self.base_url, name);
-        let mut link: Option<String> = None;
-
-        let mut result: Vec<String> = Vec::new();
-
+        let mut tags = Vec::new();
+        let mut token = None;
         loop {
-            let (tags_chunk, last) = self.fetch_tags_chunk(paginate, &base_url, &link)?;
-            for tag in tags_chunk.tags {
-                result.push(tag);
+            let (chunk, next) = self.get_tags_page(name, paginate, token.as_deref())?;
+            tags.extend(chunk.tags);
+            match next {
+                Some(next) => token = Some(next),
+                None => return Ok(tags),
             }
+        }
+    }
 
-            link = match last {
-                None => break,
-                Some(ref s) if s.is_empty() => None,
-                s => s,
-            };
+    /// Fetch a single page of tags for an image.
+    ///
+    /// `n` is the requested page size and `last` the continuation token
+    /// returned with the previous page, if any. The continuation token for the
+    /// following page is `None` once the last page was reached.
+    ///
+    /// The token is the last tag of the page for registries following the
+    /// distribution spec. For `Link` headers with other parameters, like
+    /// opaque cursors, it is the link itself, which is then followed verbatim.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(repository = name, last))
+    )]
+    pub fn get_tags_page(
+        &self,
+        name: &str,
+        n: Option<u32>,
+        last: Option<&str>,
+    ) -> Result<(TagsChunk, Option<String>)> {
+        let mut url = Url::parse(&format!("{}/v2/{}/tags/list", self.base_url, name))?;
+        match last {
+            // Tags can't contain slashes, so this is a link to follow
+            Some(link) if link.contains('/') => url = url.join(link)?,
+            last => {
+                if let Some(n) = n {
+                    url.query_pairs_mut().append_pair("n", &n.to_string());
+                }
+                if let Some(last) = last {
+                    url.query_pairs_mut().append_pair("last", last);
+                }
+            }
         }
 
+        let (chunk, next) = self.fetch_tags_chunk(name, url, n)?;
+        Ok((chunk, next.map(|next| continuation_token(&next))))
+    }
+
+    /// List existing tags for an image, treating an unknown repository as empty.
+    ///
+    /// Registries answer `404` or a `NAME_UNKNOWN` error for repositories that
+    /// were never pushed to or have been pruned. Authentication failures and
+    /// any other error are still returned.
+    pub fn get_tags_or_empty(&self, name: &str, paginate: Option<u32>) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+        for tag in self.tags_iter(name, paginate) {
+            match tag {
+                Ok(tag) => result.push(tag),
+                Err(ref e) if result.is_empty() && is_unknown_repository(e) => {
+                    debug!("repository {} is unknown, assuming no tags", name);
+                    return Ok(result);
+                }
+                Err(e) => return Err(e),
+            }
+        }
         Ok(result)
     }
 
+    /// List existing tags for an image which match a filter.
+    ///
+    /// The query sent to the registry is the same as for `get_tags`.
+    pub fn get_tags_filtered(
+        &self,
+        name: &str,
+        paginate: Option<u32>,
+        filter: TagFilter,
+    ) -> Result<Vec<String>> {
+        self.tags_iter(name, paginate)
+            .filter(|tag| tag.as_ref().map_or(true, |t| filter.matches(t)))
+            .collect()
+    }
+
+    /// List at most `max_results` existing tags for an image.
+    ///
+    /// No further pages are requested once enough tags were collected, and the
+    /// page size sent to the registry is capped by the number of tags still
+    /// missing so the last request doesn't over-fetch.
+    pub fn get_tags_limited(
+        &self,
+        name: &str,
+        page_size: Option<u32>,
+        max_results: usize,
+    ) -> Result<Vec<String>> {
+        self.tags_iter_limited(name, page_size, Some(max_results))
+            .collect()
+    }
+
+    /// Find the highest tag of an image which parses as a semantic version.
+    ///
+    /// Tags which aren't semantic versions are ignored. Pre-releases are only
+    /// considered if `prerelease` is set.
+    pub fn get_latest_semver_tag(&self, name: &str, prerelease: bool) -> Result<Option<String>> {
+        let mut tags = self
+            .tags_iter(name, None)
+            .filter(|tag| {
+                tag.as_ref().map_or(true, |t| {
+                    parse_semver(t).is_some_and(|v| prerelease || v.pre.is_empty())
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        sort_semver(&mut tags);
+        Ok(tags.pop())
+    }
+
+    /// Lazily iterate over the existing tags for an image.
+    ///
+    /// Pages are only fetched when the iterator runs out of buffered tags, so
+    /// stopping early avoids requesting the remaining pages. A failure while
+    /// fetching a page is yielded as an `Err` item and ends the iteration.
+    pub fn tags_iter<'a>(
+        &'a self,
+        name: &str,
+        page_size: Option<u32>,
+    ) -> impl Iterator<Item = Result<String>> + 'a {
+        self.tags_iter_limited(name, page_size, None)
+    }
+
+    /// Lazily iterate over at most `limit` tags for an image.
+    fn tags_iter_limited(
+        &self,
+        name: &str,
+        page_size: Option<u32>,
+        limit: Option<usize>,
+    ) -> TagsIter<'_> {
+        let base_url = format!("{}/v2/{}/tags/list", self.base_url, name);
+        let next = Url::parse(&base_url).map(|mut url| {
+            if let Some(n) = page_size {
+                url.query_pairs_mut().append_pair("n", &n.to_string());
+            }
+            url
+        });
+
+        TagsIter {
+            client: self,
+            name: name.to_string(),
+            paginate: page_size,
+            next: Some(next.map_err(Into::into)),
+            buffer: Vec::new().into_iter(),
+            remaining: limit,
+        }
+    }
+
+    /// Fetch a single page of tags and the URL of the following page, if any.
     fn fetch_tags_chunk(
         &self,
+        name: &str,
+        url: Url,
         paginate: Option<u32>,
-        base_url: &str,
-        link: &Option<String>,
-    ) -> Result<(TagsChunk, Option<String>)> {
-        let url_paginated = match (paginate, link) {
-            (Some(p), None) => format!("{}?n={}", base_url, p),
-            (None, Some(l)) => format!("{}?next_page={}", base_url, l),
-            (Some(p), Some(l)) => format!("{}?n={}&next_page={}", base_url, p, l),
-            _ => base_url.to_string(),
-        };
-        let url = Url::parse(&url_paginated)?;
+    ) -> Result<(TagsChunk, Option<Url>)> {
+        let resp = self.send_reqwest(
+            self.build_reqwest(reqwest::Method::GET, url.clone())
+                .header(header::ACCEPT, "application/json")
+                .header(header::ACCEPT_ENCODING, self.document_encoding()),
+        )?;
 
-        let resp = self
-            .build_reqwest(reqwest::Method::GET, url)
-            .header(header::ACCEPT, "application/json")
-            .send()?
-            .error_for_status()?;
+        let status = resp.status();
+        trace!("GET '{}' status: {:?}", resp.url(), status);
+        if status == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound {
+                name: name.to_string(),
+                reference: String::new(),
+            });
+        } else if !status.is_success() {
+            return Err(Error::from_response(&reqwest::Method::GET, resp));
+        }
 
         // ensure the CONTENT_TYPE header is application/json
         let ct_hdr = resp.headers().get(header::CONTENT_TYPE).cloned();
@@ -72,52 +267,467 @@ impl Client {
             None => false,
             Some(ref ct) => ct.to_str()?.starts_with("application/json"),
         };
-        if !ok {
+        if !ok && self.strict_content_types {
+            let url = resp.url().to_string();
+            let body = resp.bytes()?;
+            return Err(Error::UnexpectedContentType {
+                expected: "application/json".to_string(),
+                actual: ct_hdr.map(|ct| String::from_utf8_lossy(ct.as_bytes()).into_owned()),
+                url,
+                body: String::from_utf8_lossy(&body[..body.len().min(BODY_SNIPPET_LEN)])
+                    .into_owned(),
+            });
+        } else if !ok {
             // TODO: Make this an error once Satellite
             // returns the content type correctly
             debug!("get_tags: wrong content type '{:?}', ignoring...", ct_hdr);
         }
 
-        // extract the response body and parse the LINK header
-        let next = parse_link(resp.headers().get(header::LINK));
-        trace!("next_page {:?}", next);
-
-        let tags_chunk = resp.json::<TagsChunk>()?;
+        let pagination = Pagination::new(url, &resp, paginate);
+        let tags_chunk: TagsChunk = serde_json::from_slice(&crate::encoding::decoded_body(resp)?)?;
+        let next = pagination.next(&tags_chunk.tags)?;
         Ok((tags_chunk, next))
     }
 }
 
-/// Parse a `Link` header.
+/// Continuation token of `get_tags_page` for the URL of the next page.
+///
+/// This is the `last` parameter if the URL has no other than `n`, else the URL.
+fn continuation_token(next: &Url) -> String {
+    let mut last = None;
+    for (key, value) in next.query_pairs() {
+        match &*key {
+            "last" => last = Some(value.into_owned()),
+            "n" => {}
+            _ => return next.to_string(),
+        }
+    }
+    last.unwrap_or_else(|| next.to_string())
+}
+
+/// Whether an error means that the repository doesn't exist.
+fn is_unknown_repository(e: &Error) -> bool {
+    match e.inner() {
+        Error::NotFound { .. } => true,
+        Error::Registry { status, errors } => match *status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => false,
+            _ => errors.iter().any(|e| e.code == ErrorCode::NameUnknown),
+        },
+        _ => false,
+    }
+}
+
+/// Parse a tag as a semantic version, tolerating a leading `v`.
+fn parse_semver(tag: &str) -> Option<semver::Version> {
+    let version = tag.strip_prefix('v').unwrap_or(tag);
+    semver::Version::parse(version).ok()
+}
+
+/// Sort tags by semantic version, lowest first.
+///
+/// Tags which don't parse as semantic versions (optionally prefixed with `v`)
+/// are removed. Tags denoting the same version, like `1.2.3` and `v1.2.3`,
+/// are ordered by their name.
+pub fn sort_semver(tags: &mut Vec<String>) {
+    let mut versions = tags
+        .drain(..)
+        .filter_map(|tag| parse_semver(&tag).map(|v| (v, tag)))
+        .collect::<Vec<_>>();
+    versions.sort();
+    tags.extend(versions.into_iter().map(|(_, tag)| tag));
+}
+
+/// Iterator fetching pages of tags on demand.
+struct TagsIter<'a> {
+    client: &'a Client,
+    name: String,
+    paginate: Option<u32>,
+    next: Option<Result<Url>>,
+    buffer: std::vec::IntoIter<String>,
+    /// Number of tags still to be yielded, if limited.
+    remaining: Option<usize>,
+}
+
+impl<'a> Iterator for TagsIter<'a> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remaining == Some(0) {
+                return None;
+            }
+
+            if let Some(tag) = self.buffer.next() {
+                if let Some(remaining) = self.remaining.as_mut() {
+                    *remaining -= 1;
+                }
+                return Some(Ok(tag));
+            }
+
+            let mut url = match self.next.take()? {
+                Ok(url) => url,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let paginate = match self.remaining {
+                Some(remaining) => {
+                    let remaining = u32::try_from(remaining).unwrap_or(u32::MAX);
+                    let n = self.paginate.map_or(remaining, |n| n.min(remaining));
+                    set_page_size(&mut url, n);
+                    Some(n)
+                }
+                None => self.paginate,
+            };
+
+            match self.client.fetch_tags_chunk(&self.name, url, paginate) {
+                Ok((tags_chunk, next)) => {
+                    self.buffer = tags_chunk.tags.into_iter();
+                    self.next = next.map(Ok);
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Set the `n` query parameter of a tags URL, keeping the other parameters.
+fn set_page_size(url: &mut Url, n: u32) {
+    let n = n.to_string();
+    let mut found = false;
+    let pairs = url
+        .query_pairs()
+        .map(|(key, value)| {
+            if key == "n" {
+                found = true;
+                (key.into_owned(), n.clone())
+            } else {
+                (key.into_owned(), value.into_owned())
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut query = url.query_pairs_mut();
+    query.clear().extend_pairs(pairs);
+    if !found {
+        query.append_pair("n", &n);
+    }
+}
+
+/// Pagination of a listing, read from a page's response before its body.
+///
+/// The URL from a `Link` header is followed as-is, resolved against the
+/// page URL. Without a `Link` header, pagination falls back to `last=`
+/// whenever a full page was returned. Pagination stops when it would
+/// request the same page again.
+pub(crate) struct Pagination {
+    url: Url,
+    link: Option<String>,
+    page_size: Option<u32>,
+}
+
+impl Pagination {
+    /// Read the `Link` header of the response to a request for `url`.
+    pub(crate) fn new(url: Url, resp: &Response, page_size: Option<u32>) -> Self {
+        let link = parse_link(resp.headers().get_all(header::LINK));
+        trace!("next_page {:?}", link);
+        Pagination {
+            url,
+            link,
+            page_size,
+        }
+    }
+
+    /// URL of the page following the one holding `items`, if any.
+    pub(crate) fn next(self, items: &[String]) -> Result<Option<Url>> {
+        let next = match (&self.link, self.page_size, items.last()) {
+            (Some(link), _, _) => self.url.join(link)?,
+            (None, Some(n), Some(last)) if items.len() == n as usize => {
+                // Registries ignoring `last` return the same page again
+                let previous = self.url.query_pairs().find(|(key, _)| key == "last");
+                if previous.is_some_and(|(_, previous)| previous == last.as_str()) {
+                    debug!("ignoring pagination not advancing past {:?}", last);
+                    return Ok(None);
+                }
+                let mut next = self.url.clone();
+                next.query_pairs_mut()
+                    .clear()
+                    .append_pair("n", &n.to_string())
+                    .append_pair("last", last);
+                next
+            }
+            _ => return Ok(None),
+        };
+        if next == self.url {
+            debug!("ignoring pagination back to {}", next);
+            return Ok(None);
+        }
+        Ok(Some(next))
+    }
+}
+
+/// Find the URI of the next page in `Link` headers.
 ///
+/// The returned URI may be relative to the request URL.
 /// Format is described at https://docs.docker.com/registry/spec/api/#listing-image-tags#pagination.
-fn parse_link(hdr: Option<&header::HeaderValue>) -> Option<String> {
-    // TODO: this a brittle string-matching parser. Investigate
-    // whether there is a a common library to do this, in the future.
-
-    // Raw Header value bytes.
-    let hval = match hdr {
-        Some(v) => v,
-        None => return None,
-    };
-
-    // Header value string.
-    let sval = match hval.to_str() {
-        Ok(v) => v.to_owned(),
-        _ => return None,
-    };
-
-    // Query parameters for next page URL.
-    let uri = sval.trim_end_matches(">; rel=\"next\"");
-    let query: Vec<&str> = uri.splitn(2, "next_page=").collect();
-    let params = match query.get(1) {
-        Some(v) if !(*v).is_empty() => v,
-        _ => return None,
-    };
-
-    // Last item in current page (pagination parameter).
-    let last: Vec<&str> = params.splitn(2, '&').collect();
-    match last.get(0).cloned() {
-        Some(v) if !v.is_empty() => Some(v.to_string()),
-        _ => None,
+fn parse_link<'a, I>(values: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a header::HeaderValue>,
+{
+    values
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(parse_link_values)
+        .find(|link| {
+            link.params.iter().any(|(key, value)| {
+                key.eq_ignore_ascii_case("rel")
+                    && value
+                        .split_whitespace()
+                        .any(|rel| rel.eq_ignore_ascii_case("next"))
+            })
+        })
+        .map(|link| link.uri)
+}
+
+/// A single link-value of a `Link` header, as specified by RFC 5988.
+#[derive(Debug, PartialEq)]
+struct Link {
+    uri: String,
+    params: Vec<(String, String)>,
+}
+
+/// Parse all comma-separated link-values of a `Link` header value.
+///
+/// Malformed link-values are skipped.
+fn parse_link_values(value: &str) -> Vec<Link> {
+    let mut links = Vec::new();
+    let mut rest = value;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        let uri = match rest.strip_prefix('<').and_then(|r| r.split_once('>')) {
+            Some((uri, tail)) => {
+                rest = tail;
+                uri
+            }
+            None => break,
+        };
+
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            match rest.strip_prefix(';') {
+                Some(tail) => rest = tail.trim_start(),
+                None => break,
+            }
+            let key_end = rest
+                .find(|c: char| c == '=' || c == ';' || c == ',' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let key = rest[..key_end].to_string();
+            rest = rest[key_end..].trim_start();
+
+            let value = match rest.strip_prefix('=') {
+                Some(tail) => {
+                    let (value, tail) = parse_param_value(tail.trim_start());
+                    rest = tail;
+                    value
+                }
+                None => String::new(),
+            };
+            if !key.is_empty() {
+                params.push((key, value));
+            }
+        }
+
+        links.push(Link {
+            uri: uri.trim().to_string(),
+            params,
+        });
+
+        // Skip anything up to the next link-value
+        match rest.find(',') {
+            Some(i) => rest = &rest[i..],
+            None => break,
+        }
+    }
+
+    links
+}
+
+/// Parse a token or quoted-string parameter value, returning it and the remaining input.
+fn parse_param_value(input: &str) -> (String, &str) {
+    if let Some(quoted) = input.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = quoted.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        value.push(escaped);
+                    }
+                }
+                '"' => return (value, &quoted[i + 1..]),
+                c => value.push(c),
+            }
+        }
+        (value, "")
+    } else {
+        let end = input
+            .find(|c: char| c == ';' || c == ',' || c.is_whitespace())
+            .unwrap_or(input.len());
+        (input[..end].to_string(), &input[end..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_filter_matches() {
+        let tags = ["v1.0.0", "v1.1.0-rc1", "v2.0.0", "latest", "sha-1234"];
+        let apply = |filter: TagFilter| {
+            tags.iter()
+                .filter(|t| filter.matches(t))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            apply(TagFilter::Prefix("v1.".into())),
+            ["v1.0.0", "v1.1.0-rc1"]
+        );
+        assert_eq!(apply(TagFilter::Suffix("-rc1".into())), ["v1.1.0-rc1"]);
+        assert_eq!(
+            apply(TagFilter::predicate(|t| !t.starts_with('v'))),
+            ["latest", "sha-1234"]
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn tag_filter_matches_regex() {
+        let filter = TagFilter::Regex(regex::Regex::new(r"^v\d+\.\d+\.\d+$").unwrap());
+        let tags = ["v1.0.0", "v1.1.0-rc1", "v2.0.0", "latest"];
+        let matching = tags
+            .iter()
+            .filter(|t| filter.matches(t))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(matching, ["v1.0.0", "v2.0.0"]);
+    }
+
+    #[test]
+    fn sort_semver_drops_other_tags() {
+        let mut tags = vec![
+            "latest",
+            "v1.10.0",
+            "sha-1234",
+            "1.2.0",
+            "v1.9.3",
+            "2.0.0-rc.1",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        sort_semver(&mut tags);
+        assert_eq!(tags, ["1.2.0", "v1.9.3", "v1.10.0", "2.0.0-rc.1"]);
+    }
+
+    #[test]
+    fn sort_semver_ties() {
+        let mut tags = vec![
+            "v1.2.3",
+            "1.2.3",
+            "1.2.3-rc.1",
+            "1.2.3+build.2",
+            "1.2.3+build.1",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        sort_semver(&mut tags);
+        assert_eq!(
+            tags,
+            [
+                "1.2.3-rc.1",
+                "1.2.3",
+                "v1.2.3",
+                "1.2.3+build.1",
+                "1.2.3+build.2"
+            ]
+        );
+    }
+
+    fn next_link(values: &[&str]) -> Option<String> {
+        let values = values
+            .iter()
+            .map(|v| header::HeaderValue::from_str(v).unwrap())
+            .collect::<Vec<_>>();
+        parse_link(&values)
+    }
+
+    #[test]
+    fn parse_link_registry() {
+        // docker.io/library/registry:2
+        assert_eq!(
+            next_link(&[r#"</v2/library/alpine/tags/list?last=3.14&n=10>; rel="next""#]),
+            Some("/v2/library/alpine/tags/list?last=3.14&n=10".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_link_ghcr() {
+        assert_eq!(
+            next_link(&[r#"</v2/acheta/app/tags/list?last=sha256-3f0b1c.sig&n=100>; rel="next""#]),
+            Some("/v2/acheta/app/tags/list?last=sha256-3f0b1c.sig&n=100".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_link_harbor() {
+        assert_eq!(
+            next_link(&[
+                r#"</v2/library/nginx/tags/list?last=1.21&n=2>; rel="next", </v2/library/nginx/tags/list?last=1.25&n=2>; rel="last""#
+            ]),
+            Some("/v2/library/nginx/tags/list?last=1.21&n=2".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_link_multiple_values_and_params() {
+        // next is not the first link, comes with extra parameters and quoted commas
+        assert_eq!(
+            next_link(&[
+                r#"<https://r.example.com/v2/a/tags/list?n=1>; rel=first; title="page, one""#,
+                r#"<https://r.example.com/v2/a/tags/list?n=1&last=b>; title="page \"two\""; REL="prev next""#,
+            ]),
+            Some("https://r.example.com/v2/a/tags/list?n=1&last=b".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_link_without_next() {
+        assert_eq!(next_link(&[]), None);
+        assert_eq!(next_link(&[r#"</v2/a/tags/list?n=1>; rel="last""#]), None);
+        assert_eq!(next_link(&["garbage"]), None);
+    }
+
+    #[test]
+    fn parse_link_values_params() {
+        assert_eq!(
+            parse_link_values(r#"<a>; rel="next"; title="x, y", <b>;rel=last"#),
+            vec![
+                Link {
+                    uri: "a".into(),
+                    params: vec![
+                        ("rel".into(), "next".into()),
+                        ("title".into(), "x, y".into())
+                    ],
+                },
+                Link {
+                    uri: "b".into(),
+                    params: vec![("rel".into(), "last".into())],
+                },
+            ]
+        );
     }
 }
@@ -0,0 +1,24 @@
+//! Tests against the real ghcr.io, run with `--features ghcr-integration`.
+#![cfg(feature = "ghcr-integration")]
+
+/// Small public image, pulled anonymously.
+const IMAGE: &str = "containerd/busybox";
+const TAG: &str = "1.36";
+
+#[test]
+fn test_pull_public_image_anonymously() {
+    let client = ghregistry::Client::configure()
+        .registry("ghcr.io")
+        .build()
+        .unwrap();
+    let scope = client.pull_scope(IMAGE);
+    let client = client.authenticate(&[scope]).unwrap();
+    assert_eq!(
+        client.auth_status().unwrap(),
+        ghregistry::AuthStatus::Anonymous
+    );
+
+    let (config, layers) = client.pull_image(IMAGE, TAG).unwrap();
+    assert_eq!(config.os, "linux");
+    assert!(!layers.is_empty());
+}
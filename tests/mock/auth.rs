@@ -0,0 +1,435 @@
+use mockito::{mock, Matcher};
+use test_case::test_case;
+
+fn credentials_client() -> ghregistry::Client {
+    ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .username(Some("deploy".into()))
+        .password(Some("secret".into()))
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_oauth2_post_fallback() {
+    let _challenge = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header(
+            "WWW-Authenticate",
+            &format!(
+                r#"Bearer realm="{}/oauth2/token",service="mock""#,
+                mockito::server_url()
+            ),
+        )
+        .create();
+    let _get = mock("GET", Matcher::Regex("^/oauth2/token".into()))
+        .with_status(405)
+        .create();
+    let post = mock("POST", "/oauth2/token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("grant_type".into(), "password".into()),
+            Matcher::UrlEncoded("username".into(), "deploy".into()),
+            Matcher::UrlEncoded("password".into(), "secret".into()),
+            Matcher::UrlEncoded("service".into(), "mock".into()),
+            Matcher::UrlEncoded("scope".into(), "repository:oauth/repo:pull".into()),
+            Matcher::UrlEncoded("client_id".into(), "ghregistry".into()),
+        ]))
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"access_token": "oauth-token", "expires_in": 300}"#)
+        .expect(1)
+        .create();
+    let tags = mock("GET", "/v2/oauth/repo/tags/list")
+        .match_header("authorization", "Bearer oauth-token")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "oauth/repo", "tags": ["t1"]}"#)
+        .create();
+
+    let client = credentials_client()
+        .authenticate(&[ghregistry::Scope::repo_pull("oauth/repo")])
+        .unwrap();
+    assert_eq!(client.get_tags("oauth/repo", None).unwrap(), vec!["t1"]);
+    post.assert();
+    tags.assert();
+}
+
+fn token_challenge(path: &str, scope: Option<&str>) -> String {
+    let mut challenge = format!(
+        r#"Bearer realm="{}{}",service="mock""#,
+        mockito::server_url(),
+        path
+    );
+    if let Some(scope) = scope {
+        challenge += &format!(r#",scope="{}""#, scope);
+    }
+    challenge
+}
+
+#[test]
+fn test_expired_token_is_refreshed_once() {
+    let scope = "repository:refresh/repo:pull";
+    let _challenge = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", &token_challenge("/refresh/token", None))
+        .create();
+    let _initial = mock("GET", "/refresh/token")
+        .match_query(Matcher::Exact("service=mock".into()))
+        .with_status(200)
+        .with_body(r#"{"token": "expired-token"}"#)
+        .create();
+    let refresh = mock("GET", "/refresh/token")
+        .match_query(Matcher::UrlEncoded("scope".into(), scope.into()))
+        .with_status(200)
+        .with_body(r#"{"token": "fresh-token"}"#)
+        .expect(1)
+        .create();
+    let _expired = mock("GET", "/v2/refresh/repo/tags/list")
+        .match_header("authorization", "Bearer expired-token")
+        .with_status(401)
+        .with_header(
+            "WWW-Authenticate",
+            &token_challenge("/refresh/token", Some(scope)),
+        )
+        .create();
+    let fresh = mock("GET", "/v2/refresh/repo/tags/list")
+        .match_header("authorization", "Bearer fresh-token")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "refresh/repo", "tags": ["t1"]}"#)
+        .expect(2)
+        .create();
+
+    let client = credentials_client().authenticate(&[]).unwrap();
+    let clone = client.clone();
+    assert_eq!(client.get_tags("refresh/repo", None).unwrap(), vec!["t1"]);
+    // The refreshed token is shared with clones
+    assert_eq!(clone.get_tags("refresh/repo", None).unwrap(), vec!["t1"]);
+    refresh.assert();
+    fresh.assert();
+}
+
+#[test]
+fn test_concurrent_requests_refresh_once() {
+    let scope = "repository:shared/repo:pull";
+    let _challenge = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", &token_challenge("/shared/token", None))
+        .create();
+    let _initial = mock("GET", "/shared/token")
+        .match_query(Matcher::Exact("service=mock".into()))
+        .with_status(200)
+        .with_body(r#"{"token": "stale-token"}"#)
+        .create();
+    let refresh = mock("GET", "/shared/token")
+        .match_query(Matcher::UrlEncoded("scope".into(), scope.into()))
+        .with_status(200)
+        .with_body(r#"{"token": "shared-token"}"#)
+        .expect(1)
+        .create();
+    let _stale = mock("GET", "/v2/shared/repo/tags/list")
+        .match_header("authorization", "Bearer stale-token")
+        .with_status(401)
+        .with_header(
+            "WWW-Authenticate",
+            &token_challenge("/shared/token", Some(scope)),
+        )
+        .create();
+    let _fresh = mock("GET", "/v2/shared/repo/tags/list")
+        .match_header("authorization", "Bearer shared-token")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "shared/repo", "tags": ["t1"]}"#)
+        .create();
+
+    let client = std::sync::Arc::new(credentials_client().authenticate(&[]).unwrap());
+    let barrier = std::sync::Barrier::new(8);
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            let client = client.clone();
+            let barrier = &barrier;
+            s.spawn(move || {
+                barrier.wait();
+                assert_eq!(client.get_tags("shared/repo", None).unwrap(), vec!["t1"]);
+            });
+        }
+    });
+    refresh.assert();
+}
+
+#[test]
+fn test_rejected_refresh_is_not_retried_again() {
+    let scope = "repository:denied/repo:pull";
+    let _challenge = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", &token_challenge("/denied/token", None))
+        .create();
+    let _token = mock("GET", Matcher::Regex("^/denied/token".into()))
+        .with_status(200)
+        .with_body(r#"{"token": "useless-token"}"#)
+        .create();
+    let tags = mock("GET", "/v2/denied/repo/tags/list")
+        .with_status(401)
+        .with_header(
+            "WWW-Authenticate",
+            &token_challenge("/denied/token", Some(scope)),
+        )
+        .expect(2)
+        .create();
+
+    let client = credentials_client().authenticate(&[]).unwrap();
+    assert!(client.get_tags("denied/repo", None).is_err());
+    tags.assert();
+}
+
+#[test]
+fn test_expiring_token_is_refreshed_proactively() {
+    let _challenge = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", &token_challenge("/expiry/token", None))
+        .create();
+    // The token is within the clock skew window right away
+    let token = mock("GET", Matcher::Regex("^/expiry/token".into()))
+        .with_status(200)
+        .with_body(r#"{"token": "short-lived", "expires_in": 10}"#)
+        .expect(2)
+        .create();
+    let tags = mock("GET", "/v2/expiry/repo/tags/list")
+        .match_header("authorization", "Bearer short-lived")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "expiry/repo", "tags": ["t1"]}"#)
+        .expect(1)
+        .create();
+
+    let client = credentials_client().authenticate(&[]).unwrap();
+    let expires_at = client.token_expires_at().unwrap();
+    assert!(expires_at <= std::time::SystemTime::now() + std::time::Duration::from_secs(10));
+
+    assert_eq!(client.get_tags("expiry/repo", None).unwrap(), vec!["t1"]);
+    token.assert();
+    tags.assert();
+}
+
+#[test_case(Some(r#"Basic realm="Registry Realm""#); "basic challenge")]
+#[test_case(None; "no challenge")]
+fn test_basic_auth_fallback(challenge: Option<&str>) {
+    let basic = format!("Basic {}", base64::encode("deploy:secret"));
+    let mut unauthorized = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401);
+    if let Some(challenge) = challenge {
+        unauthorized = unauthorized.with_header("WWW-Authenticate", challenge);
+    }
+    let _unauthorized = unauthorized.create();
+    let _authorized = mock("GET", "/v2/")
+        .match_header("authorization", basic.as_str())
+        .with_status(200)
+        .with_header("Docker-Distribution-API-Version", "registry/2.0")
+        .create();
+    let tags = mock("GET", "/v2/basic/repo/tags/list")
+        .match_header("authorization", basic.as_str())
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "basic/repo", "tags": ["t1"]}"#)
+        .create();
+
+    let client = credentials_client().authenticate(&[]).unwrap();
+    assert_eq!(
+        client.is_v2_supported_and_authorized().unwrap(),
+        (true, true)
+    );
+    assert_eq!(client.get_tags("basic/repo", None).unwrap(), vec!["t1"]);
+    tags.assert();
+}
+
+#[test]
+fn test_anonymous_token() {
+    let _challenge = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header(
+            "WWW-Authenticate",
+            &token_challenge("/anonymous/token", None),
+        )
+        .create();
+    let token = mock("GET", Matcher::Regex("^/anonymous/token".into()))
+        .match_header("authorization", Matcher::Missing)
+        .with_status(200)
+        .with_body(r#"{"token": "anonymous-token"}"#)
+        .expect(1)
+        .create();
+    let _ping = mock("GET", "/v2/")
+        .match_header("authorization", "Bearer anonymous-token")
+        .with_status(200)
+        .with_header("Docker-Distribution-API-Version", "registry/2.0")
+        .create();
+
+    let client = super::client()
+        .authenticate(&[ghregistry::Scope::repo_pull("public/repo")])
+        .unwrap();
+    assert_eq!(
+        client.auth_status().unwrap(),
+        ghregistry::AuthStatus::Anonymous
+    );
+    token.assert();
+}
+
+#[test]
+fn test_rejected_credentials_status() {
+    let _challenge = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", r#"Basic realm="Registry""#)
+        .create();
+
+    assert_eq!(
+        credentials_client().auth_status().unwrap(),
+        ghregistry::AuthStatus::CredentialsRejected
+    );
+    assert_eq!(
+        super::client()
+            .authenticate(&[])
+            .unwrap()
+            .auth_status()
+            .unwrap(),
+        ghregistry::AuthStatus::Unauthorized
+    );
+}
+
+#[test]
+fn test_identity_token_from_docker_config() {
+    let _challenge = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header(
+            "WWW-Authenticate",
+            &token_challenge("/identity/token", None),
+        )
+        .create();
+    // The identity token must not be sent as basic credentials
+    let get = mock("GET", Matcher::Regex("^/identity/token".into()))
+        .expect(0)
+        .create();
+    let post = mock("POST", "/identity/token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("grant_type".into(), "refresh_token".into()),
+            Matcher::UrlEncoded("refresh_token".into(), "identity".into()),
+            Matcher::UrlEncoded("scope".into(), "repository:hub/repo:pull".into()),
+        ]))
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"access_token": "hub-token"}"#)
+        .expect(1)
+        .create();
+    let tags = mock("GET", "/v2/hub/repo/tags/list")
+        .match_header("authorization", "Bearer hub-token")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "hub/repo", "tags": ["t1"]}"#)
+        .create();
+
+    let index = mockito::server_address().to_string();
+    let config = format!(
+        r#"{{"auths": {{"{}": {{"auth": "", "identitytoken": "identity"}}}}}}"#,
+        index
+    );
+    let client = ghregistry::Client::configure()
+        .registry(&index)
+        .insecure_registry(true)
+        .read_credentials(config.as_bytes())
+        .build()
+        .unwrap()
+        .authenticate(&[ghregistry::Scope::repo_pull("hub/repo")])
+        .unwrap();
+    assert_eq!(client.get_tags("hub/repo", None).unwrap(), vec!["t1"]);
+    get.assert();
+    post.assert();
+    tags.assert();
+}
+
+#[test]
+fn test_ping_logs_in() {
+    let _challenge = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header("WWW-Authenticate", &token_challenge("/ping/token", None))
+        .create();
+    let token = mock("GET", Matcher::Regex("^/ping/token".into()))
+        .match_header("authorization", Matcher::Regex("^Basic ".into()))
+        .with_status(200)
+        .with_body(r#"{"token": "ping-token"}"#)
+        .expect(1)
+        .create();
+    let ping = mock("GET", "/v2/")
+        .match_header("authorization", "Bearer ping-token")
+        .with_status(200)
+        .with_header("Docker-Distribution-API-Version", "registry/2.0")
+        .expect(2)
+        .create();
+
+    let client = credentials_client();
+    client.ping().unwrap();
+    // Clones share the token, and pinging again doesn't log in again
+    client.clone().ping().unwrap();
+    token.assert();
+    ping.assert();
+}
+
+#[test]
+fn test_ping_short_lived_token() {
+    let _challenge = mock("GET", "/v2/")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header("WWW-Authenticate", &token_challenge("/ping/short", None))
+        .create();
+    // Tokens expiring this soon are refreshed before every request
+    let token = mock("GET", Matcher::Regex("^/ping/short".into()))
+        .with_status(200)
+        .with_body(r#"{"token": "short-token", "expires_in": 10}"#)
+        .expect_at_least(1)
+        .create();
+    let _ping = mock("GET", "/v2/")
+        .match_header("authorization", "Bearer short-token")
+        .with_status(200)
+        .with_header("Docker-Distribution-API-Version", "registry/2.0")
+        .create();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || sender.send(credentials_client().ping()).unwrap());
+    let result = receiver
+        .recv_timeout(std::time::Duration::from_secs(10))
+        .expect("ping didn't return");
+    result.unwrap();
+    token.assert();
+}
+
+#[test]
+fn test_ping_rejected() {
+    let _challenge = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header("WWW-Authenticate", r#"Basic realm="Registry""#)
+        .with_header("Docker-Distribution-API-Version", "registry/2.0")
+        .create();
+
+    for client in [credentials_client(), super::client()] {
+        let err = client.ping().unwrap_err();
+        assert!(err.is_auth(), "{:?}", err);
+    }
+}
+
+#[test]
+fn test_ping_not_a_registry() {
+    let _root = mock("GET", "/v2/")
+        .with_status(200)
+        .with_body("<html>hello</html>")
+        .create();
+
+    let err = super::client().ping().unwrap_err();
+    assert!(
+        matches!(err, ghregistry::errors::Error::V2NotSupported),
+        "{:?}",
+        err
+    );
+}
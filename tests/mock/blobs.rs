@@ -0,0 +1,583 @@
+use super::{client, digest};
+use ghregistry::errors::Error;
+use mockito::mock;
+use test_case::test_case;
+
+/// Name of the file a blob is downloaded to.
+fn file_name(digest: &str) -> String {
+    digest.replacen(':', "-", 1)
+}
+
+fn head_blob(status: usize) -> ghregistry::errors::Result<bool> {
+    let digest = digest(format!("blob for status {}", status).as_bytes());
+    let _m = mock("HEAD", format!("/v2/blobs/repo/blobs/{}", digest).as_str())
+        .with_status(status)
+        .create();
+
+    client().has_blob("blobs/repo", &digest)
+}
+
+#[test]
+fn test_has_blob_present_and_missing() {
+    assert!(head_blob(200).unwrap());
+    assert!(!head_blob(404).unwrap());
+}
+
+#[test_case(401; "unauthorized")]
+#[test_case(403; "forbidden")]
+fn test_has_blob_auth_errors(status: usize) {
+    match head_blob(status).as_ref().map_err(Error::inner) {
+        Err(Error::Unauthorized(s)) => assert_eq!(s.as_u16() as usize, status),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test_case(500; "internal server error")]
+#[test_case(503; "service unavailable")]
+fn test_has_blob_server_errors(status: usize) {
+    match head_blob(status).as_ref().map_err(Error::inner) {
+        Err(Error::UnexpectedHttpStatus(s)) => assert_eq!(s.as_u16() as usize, status),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_blob_size() {
+    let blob = b"sized blob";
+    let digest = digest(blob);
+    let _m = mock("HEAD", format!("/v2/size/repo/blobs/{}", digest).as_str())
+        .with_status(200)
+        .with_header("Content-Length", "4096")
+        .create();
+    let missing = super::digest(b"missing blob");
+    let _missing = mock("HEAD", format!("/v2/size/repo/blobs/{}", missing).as_str())
+        .with_status(404)
+        .create();
+
+    let client = client();
+    assert_eq!(client.blob_size("size/repo", &digest).unwrap(), Some(4096));
+    assert_eq!(client.blob_size("size/repo", &missing).unwrap(), None);
+}
+
+#[test]
+fn test_get_missing_blob_is_not_found() {
+    let digest = digest(b"missing blob");
+    let _m = mock("GET", format!("/v2/blobs/repo/blobs/{}", digest).as_str())
+        .with_status(404)
+        .create();
+
+    match client().get_blob("blobs/repo", &digest) {
+        Err(Error::NotFound { name, reference }) => {
+            assert_eq!(name, "blobs/repo");
+            assert_eq!(reference, digest);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_which_blobs_missing() {
+    let digests = (0..20)
+        .map(|i| digest(format!("layer {}", i).as_bytes()))
+        .collect::<Vec<_>>();
+    let _mocks = digests
+        .iter()
+        .enumerate()
+        .map(|(i, digest)| {
+            mock("HEAD", format!("/v2/batch/repo/blobs/{}", digest).as_str())
+                .with_status(if i % 3 == 0 { 404 } else { 200 })
+                .create()
+        })
+        .collect::<Vec<_>>();
+
+    let refs = digests.iter().map(String::as_str).collect::<Vec<_>>();
+    let missing = client().which_blobs_missing("batch/repo", &refs).unwrap();
+    let expected = digests.iter().step_by(3).cloned().collect::<Vec<_>>();
+    assert_eq!(missing, expected);
+    assert!(client()
+        .which_blobs_missing("batch/repo", &[])
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_download_connection_failed() {
+    // Nothing listens on the discard port
+    let client = ghregistry::Client::configure()
+        .registry("127.0.0.1:9")
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    let result =
+        client.get_blob_with_progress_file("blobs/repo", digest(b"blob"), None, None, dir.path());
+    let e = result.unwrap_err();
+    assert!(
+        matches!(e.inner(), Error::ConnectionFailed(_)),
+        "unexpected error: {:?}",
+        e
+    );
+    assert!(e.is_retryable());
+    assert!(e
+        .to_string()
+        .starts_with("GET http://127.0.0.1:9/v2/blobs/repo/blobs/"));
+}
+
+#[test]
+fn test_blob_with_size_hint() {
+    let blob = b"sized blob".to_vec();
+    let blob_digest = digest(&blob);
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/sized/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .expect(3)
+    .create();
+
+    // Wrong or absurd hints only change the initial allocation
+    for size in [Some(blob.len() as u64), Some(1), Some(u64::MAX)] {
+        let received = client()
+            .get_blob_with_progress("blobs/sized", &blob_digest, size, None)
+            .unwrap();
+        assert_eq!(received, blob);
+    }
+}
+
+#[test]
+fn test_progress_receiver_dropped() {
+    let blob = b"progress receiver gone".to_vec();
+    let blob_digest = digest(&blob);
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/progress/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .create();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    drop(receiver);
+    let received = client()
+        .get_blob_with_progress("blobs/progress", &blob_digest, None, Some(sender))
+        .unwrap();
+    assert_eq!(received, blob);
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    drop(receiver);
+    let dir = tempfile::tempdir().unwrap();
+    let path = client()
+        .get_blob_with_progress_file(
+            "blobs/progress",
+            &blob_digest,
+            None,
+            Some(sender),
+            dir.path(),
+        )
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), blob);
+}
+
+#[test]
+fn test_get_blob_by_content_digest() {
+    let blob = b"typed digest".to_vec();
+    let blob_digest = ghregistry::ContentDigest::from_bytes(&blob);
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/typed/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .create();
+
+    assert_eq!(
+        client().get_blob("blobs/typed", &blob_digest).unwrap(),
+        blob
+    );
+}
+
+#[test]
+fn test_get_blob_verified_returns_digest() {
+    let blob = b"indexed blob".to_vec();
+    let blob_digest = digest(&blob);
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/verified/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .create();
+
+    let (content, verified) = client()
+        .get_blob_verified("blobs/verified", &blob_digest)
+        .unwrap();
+    assert_eq!(content, blob);
+    assert_eq!(verified.as_ref(), blob_digest);
+    assert_eq!(verified.algorithm(), ghregistry::DigestAlgorithm::Sha256);
+}
+
+#[test]
+fn test_get_blob_sha512() {
+    use sha2::Digest;
+
+    let blob = b"large artifact".to_vec();
+    let blob_digest = format!("sha512:{:x}", sha2::Sha512::digest(&blob));
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/sha512/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .expect(2)
+    .create();
+
+    let client = client();
+    assert_eq!(client.get_blob("blobs/sha512", &blob_digest).unwrap(), blob);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = client
+        .get_blob_with_progress_file("blobs/sha512", &blob_digest, None, None, dir.path())
+        .unwrap();
+    assert_eq!(path, dir.path().join(file_name(&blob_digest)));
+    assert_eq!(std::fs::read(path).unwrap(), blob);
+}
+
+#[test_case(206; "partial content")]
+#[test_case(200; "range ignored")]
+fn test_resume_download(status: usize) {
+    let blob = b"resumable blob content".to_vec();
+    let blob_digest = digest(&blob);
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join(file_name(&blob_digest)), &blob[..8]).unwrap();
+
+    let body = if status == 206 { &blob[8..] } else { &blob[..] };
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/resume{}/blobs/{}", status, blob_digest).as_str(),
+    )
+    .match_header("range", format!("bytes=8-{}", blob.len()).as_str())
+    .with_status(status)
+    .with_body(body)
+    .create();
+
+    let path = client()
+        .get_blob_with_progress_file(
+            &format!("blobs/resume{}", status),
+            &blob_digest,
+            Some(blob.len() as u64),
+            None,
+            dir.path(),
+        )
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), blob);
+}
+
+#[test]
+fn test_complete_download_is_not_fetched_again() {
+    let blob = b"already downloaded".to_vec();
+    let blob_digest = digest(&blob);
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join(file_name(&blob_digest)), &blob).unwrap();
+    let m = mock(
+        "GET",
+        format!("/v2/blobs/complete/blobs/{}", blob_digest).as_str(),
+    )
+    .expect(0)
+    .create();
+
+    let path = client()
+        .get_blob_with_progress_file(
+            "blobs/complete",
+            &blob_digest,
+            Some(blob.len() as u64),
+            None,
+            dir.path(),
+        )
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), blob);
+    m.assert();
+}
+
+#[test]
+fn test_download_file_named_after_canonical_digest() {
+    let blob = b"spelled differently".to_vec();
+    let blob_digest = digest(&blob);
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/canonical/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .create();
+    let dir = tempfile::tempdir().unwrap();
+
+    let path = client()
+        .get_blob_with_progress_file(
+            "blobs/canonical",
+            format!(" {}\n", blob_digest.to_uppercase()),
+            None,
+            None,
+            dir.path(),
+        )
+        .unwrap();
+    assert_eq!(path, dir.path().join(file_name(&blob_digest)));
+    assert_eq!(std::fs::read(path).unwrap(), blob);
+}
+
+#[test]
+fn test_get_blob_stream() {
+    use std::io::Read;
+
+    let blob = b"streamed blob".to_vec();
+    let blob_digest = digest(&blob);
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/stream/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .create();
+
+    let mut content = Vec::new();
+    client()
+        .get_blob_stream("blobs/stream", &blob_digest)
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    assert_eq!(content, blob);
+}
+
+#[test]
+fn test_get_blob_stream_verifies_at_end() {
+    use std::io::Read;
+
+    let blob_digest = digest(b"expected blob");
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/stream-bad/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body("tampered blob")
+    .create();
+
+    let mut content = Vec::new();
+    let err = client()
+        .get_blob_stream("blobs/stream-bad", &blob_digest)
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_interrupted_download_keeps_partial_file() {
+    use std::io::{Read, Write};
+
+    let blob = b"a blob which is cut off halfway".to_vec();
+    let blob_digest = digest(&blob);
+    let sent = 12;
+
+    // Announce the whole blob but close the connection early
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let body = blob[..sent].to_vec();
+    let len = blob.len();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0; 1024];
+        let _ = stream.read(&mut request).unwrap();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            len
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+    });
+
+    let client = ghregistry::Client::configure()
+        .registry(&address.to_string())
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let result = client.get_blob_with_progress_file(
+        "blobs/interrupted",
+        &blob_digest,
+        Some(blob.len() as u64),
+        None,
+        dir.path(),
+    );
+    server.join().unwrap();
+
+    let err = result.unwrap_err();
+    assert!(err.is_retryable());
+    match err {
+        Error::DownloadInterrupted {
+            partial,
+            downloaded,
+            ..
+        } => {
+            assert_eq!(partial, dir.path().join(file_name(&blob_digest)));
+            assert_eq!(downloaded, sent as u64);
+            assert_eq!(std::fs::read(partial).unwrap(), &blob[..sent]);
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_interrupted_download_in_memory() {
+    use std::io::{Read, Write};
+
+    let blob = b"a blob which is cut off halfway".to_vec();
+    let blob_digest = digest(&blob);
+
+    // Announce the whole blob but close the connection early
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let body = blob[..12].to_vec();
+    let len = blob.len();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read(&mut [0; 1024]).unwrap();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            len
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+    });
+
+    let client = ghregistry::Client::configure()
+        .registry(&address.to_string())
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+    let err = client
+        .get_blob_with_progress("blobs/interrupted", &blob_digest, None, None)
+        .unwrap_err();
+    server.join().unwrap();
+
+    // Not mistaken for a corrupt blob
+    assert!(matches!(err.inner(), Error::Body(_)), "{:?}", err);
+    assert!(err.is_retryable());
+}
+
+#[test]
+fn test_download_to_bad_target_dir() {
+    let blob = b"blob for a bad target".to_vec();
+    let blob_digest = digest(&blob);
+    let _m = mock(
+        "GET",
+        format!("/v2/blobs/bad-target/blobs/{}", blob_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .create();
+    let dir = tempfile::tempdir().unwrap();
+
+    // The target directory is a file
+    let file = dir.path().join("file");
+    std::fs::write(&file, b"").unwrap();
+    match client().get_blob_with_progress_file("blobs/bad-target", &blob_digest, None, None, &file)
+    {
+        Err(Error::FileIo { path, .. }) => assert_eq!(path, file),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // The target file is a directory
+    let target = dir.path().join(file_name(&blob_digest));
+    std::fs::create_dir(&target).unwrap();
+    match client().get_blob_with_progress_file(
+        "blobs/bad-target",
+        &blob_digest,
+        None,
+        None,
+        dir.path(),
+    ) {
+        Err(Error::FileIo { path, .. }) => assert_eq!(path, target),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_blob_redirect_drops_authorization() {
+    let blob = b"served by object storage".to_vec();
+    let blob_digest = digest(&blob);
+    let port = mockito::server_address().port();
+    let _redirect = mock(
+        "GET",
+        format!("/v2/blobs/redirect/blobs/{}", blob_digest).as_str(),
+    )
+    .match_header("authorization", "Bearer registry-token")
+    .with_status(307)
+    .with_header(
+        "Location",
+        &format!("http://localhost:{}/storage/blob?signature=abc", port),
+    )
+    .create();
+    // Another host name for the mock server stands for the storage host
+    let storage = mock("GET", "/storage/blob?signature=abc")
+        .match_header("authorization", mockito::Matcher::Missing)
+        .with_status(200)
+        .with_body(&blob)
+        .create();
+
+    let client = ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .bearer_token("registry-token")
+        .build()
+        .unwrap();
+    assert_eq!(
+        client.get_blob("blobs/redirect", &blob_digest).unwrap(),
+        blob
+    );
+    storage.assert();
+}
+
+#[test]
+fn test_max_redirects() {
+    let blob = b"redirected twice".to_vec();
+    let blob_digest = digest(&blob);
+    let path = format!("/v2/blobs/hops/blobs/{}", blob_digest);
+    let _first = mock("GET", path.as_str())
+        .with_status(302)
+        .with_header("Location", "/hop")
+        .create();
+    let _second = mock("GET", "/hop")
+        .with_status(302)
+        .with_header("Location", "/storage/hops")
+        .create();
+    let _storage = mock("GET", "/storage/hops")
+        .with_status(200)
+        .with_body(&blob)
+        .create();
+
+    let configure = |max_redirects| {
+        ghregistry::Client::configure()
+            .registry(&mockito::server_address().to_string())
+            .insecure_registry(true)
+            .max_redirects(max_redirects)
+            .build()
+            .unwrap()
+    };
+    assert_eq!(
+        configure(2).get_blob("blobs/hops", &blob_digest).unwrap(),
+        blob
+    );
+    let e = configure(1)
+        .get_blob("blobs/hops", &blob_digest)
+        .unwrap_err();
+    assert!(matches!(e.inner(), Error::Redirect(_)), "{:?}", e);
+    // Not following redirects at all returns the redirect response
+    let e = configure(0)
+        .get_blob("blobs/hops", &blob_digest)
+        .unwrap_err();
+    assert!(!matches!(e.inner(), Error::Redirect(_)), "{:?}", e);
+    assert_eq!(e.status(), Some(reqwest::StatusCode::FOUND), "{:?}", e);
+}
@@ -0,0 +1,75 @@
+use crate::digest;
+use mockito::mock;
+
+fn cached_client(dir: &std::path::Path) -> ghregistry::Client {
+    ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .cache_dir(dir.to_path_buf())
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_blob_served_from_cache() {
+    let blob = b"cached layer".to_vec();
+    let digest = digest(&blob);
+    let hex = digest.trim_start_matches("sha256:");
+    let cache = tempfile::tempdir().unwrap();
+
+    let m = mock("GET", format!("/v2/cache/repo/blobs/{}", digest).as_str())
+        .with_status(200)
+        .with_body(&blob)
+        .expect(1)
+        .create();
+
+    let client = cached_client(cache.path());
+    assert_eq!(client.get_blob("cache/repo", &digest).unwrap(), blob);
+    assert_eq!(client.get_blob("cache/repo", &digest).unwrap(), blob);
+    assert_eq!(
+        client
+            .get_blob_with_progress("cache/repo", &digest, None, None)
+            .unwrap(),
+        blob
+    );
+
+    let target = tempfile::tempdir().unwrap();
+    let path = client
+        .get_blob_with_progress_file("other/repo", &digest, None, None, target.path())
+        .unwrap();
+    assert_eq!(std::fs::read(path).unwrap(), blob);
+
+    m.assert();
+    assert_eq!(
+        std::fs::read(cache.path().join("sha256").join(hex)).unwrap(),
+        blob
+    );
+}
+
+#[test]
+fn test_corrupt_cache_entry_is_refetched() {
+    let blob = b"fresh layer".to_vec();
+    let digest = digest(&blob);
+    let hex = digest.trim_start_matches("sha256:");
+    let cache = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(cache.path().join("sha256")).unwrap();
+    std::fs::write(cache.path().join("sha256").join(hex), b"garbage").unwrap();
+
+    let m = mock(
+        "GET",
+        format!("/v2/cache/corrupt/blobs/{}", digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&blob)
+    .expect(1)
+    .create();
+
+    let client = cached_client(cache.path());
+    assert_eq!(client.get_blob("cache/corrupt", &digest).unwrap(), blob);
+
+    m.assert();
+    assert_eq!(
+        std::fs::read(cache.path().join("sha256").join(hex)).unwrap(),
+        blob
+    );
+}
@@ -0,0 +1,75 @@
+use super::client;
+use mockito::{mock, Matcher};
+
+#[test]
+fn test_catalog_paginated() {
+    let _m1 = mock("GET", "/v2/_catalog?n=2")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_header("Link", r#"</v2/_catalog?n=2&last=b>; rel="next""#)
+        .with_body(r#"{"repositories": ["a", "b"]}"#)
+        .create();
+    let _m2 = mock("GET", "/v2/_catalog?n=2&last=b")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"repositories": ["c"]}"#)
+        .create();
+
+    let repositories = client().get_catalog(Some(2)).unwrap();
+    assert_eq!(repositories, vec!["a", "b", "c"]);
+}
+
+#[test]
+fn test_catalog_reauthenticates_with_catalog_scope() {
+    let _unauthorized = mock("GET", "/v2/_catalog?n=10")
+        .match_header("authorization", Matcher::Missing)
+        .with_status(401)
+        .with_header("Content-Type", "application/json")
+        .with_body(
+            r#"{"errors": [{"code": "UNAUTHORIZED", "message": "authentication required"}]}"#,
+        )
+        .create();
+    let _challenge = mock("GET", "/v2/")
+        .with_status(401)
+        .with_header(
+            "WWW-Authenticate",
+            &format!(
+                r#"Bearer realm="{}/token",service="mock""#,
+                mockito::server_url()
+            ),
+        )
+        .create();
+    let token = mock("GET", "/token")
+        .match_query(Matcher::UrlEncoded(
+            "scope".into(),
+            "registry:catalog:*".into(),
+        ))
+        .with_status(200)
+        .with_body(r#"{"token": "catalog-token"}"#)
+        .expect(1)
+        .create();
+    let _authorized = mock("GET", "/v2/_catalog?n=10")
+        .match_header("authorization", "Bearer catalog-token")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"repositories": ["private/repo"]}"#)
+        .create();
+
+    let repositories = client().get_catalog(Some(10)).unwrap();
+    assert_eq!(repositories, vec!["private/repo"]);
+    token.assert();
+}
+
+#[test]
+fn test_catalog_unsupported() {
+    let _m = mock("GET", "/v2/_catalog")
+        .with_status(404)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"errors": [{"code": "NOT_FOUND", "message": "not found"}]}"#)
+        .create();
+
+    match client().get_catalog(None) {
+        Err(ghregistry::errors::Error::UnsupportedOperation(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -0,0 +1,239 @@
+use mockito::mock;
+
+#[test]
+fn test_injected_http_client_is_used() {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("x-injected", "yes".parse().unwrap());
+    let http_client = reqwest::blocking::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap();
+
+    let m = mock("GET", "/v2/injected/repo/tags/list")
+        .match_header("x-injected", "yes")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "injected/repo", "tags": ["t1"]}"#)
+        .create();
+
+    let client = ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .with_http_client(http_client)
+        .build()
+        .unwrap();
+
+    assert_eq!(client.get_tags("injected/repo", None).unwrap(), vec!["t1"]);
+    m.assert();
+}
+
+#[test]
+fn test_bearer_token_skips_challenge() {
+    let m = mock("GET", "/v2/token/repo/tags/list")
+        .match_header("authorization", "Bearer ghs_static")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "token/repo", "tags": ["t1"]}"#)
+        .create();
+
+    // No challenge endpoint is mocked, so any login attempt would fail
+    let client = ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .username(Some("user".into()))
+        .password(Some("password".into()))
+        .bearer_token("ghs_static")
+        .build()
+        .unwrap()
+        .authenticate(&[ghregistry::Scope::repo_pull("token/repo")])
+        .unwrap();
+
+    assert!(!format!("{:?}", client).contains("ghs_static"));
+    assert_eq!(client.get_tags("token/repo", None).unwrap(), vec!["t1"]);
+    m.assert();
+}
+
+#[derive(Default)]
+struct CountingObserver {
+    requests: std::sync::Mutex<Vec<String>>,
+    responses: std::sync::Mutex<Vec<(u16, Option<u64>)>>,
+}
+
+impl ghregistry::Observer for CountingObserver {
+    fn on_request(&self, method: &reqwest::Method, url: &reqwest::Url) {
+        self.requests
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", method, url.path()));
+    }
+
+    fn on_response(
+        &self,
+        status: reqwest::StatusCode,
+        bytes: Option<u64>,
+        _elapsed: std::time::Duration,
+    ) {
+        self.responses
+            .lock()
+            .unwrap()
+            .push((status.as_u16(), bytes));
+    }
+}
+
+#[test]
+fn test_observer_sees_requests() {
+    let body = r#"{"name": "observed/repo", "tags": ["t1"]}"#;
+    let _m = mock("GET", "/v2/observed/repo/tags/list")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(body)
+        .create();
+
+    let observer = std::sync::Arc::new(CountingObserver::default());
+    let client = ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .observer(observer.clone())
+        .build()
+        .unwrap();
+
+    assert_eq!(client.get_tags("observed/repo", None).unwrap(), vec!["t1"]);
+    assert_eq!(
+        *observer.requests.lock().unwrap(),
+        vec!["GET /v2/observed/repo/tags/list"]
+    );
+    assert_eq!(
+        *observer.responses.lock().unwrap(),
+        vec![(200, Some(body.len() as u64))]
+    );
+}
+
+#[test]
+fn test_accept_encoding_decodes_tags_but_not_blobs() {
+    use std::io::Write;
+
+    let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+    encoder
+        .write_all(br#"{"name": "encoded/repo", "tags": ["t1"]}"#)
+        .unwrap();
+    let gzipped = encoder.finish().into_result().unwrap();
+    let tags = mock("GET", "/v2/encoded/repo/tags/list")
+        .match_header("accept-encoding", "gzip")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_header("Content-Encoding", "gzip")
+        .with_body(gzipped)
+        .create();
+
+    // Layers are already compressed and must hash as served
+    let layer = b"\x1f\x8b layer bytes";
+    let digest = super::digest(layer);
+    let blob = mock("GET", format!("/v2/encoded/repo/blobs/{}", digest).as_str())
+        .match_header("accept-encoding", "identity")
+        .with_status(200)
+        .with_body(layer)
+        .create();
+
+    let client = ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .accept_encoding(true)
+        .build()
+        .unwrap();
+
+    assert_eq!(client.get_tags("encoded/repo", None).unwrap(), vec!["t1"]);
+    assert_eq!(client.get_blob("encoded/repo", &digest).unwrap(), layer);
+    tags.assert();
+    blob.assert();
+}
+
+#[test]
+fn test_new_for_test() {
+    let m = mock("GET", "/prefix/v2/fixture/repo/tags/list")
+        .match_header("authorization", "Bearer fixture-token")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "fixture/repo", "tags": ["t1"]}"#)
+        .create();
+
+    let client = ghregistry::Client::new_for_test(
+        format!("{}/prefix/", mockito::server_url()),
+        Some("fixture-token".into()),
+    )
+    .unwrap();
+    assert_eq!(client.get_tags("fixture/repo", None).unwrap(), vec!["t1"]);
+    m.assert();
+}
+
+#[test]
+fn test_credentials_from_env() {
+    std::env::set_var("GHREGISTRY_MOCK_ENV_TOKEN", "env-token");
+
+    let m = mock("GET", "/v2/env/repo/tags/list")
+        .match_header("authorization", "Bearer env-token")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "env/repo", "tags": ["t1"]}"#)
+        .create();
+    let client = ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .credentials_from_env_prefixed("GHREGISTRY_MOCK_ENV_")
+        .build()
+        .unwrap();
+    assert_eq!(client.get_tags("env/repo", None).unwrap(), vec!["t1"]);
+    m.assert();
+
+    // Explicit credentials win over the environment
+    let m = mock("GET", "/v2/env/repo/tags/list")
+        .match_header("authorization", "Bearer explicit-token")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "env/repo", "tags": ["t2"]}"#)
+        .create();
+    let client = ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .bearer_token("explicit-token")
+        .credentials_from_env_prefixed("GHREGISTRY_MOCK_ENV_")
+        .build()
+        .unwrap();
+    assert_eq!(client.get_tags("env/repo", None).unwrap(), vec!["t2"]);
+    m.assert();
+
+    std::env::remove_var("GHREGISTRY_MOCK_ENV_TOKEN");
+}
+
+#[test]
+fn test_user_agent_default_and_override() {
+    let tags = |ua: &str| {
+        mock("GET", "/v2/agent/repo/tags/list")
+            .match_header("user-agent", ua)
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(r#"{"name": "agent/repo", "tags": ["t1"]}"#)
+            .create()
+    };
+    let configure = || {
+        ghregistry::Client::configure()
+            .registry(&mockito::server_address().to_string())
+            .insecure_registry(true)
+    };
+
+    let m = tags(ghregistry::USER_AGENT);
+    let client = configure().build().unwrap();
+    client.get_tags("agent/repo", None).unwrap();
+    m.assert();
+
+    let m = tags("mytool/1.0");
+    let client = configure().user_agent("mytool/1.0").build().unwrap();
+    client.get_tags("agent/repo", None).unwrap();
+    m.assert();
+
+    let m = tags("mytool-sync/1.0");
+    client
+        .with_user_agent("mytool-sync/1.0")
+        .get_tags("agent/repo", None)
+        .unwrap();
+    m.assert();
+}
@@ -0,0 +1,107 @@
+use super::{client, digest};
+use ghregistry::manifest::Descriptor;
+use mockito::mock;
+
+const CONFIG: &[u8] = br#"{"architecture": "amd64", "os": "linux"}"#;
+const LAYERS: [&[u8]; 2] = [b"base layer", b"app layer"];
+const FOREIGN: &[u8] = b"windows base";
+
+fn mock_manifest(name: &str) -> mockito::Mock {
+    let mut layers = vec![serde_json::json!({
+        "mediaType": "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip",
+        "size": FOREIGN.len(),
+        "digest": digest(FOREIGN),
+        "urls": ["https://example.com/windows.tar.gz"],
+    })];
+    layers.extend(LAYERS.iter().map(|layer| {
+        serde_json::json!({
+            "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+            "size": layer.len(),
+            "digest": digest(layer),
+        })
+    }));
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {
+            "mediaType": "application/vnd.docker.container.image.v1+json",
+            "size": CONFIG.len(),
+            "digest": digest(CONFIG),
+        },
+        "layers": layers,
+    });
+
+    mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .with_body(manifest.to_string())
+        .create()
+}
+
+/// Mock `HEAD` on the destination blobs, the first `present` of which exist.
+fn mock_destination(name: &str, present: usize) -> Vec<mockito::Mock> {
+    std::iter::once(CONFIG)
+        .chain(LAYERS)
+        .enumerate()
+        .map(|(i, blob)| {
+            mock(
+                "HEAD",
+                format!("/v2/{}/blobs/{}", name, digest(blob)).as_str(),
+            )
+            .with_status(if i < present { 200 } else { 404 })
+            .expect(1)
+            .create()
+        })
+        .collect()
+}
+
+fn digests(descriptors: &[Descriptor]) -> Vec<String> {
+    descriptors.iter().map(|d| d.digest.clone()).collect()
+}
+
+#[test]
+fn test_plan_copy_same_registry() {
+    let _m = mock_manifest("copy/source");
+    let dest = mock_destination("copy/mount", 1);
+
+    let client = client();
+    let plan = client
+        .plan_copy("copy/source", "latest", &client, "copy/mount")
+        .unwrap();
+    assert_eq!(digests(&plan.already_present), vec![digest(CONFIG)]);
+    assert_eq!(
+        digests(&plan.to_mount),
+        vec![digest(LAYERS[0]), digest(LAYERS[1])]
+    );
+    assert!(plan.to_upload.is_empty());
+    assert_eq!(plan.total_bytes, 0);
+    dest.iter().for_each(|m| m.assert());
+}
+
+#[test]
+fn test_plan_copy_other_registry() {
+    let _m = mock_manifest("copy/source");
+    let dest = mock_destination("copy/upload", 2);
+
+    // The same server under another name, so blobs can't be mounted
+    let destination = ghregistry::Client::configure()
+        .registry(&format!("localhost:{}", mockito::server_address().port()))
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+    let plan = client()
+        .plan_copy("copy/source", "latest", &destination, "copy/upload")
+        .unwrap();
+    assert_eq!(
+        digests(&plan.already_present),
+        vec![digest(CONFIG), digest(LAYERS[0])]
+    );
+    assert_eq!(digests(&plan.to_upload), vec![digest(LAYERS[1])]);
+    assert_eq!(plan.to_upload[0].size, LAYERS[1].len() as u64);
+    assert!(plan.to_mount.is_empty());
+    assert_eq!(plan.total_bytes, LAYERS[1].len() as u64);
+    dest.iter().for_each(|m| m.assert());
+}
@@ -0,0 +1,67 @@
+use super::client;
+use ghregistry::errors::Error;
+use mockito::mock;
+use std::io::{Read, Write};
+
+#[test]
+fn test_undecodable_body() {
+    let _m = mock("GET", "/v2/_catalog")
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body("{not json")
+        .create();
+
+    let e = client().get_catalog(None).unwrap_err();
+    assert!(
+        matches!(e.inner(), Error::Body(_)),
+        "unexpected error: {:?}",
+        e
+    );
+    assert!(!e.is_retryable());
+}
+
+#[test]
+fn test_truncated_body() {
+    // Announce a longer body than sent and close the connection
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read(&mut [0; 1024]).unwrap();
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\n{\"repo",
+            )
+            .unwrap();
+    });
+
+    let client = ghregistry::Client::configure()
+        .registry(&address.to_string())
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+    let e = client.get_catalog(None).unwrap_err();
+    server.join().unwrap();
+    assert!(
+        matches!(e.inner(), Error::Body(_)),
+        "unexpected error: {:?}",
+        e
+    );
+    assert!(e.is_retryable());
+}
+
+#[test]
+fn test_redirect_loop() {
+    let _m = mock("GET", "/v2/_catalog")
+        .with_status(302)
+        .with_header("Location", "/v2/_catalog")
+        .create();
+
+    let e = client().get_catalog(None).unwrap_err();
+    assert!(
+        matches!(e.inner(), Error::Redirect(_)),
+        "unexpected error: {:?}",
+        e
+    );
+    assert!(!e.is_retryable());
+}
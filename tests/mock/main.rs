@@ -0,0 +1,52 @@
+//! Tests running the client against a mock registry.
+
+mod auth;
+mod blobs;
+mod cache;
+mod catalog;
+mod config;
+mod copy;
+mod errors;
+mod manifest_raw;
+mod mirrors;
+mod pull;
+mod ratelimit;
+mod referrers;
+mod save;
+mod tags;
+mod v2;
+
+/// Build a client talking plain HTTP to the mock server.
+fn client() -> ghregistry::Client {
+    ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .build()
+        .unwrap()
+}
+
+/// Compute the digest of some content.
+fn digest(data: &[u8]) -> String {
+    use sha2::Digest;
+    format!("sha256:{:x}", sha2::Sha256::digest(data))
+}
+
+/// Build a tar archive of regular files.
+fn layer_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, path, *content).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Gzip-compress a layer tarball.
+fn gzip(data: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+    encoder.write_all(data).unwrap();
+    encoder.finish().into_result().unwrap()
+}
@@ -0,0 +1,201 @@
+use super::client;
+use ghregistry::errors::Error;
+use ghregistry::ContentDigestError;
+use mockito::mock;
+use sha2::Digest;
+use test_case::test_case;
+
+static MANIFEST: &str = r#"{
+   "schemaVersion": 2,
+   "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+   "config": {"mediaType": "application/vnd.docker.container.image.v1+json", "size": 2, "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"},
+   "layers": []
+}"#;
+
+fn manifest_digest() -> String {
+    format!("sha256:{:x}", sha2::Sha256::digest(MANIFEST.as_bytes()))
+}
+
+#[test]
+fn test_get_raw_manifest_is_byte_identical() {
+    let digest = format!("sha256:{:x}", sha2::Sha256::digest(MANIFEST.as_bytes()));
+    let media_type = "application/vnd.docker.distribution.manifest.v2+json";
+
+    let _m = mock("GET", "/v2/raw/src/manifests/latest")
+        .with_status(200)
+        .with_header("Content-Type", media_type)
+        .with_header("Docker-Content-Digest", &digest)
+        .with_body(MANIFEST)
+        .create();
+
+    let (body, content_type, content_digest) =
+        client().get_raw_manifest("raw/src", "latest").unwrap();
+
+    assert_eq!(body, MANIFEST.as_bytes());
+    assert_eq!(content_type, media_type);
+    assert_eq!(content_digest, digest);
+}
+
+#[test]
+fn test_get_raw_manifest_digest_survives_copy() {
+    let media_type = "application/vnd.docker.distribution.manifest.v2+json";
+
+    let _src = mock("GET", "/v2/raw/copy-src/manifests/v1")
+        .with_status(200)
+        .with_header("Content-Type", media_type)
+        .with_body(MANIFEST)
+        .create();
+
+    let client = client();
+    let (body, content_type, digest) = client.get_raw_manifest("raw/copy-src", "v1").unwrap();
+
+    // Serve the very same bytes from another repository and make sure the
+    // digest the client computes does not change.
+    let _dst = mock("GET", "/v2/raw/copy-dst/manifests/v1")
+        .with_status(200)
+        .with_header("Content-Type", &content_type)
+        .with_body(&body)
+        .create();
+
+    let (_, _, copied_digest) = client.get_raw_manifest("raw/copy-dst", "v1").unwrap();
+    assert_eq!(digest, copied_digest);
+}
+
+#[test]
+fn test_missing_manifest_is_not_found() {
+    let _m = mock("GET", "/v2/raw/src/manifests/missing")
+        .with_status(404)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"errors": [{"code": "MANIFEST_UNKNOWN", "message": "manifest unknown"}]}"#)
+        .create();
+
+    match client().get_raw_manifest("raw/src", "missing") {
+        Err(ghregistry::errors::Error::NotFound { name, reference }) => {
+            assert_eq!(name, "raw/src");
+            assert_eq!(reference, "missing");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_get_manifest_if_modified() {
+    let etag = r#""sha256:cafe""#;
+    let _changed = mock("GET", "/v2/raw/etag/manifests/latest")
+        .match_header("if-none-match", mockito::Matcher::Missing)
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .with_header("ETag", etag)
+        .with_body(MANIFEST)
+        .create();
+    let _unchanged = mock("GET", "/v2/raw/etag/manifests/latest")
+        .match_header("if-none-match", etag)
+        .with_status(304)
+        .create();
+
+    let client = client();
+    let (body, new_etag) = client
+        .get_manifest_if_modified("raw/etag", "latest", None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(body, MANIFEST.as_bytes());
+    assert_eq!(new_etag, etag);
+    assert!(client
+        .get_manifest_if_modified("raw/etag", "latest", Some(&new_etag))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_registry_error_codes() {
+    let _m = mock("GET", "/v2/raw/denied/manifests/latest")
+        .with_status(403)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"errors": [{"code": "DENIED", "message": "requested access to the resource is denied"}]}"#)
+        .create();
+
+    let result = client().get_raw_manifest("raw/denied", "latest");
+    let e = result.as_ref().unwrap_err();
+    assert_eq!(
+        e.request(),
+        Some((
+            &reqwest::Method::GET,
+            format!("{}/v2/raw/denied/manifests/latest", mockito::server_url()).as_str()
+        ))
+    );
+    match e.inner() {
+        ghregistry::errors::Error::Registry { status, errors } => {
+            assert_eq!(*status, 403);
+            assert_eq!(errors[0].code, ghregistry::errors::ErrorCode::Denied);
+            assert_eq!(
+                errors[0].message,
+                "requested access to the resource is denied"
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test_case("latest"; "tag")]
+#[test_case(&manifest_digest(); "digest")]
+#[test_case(&format!("@{}", manifest_digest()); "digest with at sign")]
+fn test_manifest_reference(reference: &str) {
+    let path = reference.trim_start_matches('@');
+    let media_type = "application/vnd.docker.distribution.manifest.v2+json";
+    let _get = mock("GET", format!("/v2/raw/pinned/manifests/{}", path).as_str())
+        .with_status(200)
+        .with_header("Content-Type", media_type)
+        .with_body(MANIFEST)
+        .create();
+    let _head = mock(
+        "HEAD",
+        format!("/v2/raw/pinned/manifests/{}", path).as_str(),
+    )
+    .with_status(200)
+    .with_header("Docker-Content-Digest", &manifest_digest())
+    .create();
+
+    let client = client();
+    let (body, _, content_digest) = client.get_raw_manifest("raw/pinned", reference).unwrap();
+    assert_eq!(body, MANIFEST.as_bytes());
+    assert_eq!(content_digest, manifest_digest());
+    assert_eq!(
+        client.get_manifestref("raw/pinned", reference).unwrap(),
+        Some(manifest_digest())
+    );
+}
+
+#[test]
+fn test_manifest_digest_reference_is_verified() {
+    let wrong = format!("sha256:{:x}", sha2::Sha256::digest(b"another manifest"));
+    let _m = mock(
+        "GET",
+        format!("/v2/raw/tampered/manifests/{}", wrong).as_str(),
+    )
+    .with_status(200)
+    .with_header(
+        "Content-Type",
+        "application/vnd.docker.distribution.manifest.v2+json",
+    )
+    .with_body(MANIFEST)
+    .create();
+
+    match client().get_raw_manifest("raw/tampered", &wrong) {
+        Err(Error::ContentDigestParse(ContentDigestError::Verify { expected, got })) => {
+            assert_eq!(expected.to_string(), wrong);
+            assert_eq!(got.to_string(), manifest_digest());
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_manifest_reference_invalid_digest() {
+    match client().get_raw_manifest("raw/invalid", "sha256:cafe") {
+        Err(Error::ContentDigestParse(ContentDigestError::BadDigest(_))) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
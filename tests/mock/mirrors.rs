@@ -0,0 +1,66 @@
+use super::digest;
+use mockito::mock;
+
+fn mirrored_client() -> ghregistry::Client {
+    ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .username(Some("deploy".into()))
+        .password(Some("secret".into()))
+        .mirrors(vec![
+            format!("{}/first", mockito::server_url()),
+            format!("{}/second/", mockito::server_url()),
+        ])
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_blob_from_mirror() {
+    let blob = b"mirrored blob";
+    let digest = digest(blob);
+    let path = format!("/v2/mirror/repo/blobs/{}", digest);
+
+    let first = mock("GET", format!("/first{}", path).as_str())
+        .with_status(404)
+        .expect(1)
+        .create();
+    let second = mock("GET", format!("/second{}", path).as_str())
+        .match_header("authorization", mockito::Matcher::Missing)
+        .with_status(200)
+        .with_body(blob)
+        .expect(1)
+        .create();
+    let primary = mock("GET", path.as_str()).expect(0).create();
+
+    let got = mirrored_client().get_blob("mirror/repo", &digest).unwrap();
+    assert_eq!(got, blob);
+    first.assert();
+    second.assert();
+    primary.assert();
+}
+
+#[test]
+fn test_mirror_miss_falls_back_to_registry() {
+    let blob = b"primary blob";
+    let digest = digest(blob);
+    let path = format!("/v2/mirror/repo/blobs/{}", digest);
+
+    let _first = mock("HEAD", format!("/first{}", path).as_str())
+        .with_status(500)
+        .create();
+    let _second = mock("HEAD", format!("/second{}", path).as_str())
+        .with_status(404)
+        .create();
+    let primary = mock("HEAD", path.as_str())
+        .with_status(200)
+        .with_header("Content-Length", "12")
+        .expect(1)
+        .create();
+
+    assert_eq!(
+        mirrored_client().blob_size("mirror/repo", &digest).unwrap(),
+        Some(12)
+    );
+    primary.assert();
+}
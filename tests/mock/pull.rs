@@ -0,0 +1,412 @@
+use super::{client, digest, gzip, layer_tar};
+use ghregistry::manifest::Platform;
+use mockito::mock;
+
+#[test]
+fn test_pull_image_resolves_manifest_list() {
+    let name = "pull/repo";
+    let config = br#"{"architecture": "amd64", "os": "linux", "rootfs": {"type": "layers", "diff_ids": []}}"#;
+    let base = b"base layer".to_vec();
+    let top = b"top layer".to_vec();
+
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {
+            "mediaType": "application/vnd.docker.container.image.v1+json",
+            "size": config.len(),
+            "digest": digest(config),
+        },
+        "layers": [
+            {
+                "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                "size": base.len(),
+                "digest": digest(&base),
+            },
+            {
+                "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                "size": top.len(),
+                "digest": digest(&top),
+            },
+        ],
+    })
+    .to_string();
+    let manifest_digest = digest(manifest.as_bytes());
+
+    let host = Platform::host();
+    let list = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+        "manifests": [
+            {
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "size": 1,
+                "digest": "sha256:0000000000000000000000000000000000000000000000000000000000000000",
+                "platform": {"architecture": "not-the-host", "os": host.os},
+            },
+            {
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "size": manifest.len(),
+                "digest": manifest_digest,
+                "platform": {"architecture": host.architecture, "os": host.os},
+            },
+        ],
+    })
+    .to_string();
+
+    let _list = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.list.v2+json",
+        )
+        .with_body(list)
+        .create();
+    let _manifest = mock(
+        "GET",
+        format!("/v2/{}/manifests/{}", name, manifest_digest).as_str(),
+    )
+    .with_status(200)
+    .with_header(
+        "Content-Type",
+        "application/vnd.docker.distribution.manifest.v2+json",
+    )
+    .with_body(&manifest)
+    .create();
+
+    let mut blobs = Vec::new();
+    for blob in [&config[..], &base, &top] {
+        blobs.push(
+            mock(
+                "GET",
+                format!("/v2/{}/blobs/{}", name, digest(blob)).as_str(),
+            )
+            .with_status(200)
+            .with_body(blob)
+            .create(),
+        );
+    }
+
+    let (image_config, layers) = client().pull_image(name, "latest").unwrap();
+    assert_eq!(image_config.architecture, "amd64");
+    assert_eq!(layers, vec![base, top]);
+}
+
+#[test]
+fn test_image_size() {
+    let name = "size/repo";
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {
+            "mediaType": "application/vnd.docker.container.image.v1+json",
+            "size": 100,
+            "digest": digest(b"config"),
+        },
+        "layers": [
+            {
+                "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                "size": 1000,
+                "digest": digest(b"base"),
+            },
+            {
+                "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                "size": 20,
+                "digest": digest(b"top"),
+            },
+        ],
+    })
+    .to_string();
+
+    let _m = mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .with_body(&manifest)
+        .create();
+
+    let size = client().image_size(name, "latest").unwrap();
+    assert_eq!(
+        size,
+        ghregistry::ImageSize {
+            total_compressed_bytes: 1120,
+            layer_count: 2,
+        }
+    );
+}
+
+#[test]
+fn test_pull_image_by_digest() {
+    let name = "pull/pinned";
+    let config = br#"{"architecture": "arm64", "os": "linux"}"#;
+    let layer = b"pinned layer".to_vec();
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {
+            "mediaType": "application/vnd.docker.container.image.v1+json",
+            "size": config.len(),
+            "digest": digest(config),
+        },
+        "layers": [{
+            "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+            "size": layer.len(),
+            "digest": digest(&layer),
+        }],
+    })
+    .to_string();
+    let manifest_digest = digest(manifest.as_bytes());
+
+    let _manifest = mock(
+        "GET",
+        format!("/v2/{}/manifests/{}", name, manifest_digest).as_str(),
+    )
+    .with_status(200)
+    .with_header(
+        "Content-Type",
+        "application/vnd.docker.distribution.manifest.v2+json",
+    )
+    .with_body(&manifest)
+    .create();
+    let _config = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, digest(config)).as_str(),
+    )
+    .with_status(200)
+    .with_body(config)
+    .create();
+    let _layer = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, digest(&layer)).as_str(),
+    )
+    .with_status(200)
+    .with_body(&layer)
+    .create();
+
+    let (image_config, layers) = client()
+        .pull_image(name, &format!("@{}", manifest_digest))
+        .unwrap();
+    assert_eq!(image_config.architecture, "arm64");
+    assert_eq!(layers, vec![layer]);
+}
+
+/// Mock an image whose only layer is a foreign layer with the given URLs.
+fn mock_foreign_image(name: &str, layer: &[u8], urls: &[String]) -> Vec<mockito::Mock> {
+    let config = br#"{"architecture": "amd64", "os": "windows"}"#;
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {
+            "mediaType": "application/vnd.docker.container.image.v1+json",
+            "size": config.len(),
+            "digest": digest(config),
+        },
+        "layers": [{
+            "mediaType": "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip",
+            "size": layer.len(),
+            "digest": digest(layer),
+            "urls": urls,
+        }],
+    })
+    .to_string();
+
+    vec![
+        mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+            .with_status(200)
+            .with_header(
+                "Content-Type",
+                "application/vnd.docker.distribution.manifest.v2+json",
+            )
+            .with_body(&manifest)
+            .create(),
+        mock(
+            "GET",
+            format!("/v2/{}/blobs/{}", name, digest(config)).as_str(),
+        )
+        .with_status(200)
+        .with_body(config)
+        .create(),
+        mock(
+            "GET",
+            format!("/v2/{}/blobs/{}", name, digest(layer)).as_str(),
+        )
+        .with_status(404)
+        .expect(0)
+        .create(),
+    ]
+}
+
+#[test]
+fn test_pull_image_foreign_layer_from_urls() {
+    let layer = b"windows base layer".to_vec();
+    let base = mockito::server_url();
+    let urls = vec![
+        format!("{}/foreign/missing", base),
+        format!("{}/foreign/layer", base),
+    ];
+    let mocks = mock_foreign_image("pull/foreign", &layer, &urls);
+    let _missing = mock("GET", "/foreign/missing").with_status(404).create();
+    let _layer = mock("GET", "/foreign/layer")
+        .with_status(200)
+        .with_body(&layer)
+        .create();
+
+    let (_, layers) = client().pull_image("pull/foreign", "latest").unwrap();
+    assert_eq!(layers, vec![layer]);
+    mocks[2].assert();
+}
+
+#[test]
+fn test_pull_image_foreign_layer_without_urls() {
+    let layer = b"unreachable layer".to_vec();
+    let _mocks = mock_foreign_image("pull/nondistributable", &layer, &[]);
+
+    match client().pull_image("pull/nondistributable", "latest") {
+        Err(ghregistry::errors::Error::NonDistributableLayer { digest: d, urls }) => {
+            assert_eq!(d, digest(&layer));
+            assert!(urls.is_empty());
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+/// Mock an image with the given layers, served under `latest`.
+fn mock_image(name: &str, layers: &[Vec<u8>]) -> Vec<mockito::Mock> {
+    let config = br#"{"architecture": "amd64", "os": "linux"}"#;
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {
+            "mediaType": "application/vnd.docker.container.image.v1+json",
+            "size": config.len(),
+            "digest": digest(config),
+        },
+        "layers": layers.iter().map(|layer| serde_json::json!({
+            "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+            "size": layer.len(),
+            "digest": digest(layer),
+        })).collect::<Vec<_>>(),
+    })
+    .to_string();
+
+    let mut mocks = vec![
+        mock("GET", format!("/v2/{}/manifests/latest", name).as_str())
+            .with_status(200)
+            .with_header(
+                "Content-Type",
+                "application/vnd.docker.distribution.manifest.v2+json",
+            )
+            .with_body(&manifest)
+            .create(),
+        mock(
+            "GET",
+            format!("/v2/{}/blobs/{}", name, digest(config)).as_str(),
+        )
+        .with_status(200)
+        .with_body(config)
+        .create(),
+    ];
+    for layer in layers {
+        mocks.push(
+            mock(
+                "GET",
+                format!("/v2/{}/blobs/{}", name, digest(layer)).as_str(),
+            )
+            .with_status(200)
+            .with_body(layer)
+            .create(),
+        );
+    }
+    mocks
+}
+
+#[test]
+fn test_pull_and_unpack() {
+    let layers = vec![
+        gzip(&layer_tar(&[
+            ("etc/os-release", b"base"),
+            ("etc/motd", b"hi"),
+        ])),
+        layer_tar(&[("etc/os-release", b"top"), ("etc/.wh.motd", b"")]),
+    ];
+    let _mocks = mock_image("pull/unpack", &layers);
+
+    let dir = tempfile::tempdir().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let config = client()
+        .pull_and_unpack("pull/unpack", "latest", dir.path(), Some(sender))
+        .unwrap();
+
+    assert_eq!(config.architecture, "amd64");
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/os-release")).unwrap(),
+        b"top"
+    );
+    assert!(!dir.path().join("etc/motd").exists());
+    let received: u64 = receiver.iter().sum();
+    assert_eq!(received, layers.iter().map(|l| l.len() as u64).sum::<u64>());
+}
+
+#[test]
+fn test_pull_and_unpack_verifies_layers() {
+    let layers = vec![gzip(&layer_tar(&[("bin/sh", b"#!")]))];
+    let mut mocks = mock_image("pull/tampered", &layers);
+    mocks.pop();
+    let _layer = mock(
+        "GET",
+        format!("/v2/pull/tampered/blobs/{}", digest(&layers[0])).as_str(),
+    )
+    .with_status(200)
+    .with_body(gzip(&layer_tar(&[("bin/sh", b"rm -rf /")])))
+    .create();
+
+    let dir = tempfile::tempdir().unwrap();
+    match client().pull_and_unpack("pull/tampered", "latest", dir.path(), None) {
+        Err(ghregistry::errors::Error::ContentDigestParse(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_pull_and_unpack_render_error() {
+    // The tar builder refuses to write such a path
+    let mut header = tar::Header::new_old();
+    header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+    header.set_size(0);
+    header.set_cksum();
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.append(&header, &[][..]).unwrap();
+    let layers = vec![builder.into_inner().unwrap()];
+    let _mocks = mock_image("pull/hostile", &layers);
+
+    let dir = tempfile::tempdir().unwrap();
+    match client().pull_and_unpack("pull/hostile", "latest", dir.path(), None) {
+        Err(ghregistry::errors::Error::Unpack { digest: d, .. }) => {
+            assert_eq!(d, digest(&layers[0]))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_get_layer_descriptors() {
+    let layers = vec![b"base layer".to_vec(), b"top layer".to_vec()];
+    let mocks = mock_image("pull/descriptors", &layers);
+
+    let descriptors = client()
+        .get_layer_descriptors("pull/descriptors", "latest")
+        .unwrap();
+    assert_eq!(descriptors.len(), 2);
+    for (descriptor, layer) in descriptors.iter().zip(&layers) {
+        assert_eq!(
+            descriptor.media_type,
+            "application/vnd.docker.image.rootfs.diff.tar.gzip"
+        );
+        assert_eq!(descriptor.digest, digest(layer));
+        assert_eq!(descriptor.size, layer.len() as u64);
+    }
+    // Only the manifest is fetched
+    assert!(mocks[1..].iter().all(|m| !m.matched()));
+}
@@ -0,0 +1,76 @@
+use super::{client, digest};
+use ghregistry::errors::Error;
+use ghregistry::RateLimit;
+use mockito::mock;
+use std::time::Duration;
+
+#[test]
+fn test_rate_limit_status() {
+    let m = mock("HEAD", "/v2/ratelimitpreview/test/manifests/latest")
+        .with_status(200)
+        .with_header("ratelimit-limit", "100;w=21600")
+        .with_header("ratelimit-remaining", "76;w=21600")
+        .expect(1)
+        .create();
+
+    assert_eq!(
+        client().rate_limit_status("ratelimitpreview/test").unwrap(),
+        RateLimit {
+            limit: Some(100),
+            remaining: Some(76),
+            retry_after: None,
+        }
+    );
+    m.assert();
+}
+
+#[test]
+fn test_rate_limited_manifest() {
+    let _m = mock("GET", "/v2/ratelimit/repo/manifests/latest")
+        .with_status(429)
+        .with_header("Content-Type", "application/json")
+        .with_header("Retry-After", "30")
+        .with_header("ratelimit-remaining", "0;w=21600")
+        .with_body(r#"{"errors": [{"code": "TOOMANYREQUESTS", "message": "pull rate limit"}]}"#)
+        .create();
+
+    let e = client()
+        .get_raw_manifest("ratelimit/repo", "latest")
+        .unwrap_err();
+    match e.inner() {
+        Error::RateLimited {
+            retry_after,
+            limit,
+            remaining,
+        } => {
+            assert_eq!(*retry_after, Some(Duration::from_secs(30)));
+            assert_eq!(*limit, None);
+            assert_eq!(*remaining, Some(0));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(e.is_retryable());
+}
+
+#[test]
+fn test_rate_limited_blob_head() {
+    let blob = digest(b"rate limited");
+    let _m = mock(
+        "HEAD",
+        format!("/v2/ratelimit/repo/blobs/{}", blob).as_str(),
+    )
+    .with_status(429)
+    .with_header("Retry-After", "5")
+    .create();
+
+    match client()
+        .has_blob("ratelimit/repo", &blob)
+        .unwrap_err()
+        .inner()
+    {
+        Error::RateLimited { retry_after, .. } => {
+            assert_eq!(*retry_after, Some(Duration::from_secs(5)))
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
@@ -0,0 +1,100 @@
+use super::{client, digest};
+use mockito::{mock, Matcher};
+
+#[test]
+fn test_referrers_api() {
+    let subject = digest(b"subject");
+    let sbom = digest(b"sbom");
+    let index = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.index.v1+json",
+        "manifests": [
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": digest(b"signature"),
+                "size": 100,
+                "artifactType": "application/vnd.dev.cosign.artifact.sig.v1+json",
+            },
+            {
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": sbom,
+                "size": 200,
+                "artifactType": "application/spdx+json",
+                "annotations": {"org.opencontainers.image.created": "2023-01-01T00:00:00Z"},
+            },
+        ],
+    })
+    .to_string();
+
+    // The registry ignores the filter, so the client applies it
+    let _m = mock(
+        "GET",
+        format!("/v2/referrers/repo/referrers/{}", subject).as_str(),
+    )
+    .match_query(Matcher::UrlEncoded(
+        "artifactType".into(),
+        "application/spdx+json".into(),
+    ))
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+    .with_body(&index)
+    .create();
+
+    let referrers = client()
+        .get_referrers("referrers/repo", &subject, Some("application/spdx+json"))
+        .unwrap();
+    assert_eq!(referrers.len(), 1);
+    assert_eq!(referrers[0].digest, sbom);
+    assert_eq!(referrers[0].size, 200);
+}
+
+#[test]
+fn test_referrers_tag_schema_fallback() {
+    let subject = digest(b"legacy subject");
+    let tag = subject.replace(':', "-");
+    let signature = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "config": {
+            "mediaType": "application/vnd.dev.cosign.artifact.sig.v1+json",
+            "digest": digest(b"config"),
+            "size": 6,
+        },
+        "layers": [],
+    })
+    .to_string();
+
+    let _api = mock(
+        "GET",
+        format!("/v2/legacy/repo/referrers/{}", subject).as_str(),
+    )
+    .with_status(404)
+    .create();
+    let _missing = mock(
+        "GET",
+        Matcher::Regex(format!(
+            r"^/v2/legacy/repo/manifests/{}(\.att|\.sbom)?$",
+            tag
+        )),
+    )
+    .with_status(404)
+    .create();
+    let _sig = mock(
+        "GET",
+        format!("/v2/legacy/repo/manifests/{}.sig", tag).as_str(),
+    )
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(&signature)
+    .create();
+
+    let referrers = client()
+        .get_referrers("legacy/repo", &subject, None)
+        .unwrap();
+    assert_eq!(referrers.len(), 1);
+    assert_eq!(referrers[0].digest, digest(signature.as_bytes()));
+    assert_eq!(
+        referrers[0].artifact_type.as_deref(),
+        Some("application/vnd.dev.cosign.artifact.sig.v1+json")
+    );
+}
@@ -0,0 +1,83 @@
+use super::{client, digest, gzip, layer_tar};
+use mockito::mock;
+use std::io::Read;
+
+#[test]
+fn test_save_image_layout() {
+    let name = "save/repo";
+    let config = br#"{"architecture": "amd64", "os": "linux"}"#.to_vec();
+    let layer_tar = layer_tar(&[("etc/motd", b"hi")]);
+    let layer = gzip(&layer_tar);
+    let config_digest = digest(&config);
+    let layer_digest = digest(&layer);
+
+    let manifest = format!(
+        r#"{{"schemaVersion": 2, "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {{"mediaType": "application/vnd.docker.container.image.v1+json", "size": {}, "digest": "{}"}},
+            "layers": [{{"mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip", "size": {}, "digest": "{}"}}]}}"#,
+        config.len(),
+        config_digest,
+        layer.len(),
+        layer_digest
+    );
+
+    let _m = mock("GET", format!("/v2/{}/manifests/v1", name).as_str())
+        .with_status(200)
+        .with_header(
+            "Content-Type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .with_body(manifest)
+        .create();
+    let _c = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, config_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&config)
+    .expect_at_least(1)
+    .create();
+    let _l = mock(
+        "GET",
+        format!("/v2/{}/blobs/{}", name, layer_digest).as_str(),
+    )
+    .with_status(200)
+    .with_body(&layer)
+    .create();
+
+    let mut out = Vec::new();
+    client().save_image(name, "v1", &mut out).unwrap();
+
+    let config_hex = config_digest.trim_start_matches("sha256:");
+    let layer_hex = layer_digest.trim_start_matches("sha256:");
+
+    let mut archive = tar::Archive::new(out.as_slice());
+    let mut files = std::collections::HashMap::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        files.insert(path, data);
+    }
+
+    assert_eq!(files[&format!("{}.json", config_hex)], config);
+    // `docker load` wants plain tarballs
+    assert_eq!(files[&format!("{}/layer.tar", layer_hex)], layer_tar);
+
+    let manifest_json: serde_json::Value = serde_json::from_slice(&files["manifest.json"]).unwrap();
+    assert_eq!(
+        manifest_json,
+        serde_json::json!([{
+            "Config": format!("{}.json", config_hex),
+            "RepoTags": ["save/repo:v1"],
+            "Layers": [format!("{}/layer.tar", layer_hex)],
+        }])
+    );
+
+    let repositories: serde_json::Value = serde_json::from_slice(&files["repositories"]).unwrap();
+    assert_eq!(
+        repositories,
+        serde_json::json!({ "save/repo": { "v1": layer_hex } })
+    );
+}
@@ -0,0 +1,367 @@
+use super::client;
+use mockito::mock;
+
+fn tags_page(name: &str, query: &str, tags: &[&str]) -> mockito::Mock {
+    mock("GET", format!("/v2/{}/tags/list?{}", name, query).as_str())
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(serde_json::json!({ "name": name, "tags": tags }).to_string())
+}
+
+#[test]
+fn test_tags_iter_fetches_lazily() {
+    let name = "lazy/repo";
+    let _m1 = tags_page(name, "n=2", &["t1", "t2"])
+        .with_header(
+            "Link",
+            &format!(r#"</v2/{}/tags/list?n=2&last=t2>; rel="next""#, name),
+        )
+        .create();
+    let m2 = tags_page(name, "n=2&last=t2", &["t3"]).expect(0).create();
+
+    let client = client();
+    let tags: Vec<String> = client
+        .tags_iter(name, Some(2))
+        .take(2)
+        .collect::<ghregistry::errors::Result<_>>()
+        .unwrap();
+
+    assert_eq!(tags, vec!["t1", "t2"]);
+    m2.assert();
+}
+
+#[test]
+fn test_tags_follow_link_header_verbatim() {
+    let name = "link/repo";
+    // The continuation URL carries parameters the client knows nothing about
+    let _m1 = tags_page(name, "n=2", &["t1", "t2"])
+        .with_header(
+            "Link",
+            &format!(
+                r#"</v2/{}/tags/list?last=t2&n=2&cursor=opaque>; rel="next""#,
+                name
+            ),
+        )
+        .create();
+    let _m2 = tags_page(name, "last=t2&n=2&cursor=opaque", &["t3"]).create();
+
+    let tags = client().get_tags(name, Some(2)).unwrap();
+    assert_eq!(tags, vec!["t1", "t2", "t3"]);
+}
+
+#[test]
+fn test_tags_fall_back_to_last_without_link_header() {
+    let name = "last/repo";
+    let _m1 = tags_page(name, "n=2", &["t1", "t2"]).create();
+    let _m2 = tags_page(name, "n=2&last=t2", &["t3"]).create();
+
+    let tags = client().get_tags(name, Some(2)).unwrap();
+    assert_eq!(tags, vec!["t1", "t2", "t3"]);
+}
+
+#[test]
+fn test_tags_stop_when_last_is_ignored() {
+    let name = "ignore-last/repo";
+    let m = mock(
+        "GET",
+        mockito::Matcher::Regex(format!(r"^/v2/{}/tags/list\?n=2", name)),
+    )
+    .with_status(200)
+    .with_header("Content-Type", "application/json")
+    .with_body(serde_json::json!({ "name": name, "tags": ["t1", "t2"] }).to_string())
+    .expect(2)
+    .create();
+
+    let tags = client().get_tags(name, Some(2)).unwrap();
+    assert_eq!(tags[..2], ["t1", "t2"]);
+    m.assert();
+}
+
+#[test]
+fn test_tags_stop_when_link_points_back() {
+    let name = "link-loop/repo";
+    let m = tags_page(name, "n=2", &["t1", "t2"])
+        .with_header(
+            "Link",
+            &format!(r#"</v2/{}/tags/list?n=2>; rel="next""#, name),
+        )
+        .expect(1)
+        .create();
+
+    let tags = client().get_tags(name, Some(2)).unwrap();
+    assert_eq!(tags, vec!["t1", "t2"]);
+    m.assert();
+}
+
+#[test]
+fn test_tags_unpaginated_single_request() {
+    let name = "single/repo";
+    let _m = mock("GET", format!("/v2/{}/tags/list", name).as_str())
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"name": "single/repo", "tags": ["t1", "t2"]}"#)
+        .expect(1)
+        .create();
+
+    let tags = client().get_tags(name, None).unwrap();
+    assert_eq!(tags, vec!["t1", "t2"]);
+}
+
+#[test]
+fn test_tags_iter_yields_error_mid_pagination() {
+    let name = "broken/repo";
+    let _m1 = tags_page(name, "n=1", &["t1"])
+        .with_header(
+            "Link",
+            &format!(r#"</v2/{}/tags/list?n=1&last=t1>; rel="next""#, name),
+        )
+        .create();
+    let _m2 = mock(
+        "GET",
+        format!("/v2/{}/tags/list?n=1&last=t1", name).as_str(),
+    )
+    .with_status(500)
+    .create();
+
+    let client = client();
+    let mut iter = client.tags_iter(name, Some(1));
+    assert_eq!(iter.next().unwrap().unwrap(), "t1");
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_get_latest_semver_tag() {
+    let name = "semver/repo";
+    let _m = mock("GET", format!("/v2/{}/tags/list", name).as_str())
+        .with_status(200)
+        .with_header("Content-Type", "application/json")
+        .with_body(
+            r#"{"name": "semver/repo", "tags": ["latest", "v1.2.0", "1.10.0", "2.0.0-rc.1", "sha-abc"]}"#,
+        )
+        .expect(2)
+        .create();
+
+    let client = client();
+    assert_eq!(
+        client
+            .get_latest_semver_tag(name, false)
+            .unwrap()
+            .as_deref(),
+        Some("1.10.0")
+    );
+    assert_eq!(
+        client.get_latest_semver_tag(name, true).unwrap().as_deref(),
+        Some("2.0.0-rc.1")
+    );
+}
+
+#[test]
+fn test_tags_strict_content_type() {
+    let name = "html/repo";
+    let _m = mock("GET", format!("/v2/{}/tags/list", name).as_str())
+        .with_status(200)
+        .with_header("Content-Type", "text/html")
+        .with_body("<html><body>Please log in</body></html>")
+        .create();
+
+    let client = ghregistry::Client::configure()
+        .registry(&mockito::server_address().to_string())
+        .insecure_registry(true)
+        .strict_content_types(true)
+        .build()
+        .unwrap();
+
+    match client.get_tags(name, None) {
+        Err(ghregistry::errors::Error::UnexpectedContentType {
+            expected,
+            actual,
+            body,
+            ..
+        }) => {
+            assert_eq!(expected, "application/json");
+            assert_eq!(actual.as_deref(), Some("text/html"));
+            assert!(body.contains("Please log in"));
+        }
+        other => panic!("expected a content type error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_tags_or_empty_on_unknown_repository() {
+    let _m1 = mock("GET", "/v2/unknown/repo/tags/list")
+        .with_status(404)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"errors": [{"code": "NAME_UNKNOWN", "message": "repository name not known to registry", "detail": {"name": "unknown/repo"}}]}"#)
+        .create();
+    let _m2 = mock("GET", "/v2/pruned/repo/tags/list")
+        .with_status(400)
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"errors": [{"code": "NAME_UNKNOWN", "message": "repository name not known to registry"}]}"#)
+        .create();
+
+    let client = client();
+    assert!(client.get_tags("unknown/repo", None).is_err());
+    assert!(client
+        .get_tags_or_empty("unknown/repo", None)
+        .unwrap()
+        .is_empty());
+    assert!(client
+        .get_tags_or_empty("pruned/repo", None)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_tags_or_empty_propagates_auth_errors() {
+    let _m = mock("GET", "/v2/private/repo/tags/list")
+        .with_status(401)
+        .with_header("Content-Type", "application/json")
+        .with_body(
+            r#"{"errors": [{"code": "UNAUTHORIZED", "message": "authentication required"}]}"#,
+        )
+        .create();
+
+    assert!(client().get_tags_or_empty("private/repo", None).is_err());
+}
+
+#[test]
+fn test_tags_limited_stops_early() {
+    let name = "limited/repo";
+    let _m1 = tags_page(name, "n=3", &["t1", "t2", "t3"])
+        .with_header(
+            "Link",
+            &format!(r#"</v2/{}/tags/list?n=3&last=t3>; rel="next""#, name),
+        )
+        .create();
+    // Only two more tags are needed, so the last page is requested smaller
+    let m2 = tags_page(name, "n=2&last=t3", &["t4", "t5"])
+        .with_header(
+            "Link",
+            &format!(r#"</v2/{}/tags/list?n=2&last=t5>; rel="next""#, name),
+        )
+        .expect(1)
+        .create();
+    let m3 = tags_page(name, "n=2&last=t5", &["t6"]).expect(0).create();
+
+    let tags = client().get_tags_limited(name, Some(3), 5).unwrap();
+    assert_eq!(tags, vec!["t1", "t2", "t3", "t4", "t5"]);
+    m2.assert();
+    m3.assert();
+}
+
+#[test]
+fn test_tags_limited_truncates_page() {
+    let name = "truncated/repo";
+    // Registries may ignore `n` and return a larger page
+    let _m = tags_page(name, "n=2", &["t1", "t2", "t3"]).create();
+
+    let client = client();
+    assert_eq!(
+        client.get_tags_limited(name, None, 2).unwrap(),
+        vec!["t1", "t2"]
+    );
+    assert!(client.get_tags_limited(name, None, 0).unwrap().is_empty());
+}
+
+#[test]
+fn test_tags_page_resumes_from_token() {
+    let name = "paged/repo";
+    let _m1 = tags_page(name, "n=2", &["t1", "t2"])
+        .with_header(
+            "Link",
+            &format!(r#"</v2/{}/tags/list?n=2&last=t2>; rel="next""#, name),
+        )
+        .create();
+    let _m2 = tags_page(name, "n=2&last=t2", &["t3"]).create();
+
+    let client = client();
+    let (chunk, token) = client.get_tags_page(name, Some(2), None).unwrap();
+    assert_eq!(chunk.name, name);
+    assert_eq!(chunk.tags, vec!["t1", "t2"]);
+    assert_eq!(token.as_deref(), Some("t2"));
+
+    let (chunk, token) = client
+        .get_tags_page(name, Some(2), token.as_deref())
+        .unwrap();
+    assert_eq!(chunk.tags, vec!["t3"]);
+    assert_eq!(token, None);
+}
+
+#[test]
+fn test_tags_page_keeps_opaque_cursor() {
+    let name = "cursor/repo";
+    let _m1 = tags_page(name, "n=2", &["t1", "t2"])
+        .with_header(
+            "Link",
+            &format!(r#"</v2/{}/tags/list?n=2&cursor=abc>; rel="next""#, name),
+        )
+        .create();
+    let _m2 = tags_page(name, "n=2&cursor=abc", &["t3"]).create();
+
+    let client = client();
+    let (_, token) = client.get_tags_page(name, Some(2), None).unwrap();
+    let token = token.unwrap();
+    assert!(token.ends_with("/tags/list?n=2&cursor=abc"), "{}", token);
+
+    let (chunk, token) = client.get_tags_page(name, Some(2), Some(&token)).unwrap();
+    assert_eq!(chunk.tags, vec!["t3"]);
+    assert_eq!(token, None);
+}
+
+#[test]
+fn test_tags_of_missing_repository_are_not_found() {
+    let _m = mock("GET", "/v2/missing/repo/tags/list")
+        .with_status(404)
+        .create();
+
+    match client().get_tags("missing/repo", None) {
+        Err(ghregistry::errors::Error::NotFound { name, .. }) => assert_eq!(name, "missing/repo"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+fn delete_tag(name: &str, delete_status: usize) -> ghregistry::errors::Result<()> {
+    let digest = super::digest(name.as_bytes());
+    let _head = mock("HEAD", format!("/v2/{}/manifests/old", name).as_str())
+        .with_status(200)
+        .with_header("Docker-Content-Digest", &digest)
+        .create();
+    let delete = mock(
+        "DELETE",
+        format!("/v2/{}/manifests/{}", name, digest).as_str(),
+    )
+    .with_status(delete_status)
+    .create();
+
+    let result = client().delete_tag(name, "old");
+    delete.assert();
+    result
+}
+
+#[test]
+fn test_delete_tag_by_digest() {
+    delete_tag("delete/repo", 202).unwrap();
+}
+
+#[test]
+fn test_delete_tag_not_supported() {
+    match delete_tag("delete/disabled", 405) {
+        Err(ghregistry::errors::Error::DeleteNotSupported) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_delete_missing_tag() {
+    let _head = mock("HEAD", "/v2/delete/missing/manifests/gone")
+        .with_status(404)
+        .create();
+    let delete = mock("DELETE", mockito::Matcher::Any).expect(0).create();
+
+    assert!(client()
+        .delete_tag("delete/missing", "gone")
+        .unwrap_err()
+        .is_not_found());
+    delete.assert();
+}
@@ -0,0 +1,62 @@
+use super::client;
+use ghregistry::errors::Error;
+use ghregistry::V2Support;
+use mockito::mock;
+use test_case::test_case;
+
+#[test]
+fn test_ensure_v2_registry_ref() {
+    let m = mock("GET", "/v2/")
+        .with_status(200)
+        .with_header("Docker-Distribution-API-Version", "registry/2.0")
+        .expect(2)
+        .create();
+
+    let client = client();
+    client.ensure_v2_registry_ref().unwrap();
+    // The client is still usable afterwards
+    client.ensure_v2_registry().unwrap();
+    m.assert();
+}
+
+#[test]
+fn test_ensure_v2_registry_ref_not_v2() {
+    let _m = mock("GET", "/v2/")
+        .with_status(200)
+        .with_header("Docker-Distribution-API-Version", "registry/1.0")
+        .create();
+
+    match client().ensure_v2_registry_ref() {
+        Err(Error::V2NotSupported) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test_case(200, Some("registry/2.0"), V2Support::Supported; "supported")]
+#[test_case(200, Some("registry/2.1"), V2Support::Supported; "newer minor version")]
+#[test_case(401, Some("registry/2.0"), V2Support::Unauthorized; "unauthorized")]
+#[test_case(200, None, V2Support::NotV2; "no version header")]
+#[test_case(404, None, V2Support::NotV2; "not found")]
+fn test_v2_support(status: usize, version: Option<&str>, expected: V2Support) {
+    let mut m = mock("GET", "/v2/").with_status(status);
+    if let Some(version) = version {
+        m = m.with_header("Docker-Distribution-API-Version", version);
+    }
+    let _m = m.create();
+
+    assert_eq!(client().v2_support(), expected);
+}
+
+#[test]
+fn test_v2_support_unreachable() {
+    // Nothing listens on the discard port
+    let client = ghregistry::Client::configure()
+        .registry("127.0.0.1:9")
+        .insecure_registry(true)
+        .build()
+        .unwrap();
+    match client.v2_support() {
+        V2Support::Unreachable(reason) => assert!(!reason.is_empty()),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -0,0 +1,98 @@
+use super::{digest, gzip, layer_tar};
+use ghregistry::render::{self, Compression, UnpackOptions};
+use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+use std::fs;
+
+fn zstd(data: &[u8]) -> Vec<u8> {
+    compress_to_vec(data, CompressionLevel::Fastest)
+}
+
+#[test]
+fn test_unpack_detects_compression() {
+    let plain = layer_tar(&[("etc/plain", b"plain")]);
+    let gzipped = layer_tar(&[("etc/gzip", b"gzip")]);
+    let zstded = layer_tar(&[("etc/zstd", b"zstd")]);
+    let layers = vec![plain.clone(), gzip(&gzipped), zstd(&zstded)];
+
+    let dir = tempfile::tempdir().unwrap();
+    let diff_ids = vec![digest(&plain), digest(&gzipped), digest(&zstded)];
+    render::unpack_with_diff_ids(&layers, &diff_ids, dir.path()).unwrap();
+
+    for name in &["plain", "gzip", "zstd"] {
+        assert_eq!(
+            fs::read(dir.path().join("etc").join(name)).unwrap(),
+            name.as_bytes()
+        );
+    }
+}
+
+#[test]
+fn test_unpack_files_detects_compression() {
+    let dir = tempfile::tempdir().unwrap();
+    let plain = dir.path().join("plain.tar");
+    fs::write(&plain, layer_tar(&[("srv/plain", b"plain")])).unwrap();
+    let zstded = dir.path().join("layer.tar.zst");
+    fs::write(&zstded, zstd(&layer_tar(&[("srv/zstd", b"zstd")]))).unwrap();
+
+    let target = dir.path().join("rootfs");
+    fs::create_dir(&target).unwrap();
+    let files = vec![
+        plain.to_string_lossy().into_owned(),
+        zstded.to_string_lossy().into_owned(),
+    ];
+    render::unpack_files(files.clone(), &target).unwrap();
+    assert!(target.join("srv/plain").exists());
+    assert!(target.join("srv/zstd").exists());
+
+    let partial = dir.path().join("partial");
+    fs::create_dir(&partial).unwrap();
+    render::unpack_partial_files(files, &partial, "srv").unwrap();
+    assert!(partial.join("plain").exists());
+    assert!(partial.join("zstd").exists());
+}
+
+#[test]
+fn test_unpack_multi_frame_zstd() {
+    let tar = layer_tar(&[("etc/hostname", b"host\n"), ("etc/hosts", b"127.0.0.1\n")]);
+    let (first, second) = tar.split_at(1024);
+    let mut layer = zstd(first);
+    // A skippable frame between the data frames
+    layer.extend([0x50, 0x2a, 0x4d, 0x18, 4, 0, 0, 0, 1, 2, 3, 4]);
+    layer.extend(zstd(second));
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack_with_diff_ids(&[layer], &[digest(&tar)], dir.path()).unwrap();
+    assert!(dir.path().join("etc/hostname").exists());
+    assert!(dir.path().join("etc/hosts").exists());
+}
+
+#[test]
+fn test_compression_override_wins() {
+    let layers = vec![gzip(&layer_tar(&[("etc/motd", b"motd")]))];
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = UnpackOptions::default().compression(Compression::None);
+    assert!(render::unpack_with_options(&layers, dir.path(), &options).is_err());
+    assert!(!dir.path().join("etc/motd").exists());
+
+    let options = UnpackOptions::default().compression(Compression::Gzip);
+    render::unpack_with_options(&layers, dir.path(), &options).unwrap();
+    assert!(dir.path().join("etc/motd").exists());
+}
+
+#[test]
+fn test_compression_from_media_type() {
+    assert_eq!(
+        Compression::from_media_type("application/vnd.oci.image.layer.v1.tar+zstd"),
+        Some(Compression::Zstd)
+    );
+    assert_eq!(
+        Compression::from_media_type("application/vnd.docker.image.rootfs.diff.tar.gzip"),
+        Some(Compression::Gzip)
+    );
+    assert_eq!(
+        Compression::from_media_type("application/vnd.oci.image.layer.v1.tar"),
+        Some(Compression::None)
+    );
+    assert_eq!(Compression::from_media_type("application/json"), None);
+}
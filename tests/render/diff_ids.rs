@@ -0,0 +1,125 @@
+use super::{digest, gzip, layer_tar};
+use ghregistry::render::{self, RenderError, UnpackOptions};
+
+#[test]
+fn test_unpack_with_matching_diff_ids() {
+    let base = layer_tar(&[("etc/os-release", b"ID=test\n")]);
+    let top = layer_tar(&[("srv/app", b"app")]);
+    let layers = vec![gzip(&base), gzip(&top)];
+    let diff_ids = vec![digest(&base), digest(&top)];
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack_with_diff_ids(&layers, &diff_ids, dir.path()).unwrap();
+
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/os-release")).unwrap(),
+        b"ID=test\n"
+    );
+    assert!(dir.path().join("srv/app").exists());
+}
+
+#[test]
+fn test_unpack_with_mismatching_diff_id() {
+    let base = layer_tar(&[("etc/os-release", b"ID=test\n")]);
+    let top = layer_tar(&[("srv/app", b"app")]);
+    let layers = vec![gzip(&base), gzip(&top)];
+    // The compressed digest is not a valid diff_id
+    let diff_ids = vec![digest(&base), digest(&layers[1])];
+
+    let dir = tempfile::tempdir().unwrap();
+    match render::unpack_with_diff_ids(&layers, &diff_ids, dir.path()) {
+        Err(RenderError::DiffIdMismatch {
+            layer,
+            expected,
+            got,
+        }) => {
+            assert_eq!(layer, 1);
+            assert_eq!(expected, diff_ids[1]);
+            assert_eq!(got, digest(&top));
+        }
+        other => panic!("expected a diff_id mismatch, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_unpack_with_missing_diff_ids() {
+    let layers = vec![gzip(&layer_tar(&[("a", b"a")]))];
+
+    let dir = tempfile::tempdir().unwrap();
+    assert!(matches!(
+        render::unpack_with_diff_ids(&layers, &[], dir.path()),
+        Err(RenderError::DiffIdCount {
+            layers: 1,
+            diff_ids: 0
+        })
+    ));
+}
+
+#[test]
+fn test_unpack_returns_diff_ids() {
+    let base = layer_tar(&[("etc/os-release", b"ID=test\n")]);
+    let top = layer_tar(&[("srv/app", b"app")]);
+    let layers = vec![gzip(&base), top.clone()];
+
+    let dir = tempfile::tempdir().unwrap();
+    let summary =
+        render::unpack_with_options(&layers, dir.path(), &UnpackOptions::default()).unwrap();
+    assert_eq!(summary.diff_ids, vec![digest(&base), digest(&top)]);
+}
+
+#[test]
+fn test_unpack_stream_verifies_diff_ids() {
+    let base = layer_tar(&[("etc/os-release", b"ID=test\n")]);
+    let top = layer_tar(&[("srv/app", b"app")]);
+    let layers = [gzip(&base), gzip(&top)];
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = UnpackOptions::default().verify_diff_ids(vec![digest(&base), digest(&top)]);
+    let summary =
+        render::unpack_stream_with_options(layers.iter().map(|l| &l[..]), dir.path(), &options)
+            .unwrap();
+    assert_eq!(summary.diff_ids, vec![digest(&base), digest(&top)]);
+
+    // A mirror serving another, internally consistent, layer
+    let other = layer_tar(&[("srv/app", b"tampered")]);
+    let dir = tempfile::tempdir().unwrap();
+    let result = render::unpack_stream_with_options(
+        vec![&gzip(&base)[..], &gzip(&other)[..]],
+        dir.path(),
+        &options,
+    );
+    assert!(
+        matches!(&result, Err(RenderError::DiffIdMismatch { layer: 1, .. })),
+        "{:?}",
+        result
+    );
+
+    // Streams aren't counted before unpacking
+    let dir = tempfile::tempdir().unwrap();
+    let result = render::unpack_stream_with_options(
+        layers.iter().take(1).map(|l| &l[..]),
+        dir.path(),
+        &options,
+    );
+    assert!(matches!(
+        result,
+        Err(RenderError::DiffIdCount {
+            layers: 1,
+            diff_ids: 2
+        })
+    ));
+}
+
+#[test]
+fn test_unpack_partial_verifies_diff_ids() {
+    let base = layer_tar(&[("etc/os-release", b"ID=test\n")]);
+    let layers = vec![gzip(&base)];
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = UnpackOptions::default().verify_diff_ids(vec![digest(&layers[0])]);
+    let result = render::unpack_partial_with_options(&layers, dir.path(), "etc", true, &options);
+    assert!(matches!(
+        result,
+        Err(RenderError::DiffIdMismatch { layer: 0, .. })
+    ));
+}
@@ -0,0 +1,76 @@
+use super::{gzip, layer_tar, tar_of, Entry};
+use ghregistry::render;
+
+#[test]
+fn test_extract_topmost_version() {
+    let layers = vec![
+        gzip(&layer_tar(&[
+            ("etc/motd", b"lower"),
+            ("etc/hosts", b"localhost"),
+        ])),
+        layer_tar(&[("etc/motd", b"upper")]),
+    ];
+    let files = render::extract_paths(&layers, &["/etc/motd", "etc/hosts"]).unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files["/etc/motd"], b"upper");
+    assert_eq!(files["etc/hosts"], b"localhost");
+}
+
+#[test]
+fn test_extract_respects_whiteouts() {
+    let layers = vec![
+        layer_tar(&[("etc/motd", b"lower"), ("var/log/a", b"a")]),
+        tar_of(&[
+            Entry::File("etc/.wh.motd", b""),
+            Entry::File("var/log/.wh..wh..opq", b""),
+        ]),
+    ];
+    let files = render::extract_paths(&layers, &["etc/motd", "var/log/a"]).unwrap();
+    assert!(files.is_empty());
+}
+
+#[test]
+fn test_extract_skips_missing_and_directories() {
+    let layers = vec![tar_of(&[
+        Entry::Dir("etc"),
+        Entry::File("etc/motd", b"hello"),
+    ])];
+    let files = render::extract_paths(&layers, &["etc", "etc/nope", "etc/motd"]).unwrap();
+    assert_eq!(files.keys().collect::<Vec<_>>(), vec!["etc/motd"]);
+}
+
+#[test]
+fn test_extract_follows_links() {
+    let layers = vec![
+        layer_tar(&[("usr/lib/os-release", b"ID=test")]),
+        tar_of(&[
+            Entry::Symlink("etc/os-release", "../usr/lib/os-release"),
+            Entry::Symlink("etc/absolute", "/usr/lib/os-release"),
+            Entry::File("bin/tool", b"binary"),
+            Entry::Hardlink("bin/alias", "bin/tool"),
+            Entry::Symlink("loop", "loop"),
+        ]),
+    ];
+    let files = render::extract_paths(
+        &layers,
+        &["etc/os-release", "etc/absolute", "bin/alias", "loop"],
+    )
+    .unwrap();
+    assert_eq!(files.len(), 3);
+    assert_eq!(files["etc/os-release"], b"ID=test");
+    assert_eq!(files["etc/absolute"], b"ID=test");
+    assert_eq!(files["bin/alias"], b"binary");
+}
+
+#[test]
+fn test_extract_stops_early() {
+    // The lower layer is never read once the path is found above it
+    let layers = vec![
+        b"not a layer".to_vec(),
+        layer_tar(&[("etc/motd", b"hello")]),
+    ];
+    let files = render::extract_paths(&layers, &["etc/motd"]).unwrap();
+    assert_eq!(files["etc/motd"], b"hello");
+
+    assert!(render::extract_paths(&layers, &["etc/other"]).is_err());
+}
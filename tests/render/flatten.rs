@@ -0,0 +1,116 @@
+use super::{gzip, layer_tar, tar_of, Entry};
+use ghregistry::render;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Entries of a flattened tarball by path, with their type and content or link.
+fn read_flat(flat: &[u8]) -> BTreeMap<PathBuf, (tar::EntryType, Vec<u8>)> {
+    let mut archive = tar::Archive::new(flat);
+    let mut entries = BTreeMap::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().into_owned();
+        let entry_type = entry.header().entry_type();
+        let mut data = Vec::new();
+        match entry.link_name().unwrap() {
+            Some(link) => data.extend(link.to_string_lossy().bytes()),
+            None => {
+                entry.read_to_end(&mut data).unwrap();
+            }
+        }
+        assert!(entries.insert(path, (entry_type, data)).is_none());
+    }
+    entries
+}
+
+#[test]
+fn test_flatten_layers() {
+    let layers = vec![
+        gzip(&tar_of(&[
+            Entry::Dir("etc"),
+            Entry::File("etc/motd", b"lower"),
+            Entry::File("etc/passwd", b"root"),
+            Entry::File("etc/issue", b"issue"),
+            Entry::File("bin/tool", b"old tool"),
+            Entry::Hardlink("bin/alias", "bin/tool"),
+            Entry::File("usr/dir/x", b"x"),
+            Entry::Symlink("motd", "etc/motd"),
+        ])),
+        tar_of(&[
+            Entry::File("etc/motd", b"upper"),
+            Entry::File("etc/.wh.passwd", b""),
+            Entry::File("usr/dir", b"file over directory"),
+            Entry::File("bin/tool", b"new tool"),
+            Entry::Hardlink("etc/issue.net", "etc/issue"),
+        ]),
+    ];
+    let mut flat = Vec::new();
+    render::flatten_to_tar(&layers, &mut flat).unwrap();
+    let entries = read_flat(&flat);
+
+    let file = |data: &[u8]| (tar::EntryType::Regular, data.to_vec());
+    let link = |entry_type, target: &str| (entry_type, target.as_bytes().to_vec());
+    let expected = vec![
+        ("bin/alias", file(b"old tool")),
+        ("bin/tool", file(b"new tool")),
+        ("etc", (tar::EntryType::Directory, vec![])),
+        ("etc/issue", file(b"issue")),
+        ("etc/issue.net", link(tar::EntryType::Link, "etc/issue")),
+        ("etc/motd", file(b"upper")),
+        ("motd", link(tar::EntryType::Symlink, "etc/motd")),
+        ("usr/dir", file(b"file over directory")),
+    ];
+    assert_eq!(
+        entries,
+        expected
+            .into_iter()
+            .map(|(path, entry)| (PathBuf::from(path), entry))
+            .collect()
+    );
+
+    // The same paths as the image seen through its layers
+    let listed = render::list_image_entries(&layers).unwrap();
+    assert_eq!(
+        listed.iter().map(|e| &e.path).collect::<Vec<_>>(),
+        entries.keys().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_flatten_keeps_headers() {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o4750);
+    header.set_uid(1000);
+    header.set_gid(100);
+    header.set_mtime(1_600_000_000);
+    header.set_size(4);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "usr/bin/setuid", &b"suid"[..])
+        .unwrap();
+    let long_name = format!("usr/share/{}/file", "long".repeat(40));
+    let layers = vec![
+        builder.into_inner().unwrap(),
+        layer_tar(&[(long_name.as_str(), b"long")]),
+    ];
+
+    let mut flat = Vec::new();
+    render::flatten_to_tar(&layers, &mut flat).unwrap();
+    let entries = render::list_entries(&flat).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].path, PathBuf::from("usr/bin/setuid"));
+    assert_eq!(entries[0].mode, 0o4750);
+    assert_eq!((entries[0].uid, entries[0].gid), (1000, 100));
+    assert_eq!(entries[0].mtime, 1_600_000_000);
+    assert_eq!(entries[1].path, PathBuf::from(long_name));
+    assert_eq!(entries[1].size, 4);
+}
+
+#[test]
+fn test_flatten_empty() {
+    let mut flat = Vec::new();
+    render::flatten_to_tar(&[], &mut flat).unwrap();
+    assert!(read_flat(&flat).is_empty());
+}
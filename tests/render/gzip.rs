@@ -0,0 +1,34 @@
+use super::{digest, gzip, layer_tar};
+use ghregistry::render;
+
+#[test]
+fn test_unpack_multi_member_gzip() {
+    let tar = layer_tar(&[("etc/hostname", b"host\n"), ("etc/hosts", b"127.0.0.1\n")]);
+    // Split the tarball after the first entry's header and data blocks
+    let (first, second) = tar.split_at(1024);
+    let mut layer = gzip(first);
+    layer.extend(gzip(second));
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack_with_diff_ids(&[layer], &[digest(&tar)], dir.path()).unwrap();
+
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/hostname")).unwrap(),
+        b"host\n"
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/hosts")).unwrap(),
+        b"127.0.0.1\n"
+    );
+}
+
+#[test]
+fn test_unpack_gzip_with_trailing_padding() {
+    let tar = layer_tar(&[("srv/app", b"app")]);
+    let mut layer = gzip(&tar);
+    layer.extend([0u8; 512]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack_with_diff_ids(&[layer], &[digest(&tar)], dir.path()).unwrap();
+    assert_eq!(std::fs::read(dir.path().join("srv/app")).unwrap(), b"app");
+}
@@ -0,0 +1,115 @@
+//! Layers trying to write outside of the target directory.
+
+use super::{gzip, tar_of, Entry};
+use ghregistry::render::{self, RenderError, UnpackOptions, UnsafeEntryPolicy};
+use std::fs;
+
+fn skip_unsafe() -> UnpackOptions {
+    UnpackOptions::default()
+        .preserve_permissions(false)
+        .unpack_xattrs(false)
+        .unsafe_entries(UnsafeEntryPolicy::Skip)
+}
+
+#[test]
+fn test_parent_traversal_is_rejected() {
+    let layer = tar_of(&[
+        Entry::File("etc/hostname", b"host"),
+        Entry::RawFile("etc/../../../pwned", b"pwned"),
+    ]);
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("rootfs");
+    fs::create_dir(&target).unwrap();
+
+    match render::unpack(&[gzip(&layer)], &target) {
+        Err(RenderError::PathEscape(path)) => {
+            assert_eq!(path, std::path::Path::new("etc/../../../pwned"))
+        }
+        other => panic!("expected a path escape error, got {:?}", other),
+    }
+    assert!(!dir.path().join("pwned").exists());
+
+    render::unpack_with_options(&[gzip(&layer)], &target, &skip_unsafe()).unwrap();
+    assert!(!dir.path().join("pwned").exists());
+    assert_eq!(fs::read(target.join("etc/hostname")).unwrap(), b"host");
+}
+
+#[test]
+fn test_absolute_path_stays_inside_target() {
+    let outside = tempfile::tempdir().unwrap();
+    let victim = outside.path().join("victim");
+    let layer = tar_of(&[Entry::RawFile(victim.to_str().unwrap(), b"pwned")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&layer)], dir.path()).unwrap();
+
+    assert!(!victim.exists());
+    let inside = dir.path().join(victim.strip_prefix("/").unwrap());
+    assert_eq!(fs::read(inside).unwrap(), b"pwned");
+}
+
+#[test]
+fn test_writes_through_absolute_symlink_stay_inside_target() {
+    let outside = tempfile::tempdir().unwrap();
+    fs::write(outside.path().join("victim"), b"safe").unwrap();
+    let link_target = outside.path().to_str().unwrap();
+
+    // The symlink is valid inside a container, pointing at a path of the image
+    let base = tar_of(&[Entry::Symlink("escape", link_target)]);
+    let top = tar_of(&[
+        Entry::File("escape/pwned", b"pwned"),
+        Entry::File("escape/.wh.victim", b""),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+
+    assert!(!outside.path().join("pwned").exists());
+    assert_eq!(fs::read(outside.path().join("victim")).unwrap(), b"safe");
+    let inside = dir.path().join(outside.path().strip_prefix("/").unwrap());
+    assert_eq!(fs::read(inside.join("pwned")).unwrap(), b"pwned");
+}
+
+#[test]
+fn test_relative_symlink_chain_is_resolved_in_root() {
+    let layer = tar_of(&[
+        Entry::Dir("usr/lib"),
+        Entry::Symlink("lib", "usr/lib"),
+        Entry::Symlink("usr/lib/up", "../../.."),
+        Entry::File("lib/libc.so", b"libc"),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    // The symlink climbing above the root is skipped, the rest is extracted
+    render::unpack_with_options(&[gzip(&layer)], dir.path(), &skip_unsafe()).unwrap();
+
+    assert_eq!(
+        fs::read(dir.path().join("usr/lib/libc.so")).unwrap(),
+        b"libc"
+    );
+    assert!(fs::symlink_metadata(dir.path().join("usr/lib/up")).is_err());
+}
+
+#[test]
+fn test_hardlink_escape_is_rejected() {
+    let layer = tar_of(&[Entry::Hardlink("etc/shadow", "../../etc/shadow")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    assert!(matches!(
+        render::unpack(&[gzip(&layer)], dir.path()),
+        Err(RenderError::PathEscape(_))
+    ));
+    assert!(!dir.path().join("etc/shadow").exists());
+}
+
+#[test]
+fn test_symlink_loop_fails_cleanly() {
+    let base = tar_of(&[Entry::Symlink("a", "b"), Entry::Symlink("b", "a")]);
+    let top = tar_of(&[Entry::File("a/file", b"data")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    assert!(matches!(
+        render::unpack(&[gzip(&base), gzip(&top)], dir.path()),
+        Err(RenderError::Io(_))
+    ));
+}
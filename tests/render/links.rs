@@ -0,0 +1,173 @@
+use super::{gzip, tar_of, Entry};
+use ghregistry::render::{self, RenderError};
+use std::fs;
+
+#[test]
+fn test_hardlinks_across_layers_and_whiteouts() {
+    let base = tar_of(&[
+        Entry::Dir("bin"),
+        Entry::File("bin/busybox", b"busybox"),
+        Entry::File("etc/app.conf", b"old"),
+    ]);
+    // Links to a file of the lower layer which this layer also hides, and
+    // replaces a file while whiting out its previous version.
+    let middle = tar_of(&[
+        Entry::Hardlink("bin/sh", "bin/busybox"),
+        Entry::File("etc/.wh.app.conf", b""),
+        Entry::File("etc/app.conf", b"new"),
+        Entry::File("bin/.wh.busybox", b""),
+    ]);
+    let top = tar_of(&[
+        Entry::Dir("usr/bin"),
+        Entry::Hardlink("usr/bin/ls", "bin/sh"),
+    ]);
+    let layers = vec![gzip(&base), gzip(&middle), gzip(&top)];
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&layers, dir.path()).unwrap();
+
+    let root = dir.path();
+    assert!(!root.join("bin/busybox").exists());
+    assert!(!root.join("bin/.wh.busybox").exists());
+    assert!(!root.join("etc/.wh.app.conf").exists());
+    assert_eq!(fs::read(root.join("bin/sh")).unwrap(), b"busybox");
+    assert_eq!(fs::read(root.join("usr/bin/ls")).unwrap(), b"busybox");
+    assert_eq!(fs::read(root.join("etc/app.conf")).unwrap(), b"new");
+}
+
+#[test]
+fn test_whiteout_of_symlink_keeps_target() {
+    let base = tar_of(&[
+        Entry::File("usr/lib/libc.so", b"libc"),
+        Entry::Symlink("lib", "usr/lib"),
+    ]);
+    let top = tar_of(&[Entry::File(".wh.lib", b"")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+
+    assert!(fs::symlink_metadata(dir.path().join("lib")).is_err());
+    assert_eq!(
+        fs::read(dir.path().join("usr/lib/libc.so")).unwrap(),
+        b"libc"
+    );
+}
+
+#[test]
+fn test_symlink_inside_root_is_kept() {
+    let layer = tar_of(&[
+        Entry::File("usr/lib/libfoo.so.1", b"lib"),
+        Entry::Symlink("usr/lib/libfoo.so", "libfoo.so.1"),
+        Entry::Symlink("lib", "usr/lib"),
+        Entry::Symlink("bin/sh", "/usr/bin/busybox"),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&layer)], dir.path()).unwrap();
+
+    assert_eq!(fs::read(dir.path().join("lib/libfoo.so")).unwrap(), b"lib");
+    assert_eq!(
+        fs::read_link(dir.path().join("bin/sh")).unwrap(),
+        std::path::Path::new("/usr/bin/busybox")
+    );
+}
+
+#[test]
+fn test_symlink_escaping_root_is_rejected() {
+    let layer = tar_of(&[Entry::Symlink("etc/evil", "../../outside")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    match render::unpack(&[gzip(&layer)], dir.path()) {
+        Err(RenderError::SymlinkEscape { path, .. }) => {
+            assert_eq!(path, std::path::Path::new("etc/evil"))
+        }
+        other => panic!("expected a symlink escape error, got {:?}", other),
+    }
+    assert!(fs::symlink_metadata(dir.path().join("etc/evil")).is_err());
+}
+
+/// Layers of a busybox image whose applets are hardlinks, also across layers.
+fn busybox_layers() -> Vec<Vec<u8>> {
+    let base = tar_of(&[
+        Entry::File("bin/busybox", b"busybox"),
+        Entry::Hardlink("bin/sh", "bin/busybox"),
+    ]);
+    let applets = tar_of(&[
+        Entry::Hardlink("usr/bin/env", "bin/busybox"),
+        Entry::Hardlink("usr/bin/ash", "bin/sh"),
+        Entry::Hardlink("usr/bin/vi", "usr/bin/env"),
+    ]);
+    vec![gzip(&base), applets]
+}
+
+#[test]
+fn test_hardlinks_to_lower_layers() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&busybox_layers(), dir.path()).unwrap();
+    let inode = |path: &str| fs::metadata(dir.path().join(path)).unwrap().ino();
+    for applet in ["bin/sh", "usr/bin/env", "usr/bin/ash", "usr/bin/vi"] {
+        assert_eq!(inode(applet), inode("bin/busybox"), "{}", applet);
+    }
+
+    let files = render::extract_paths(&busybox_layers(), &["usr/bin/ash", "usr/bin/vi"]).unwrap();
+    assert_eq!(files["usr/bin/ash"], b"busybox");
+    assert_eq!(files["usr/bin/vi"], b"busybox");
+}
+
+#[test]
+fn test_partial_unpack_copies_filtered_out_link_targets() {
+    use std::os::unix::fs::MetadataExt;
+
+    let layers = busybox_layers();
+    let files_dir = tempfile::tempdir().unwrap();
+    let files = layers
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            let path = files_dir.path().join(format!("layer{}.tar", i));
+            fs::write(&path, layer).unwrap();
+            path.to_string_lossy().into_owned()
+        })
+        .collect::<Vec<_>>();
+
+    let from_memory = tempfile::tempdir().unwrap();
+    render::unpack_partial(&layers, from_memory.path(), "usr/bin", true).unwrap();
+    let from_files = tempfile::tempdir().unwrap();
+    render::unpack_partial_files(files, from_files.path(), "usr/bin").unwrap();
+
+    for root in [from_memory.path(), from_files.path()] {
+        for applet in ["env", "ash", "vi"] {
+            assert_eq!(fs::read(root.join(applet)).unwrap(), b"busybox");
+        }
+        assert!(!root.join("bin").exists());
+        // Links within the subtree stay links, to the copy
+        let inode = |path: &str| fs::metadata(root.join(path)).unwrap().ino();
+        assert_eq!(inode("vi"), inode("env"));
+        assert_ne!(inode("ash"), inode("env"));
+    }
+}
+
+#[test]
+fn test_hardlink_to_missing_target() {
+    let layers = vec![tar_of(&[
+        Entry::File("usr/bin/tool", b"tool"),
+        Entry::Hardlink("usr/bin/gone", "bin/missing"),
+    ])];
+
+    let full = tempfile::tempdir().unwrap();
+    let partial = tempfile::tempdir().unwrap();
+    for result in [
+        render::unpack(&layers, full.path()),
+        render::unpack_partial(&layers, partial.path(), "usr", false),
+    ] {
+        match result {
+            Err(RenderError::HardlinkTarget { path, target }) => {
+                assert_eq!(path, std::path::Path::new("usr/bin/gone"));
+                assert_eq!(target, std::path::Path::new("bin/missing"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
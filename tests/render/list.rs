@@ -0,0 +1,84 @@
+use super::{gzip, tar_of, Entry};
+use ghregistry::render::{self, EntryType};
+use std::path::{Path, PathBuf};
+
+fn paths(entries: &[render::EntryInfo]) -> Vec<&Path> {
+    entries.iter().map(|e| e.path.as_path()).collect()
+}
+
+#[test]
+fn test_list_entries() {
+    let layer = gzip(&tar_of(&[
+        Entry::Dir("./etc"),
+        Entry::File("etc/motd", b"hello"),
+        Entry::Symlink("etc/issue", "motd"),
+        Entry::Hardlink("etc/motd.bak", "etc/motd"),
+        Entry::File("etc/.wh.old", b""),
+    ]));
+    let entries = render::list_entries(&layer).unwrap();
+    assert_eq!(
+        paths(&entries),
+        vec![
+            Path::new("etc"),
+            Path::new("etc/motd"),
+            Path::new("etc/issue"),
+            Path::new("etc/motd.bak"),
+            Path::new("etc/.wh.old"),
+        ]
+    );
+    assert_eq!(entries[0].entry_type, EntryType::Directory);
+    assert_eq!(entries[0].mode, 0o755);
+    assert_eq!(entries[1].entry_type, EntryType::File);
+    assert_eq!(entries[1].size, 5);
+    assert_eq!(entries[1].mode, 0o644);
+    assert_eq!(entries[2].entry_type, EntryType::Symlink);
+    assert_eq!(entries[2].link_target, Some(PathBuf::from("motd")));
+    assert_eq!(entries[3].entry_type, EntryType::Hardlink);
+    assert_eq!(entries[3].link_target, Some(PathBuf::from("etc/motd")));
+
+    assert_eq!(render::list_entries_stream(&layer[..]).unwrap(), entries);
+}
+
+#[test]
+fn test_list_image_entries() {
+    let base = tar_of(&[
+        Entry::File("bin/sh", b"sh"),
+        Entry::File("etc/motd", b"base"),
+        Entry::File("etc/passwd", b"root"),
+        Entry::File("var/cache/a", b"a"),
+        Entry::File("var/cache/b", b"b"),
+    ]);
+    let top = tar_of(&[
+        Entry::File("etc/.wh.passwd", b""),
+        Entry::File("etc/motd", b"top motd"),
+        Entry::File("var/cache/.wh..wh..opq", b""),
+        Entry::File("var/cache/c", b"c"),
+        Entry::File("bin/.wh.missing", b""),
+    ]);
+
+    let entries = render::list_image_entries(&[gzip(&base), top]).unwrap();
+    assert_eq!(
+        paths(&entries),
+        vec![
+            Path::new("bin/sh"),
+            Path::new("etc/motd"),
+            Path::new("var/cache/c"),
+        ]
+    );
+    assert_eq!(entries[1].size, 8);
+}
+
+#[test]
+fn test_list_image_entries_whiteout_with_same_layer_file() {
+    let base = tar_of(&[Entry::Dir("opt/tool"), Entry::File("opt/tool/old", b"old")]);
+    let top = tar_of(&[
+        Entry::File("opt/.wh.tool", b""),
+        Entry::File("opt/tool/new", b"new"),
+    ]);
+
+    let entries = render::list_image_entries(&[base, top]).unwrap();
+    assert_eq!(
+        paths(&entries),
+        vec![Path::new("opt/tool"), Path::new("opt/tool/new")]
+    );
+}
@@ -0,0 +1,94 @@
+//! Tests rendering handcrafted layers to a directory.
+
+mod compression;
+mod diff_ids;
+mod extract;
+mod flatten;
+mod gzip;
+#[cfg(unix)]
+mod hostile;
+#[cfg(unix)]
+mod links;
+mod list;
+mod options;
+#[cfg(unix)]
+mod ownership;
+mod partial;
+mod stream;
+mod whiteouts;
+
+use std::io::Write;
+
+/// Entry of a handcrafted layer.
+enum Entry<'a> {
+    File(&'a str, &'a [u8]),
+    Dir(&'a str),
+    Symlink(&'a str, &'a str),
+    Hardlink(&'a str, &'a str),
+    /// File whose path is written verbatim, bypassing tar's path checks.
+    RawFile(&'a str, &'a [u8]),
+}
+
+/// Build an uncompressed layer tarball holding the given entries.
+fn tar_of(entries: &[Entry]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        match entry {
+            Entry::File(path, data) => {
+                header.set_mode(0o644);
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                builder.append_data(&mut header, path, *data).unwrap();
+            }
+            Entry::Dir(path) => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_cksum();
+                builder
+                    .append_data(&mut header, path, std::io::empty())
+                    .unwrap();
+            }
+            Entry::RawFile(path, data) => {
+                header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+                header.set_mode(0o644);
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                builder.append(&header, *data).unwrap();
+            }
+            Entry::Symlink(path, target) | Entry::Hardlink(path, target) => {
+                let entry_type = match entry {
+                    Entry::Symlink(..) => tar::EntryType::Symlink,
+                    _ => tar::EntryType::Link,
+                };
+                header.set_entry_type(entry_type);
+                header.set_mode(0o777);
+                builder.append_link(&mut header, path, target).unwrap();
+            }
+        }
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Build an uncompressed layer tarball holding the given regular files.
+fn layer_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let entries = files
+        .iter()
+        .map(|(path, data)| Entry::File(path, data))
+        .collect::<Vec<_>>();
+    tar_of(&entries)
+}
+
+/// Gzip-compress a layer tarball.
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+    encoder.write_all(data).unwrap();
+    encoder.finish().into_result().unwrap()
+}
+
+/// Compute the digest of some content.
+fn digest(data: &[u8]) -> String {
+    use sha2::Digest;
+    format!("sha256:{:x}", sha2::Sha256::digest(data))
+}
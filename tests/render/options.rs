@@ -0,0 +1,197 @@
+use super::{gzip, layer_tar, tar_of, Entry};
+use ghregistry::render::{self, OverwritePolicy, UnpackOptions};
+use std::path::{Path, PathBuf};
+
+#[test]
+fn test_unpack_without_overwrite_keeps_existing_files() {
+    let layers = vec![gzip(&layer_tar(&[("etc/motd", b"from layer")]))];
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("etc")).unwrap();
+    std::fs::write(dir.path().join("etc/motd"), b"local").unwrap();
+
+    let options = UnpackOptions::default()
+        .preserve_permissions(false)
+        .unpack_xattrs(false)
+        .overwrite(false);
+    assert!(render::unpack_with_options(&layers, dir.path(), &options).is_err());
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/motd")).unwrap(),
+        b"local"
+    );
+
+    render::unpack(&layers, dir.path()).unwrap();
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/motd")).unwrap(),
+        b"from layer"
+    );
+}
+
+/// Target directory with an existing file, directory and symlink.
+fn existing_rootfs() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("etc")).unwrap();
+    std::fs::write(dir.path().join("etc/motd"), b"local").unwrap();
+    std::fs::create_dir(dir.path().join("opt")).unwrap();
+    std::fs::write(dir.path().join("opt/tool"), b"local").unwrap();
+    std::fs::write(dir.path().join("srv"), b"local").unwrap();
+    dir
+}
+
+/// Layers colliding with `existing_rootfs` in every way.
+fn colliding_layers() -> Vec<Vec<u8>> {
+    vec![
+        tar_of(&[
+            Entry::Dir("etc"),
+            Entry::File("etc/motd", b"lower"),
+            Entry::File("opt", b"file over directory"),
+            Entry::Dir("srv"),
+            Entry::File("srv/index.html", b"directory over file"),
+            Entry::File("etc/.wh.hostname", b""),
+        ]),
+        layer_tar(&[("etc/motd", b"upper")]),
+    ]
+}
+
+fn options(policy: OverwritePolicy) -> UnpackOptions {
+    UnpackOptions::default()
+        .preserve_permissions(false)
+        .unpack_xattrs(false)
+        .overwrite_policy(policy)
+}
+
+#[test]
+fn test_overwrite_policy_overwrite() {
+    let dir = existing_rootfs();
+    std::fs::write(dir.path().join("etc/hostname"), b"local").unwrap();
+    let summary = render::unpack_with_options(
+        &colliding_layers(),
+        dir.path(),
+        &options(OverwritePolicy::Overwrite),
+    )
+    .unwrap();
+
+    // Only existing paths are reported, not the ones of lower layers
+    assert_eq!(
+        summary.overwritten,
+        vec![
+            PathBuf::from("etc/motd"),
+            PathBuf::from("opt"),
+            PathBuf::from("srv")
+        ]
+    );
+    assert!(summary.skipped.is_empty());
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/motd")).unwrap(),
+        b"upper"
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("opt")).unwrap(),
+        b"file over directory"
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("srv/index.html")).unwrap(),
+        b"directory over file"
+    );
+    assert!(!dir.path().join("etc/hostname").exists());
+}
+
+#[test]
+fn test_overwrite_policy_skip() {
+    let dir = existing_rootfs();
+    std::fs::write(dir.path().join("etc/hostname"), b"local").unwrap();
+    let summary = render::unpack_with_options(
+        &colliding_layers(),
+        dir.path(),
+        &options(OverwritePolicy::Skip),
+    )
+    .unwrap();
+
+    assert!(summary.overwritten.is_empty());
+    assert_eq!(
+        summary.skipped,
+        vec![
+            PathBuf::from("etc/motd"),
+            PathBuf::from("opt"),
+            PathBuf::from("srv"),
+            PathBuf::from("srv/index.html"),
+            PathBuf::from("etc/motd"),
+        ]
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/motd")).unwrap(),
+        b"local"
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("opt/tool")).unwrap(),
+        b"local"
+    );
+    assert_eq!(std::fs::read(dir.path().join("srv")).unwrap(), b"local");
+    // Whiteouts still apply
+    assert!(!dir.path().join("etc/hostname").exists());
+}
+
+#[test]
+fn test_overwrite_policy_error() {
+    let dir = existing_rootfs();
+    let err = render::unpack_with_options(
+        &colliding_layers(),
+        dir.path(),
+        &options(OverwritePolicy::Error),
+    )
+    .unwrap_err();
+    assert!(
+        matches!(&err, render::RenderError::AlreadyExists(path) if path == Path::new("etc/motd")),
+        "{:?}",
+        err
+    );
+
+    // Layers still replace each other in an empty target
+    let dir = tempfile::tempdir().unwrap();
+    let summary = render::unpack_with_options(
+        &colliding_layers(),
+        dir.path(),
+        &options(OverwritePolicy::Error),
+    )
+    .unwrap();
+    assert!(summary.overwritten.is_empty());
+    assert!(summary.skipped.is_empty());
+    assert_eq!(
+        std::fs::read(dir.path().join("etc/motd")).unwrap(),
+        b"upper"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_overwrite_policy_symlinks() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("data")).unwrap();
+    std::fs::write(dir.path().join("data/keep"), b"local").unwrap();
+    std::os::unix::fs::symlink("data", dir.path().join("link-to-dir")).unwrap();
+    std::os::unix::fs::symlink("data/keep", dir.path().join("link-to-file")).unwrap();
+
+    let layers = vec![tar_of(&[
+        Entry::Dir("link-to-dir"),
+        Entry::File("link-to-dir/new", b"through the link"),
+        Entry::File("link-to-file", b"replaces the link"),
+    ])];
+    let summary =
+        render::unpack_with_options(&layers, dir.path(), &options(OverwritePolicy::Overwrite))
+            .unwrap();
+
+    // Directories are merged through symlinks, files replace the symlink itself
+    assert_eq!(summary.overwritten, vec![PathBuf::from("link-to-file")]);
+    assert_eq!(
+        std::fs::read(dir.path().join("data/new")).unwrap(),
+        b"through the link"
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("link-to-file")).unwrap(),
+        b"replaces the link"
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("data/keep")).unwrap(),
+        b"local"
+    );
+}
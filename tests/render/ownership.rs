@@ -0,0 +1,96 @@
+use ghregistry::render::{self, IdMap, RenderError, UnpackOptions};
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+/// Build a layer of files owned by the given user and group ids.
+fn owned_tar(files: &[(&str, u64, u64, u32)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, uid, gid, mode) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_uid(*uid);
+        header.set_gid(*gid);
+        header.set_mode(*mode);
+        builder
+            .append_data(&mut header, path, std::io::empty())
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Whether the tests run with the privileges to change owners.
+fn is_root(dir: &std::path::Path) -> bool {
+    let probe = dir.join(".probe");
+    fs::write(&probe, b"").unwrap();
+    let root = fs::metadata(&probe).unwrap().uid() == 0;
+    fs::remove_file(probe).unwrap();
+    root
+}
+
+#[test]
+fn test_ownership_is_not_preserved_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let layers = vec![owned_tar(&[("data", 999, 999, 0o644)])];
+    render::unpack(&layers, dir.path()).unwrap();
+
+    let owner = fs::metadata(dir.path().join("data")).unwrap().uid();
+    assert_eq!(owner, fs::metadata(dir.path()).unwrap().uid());
+}
+
+#[test]
+fn test_preserve_ownership_with_id_maps() {
+    let dir = tempfile::tempdir().unwrap();
+    if !is_root(dir.path()) {
+        return;
+    }
+    let layers = vec![owned_tar(&[
+        ("var/lib/postgresql", 999, 999, 0o700),
+        ("usr/bin/sudo", 0, 0, 0o4755),
+    ])];
+    let options = UnpackOptions::default()
+        .preserve_ownership(true)
+        .uid_map(IdMap::offset(100_000))
+        .gid_map(IdMap::new().range(0, 200_000, 65_536));
+    render::unpack_with_options(&layers, dir.path(), &options).unwrap();
+
+    let data = fs::metadata(dir.path().join("var/lib/postgresql")).unwrap();
+    assert_eq!((data.uid(), data.gid()), (100_999, 200_999));
+    // Changing the owner doesn't drop the setuid bit
+    let sudo = fs::metadata(dir.path().join("usr/bin/sudo")).unwrap();
+    assert_eq!((sudo.uid(), sudo.gid()), (100_000, 200_000));
+    assert_eq!(sudo.permissions().mode() & 0o7777, 0o4755);
+}
+
+#[test]
+fn test_ownership_errors() {
+    let layers = vec![owned_tar(&[("srv/data", 70_000, 0, 0o644)])];
+    let options = UnpackOptions::default()
+        .preserve_ownership(true)
+        .uid_map(IdMap::new().range(0, 100_000, 65_536));
+
+    let dir = tempfile::tempdir().unwrap();
+    match render::unpack_with_options(&layers, dir.path(), &options) {
+        Err(RenderError::Ownership { path, .. }) => {
+            assert_eq!(path, std::path::Path::new("srv/data"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let options = options.ignore_ownership_errors(true);
+    render::unpack_with_options(&layers, dir.path(), &options).unwrap();
+    assert!(dir.path().join("srv/data").exists());
+}
+
+#[test]
+fn test_id_map() {
+    let map = IdMap::offset(1000);
+    assert_eq!(map.map(0), Some(1000));
+    assert_eq!(map.map(u32::MAX - 1000), None);
+
+    let map = IdMap::new().range(0, 1000, 1).range(1, 100_000, 65_536);
+    assert_eq!(map.map(0), Some(1000));
+    assert_eq!(map.map(1), Some(100_000));
+    assert_eq!(map.map(65_536), Some(165_535));
+    assert_eq!(map.map(65_537), None);
+    assert_eq!(IdMap::new().map(0), None);
+}
@@ -0,0 +1,155 @@
+use super::{gzip, tar_of, Entry};
+use ghregistry::render::{self, EntryAction, EntryType};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn layers() -> Vec<Vec<u8>> {
+    let base = tar_of(&[
+        Entry::Dir("usr/share/doc"),
+        Entry::File("usr/share/doc/README", b"readme"),
+        Entry::File("usr/share/doc/CHANGES", b"changes"),
+        Entry::File("usr/bin/app", b"app"),
+        Entry::File("etc/app.conf", b"conf"),
+    ]);
+    let top = tar_of(&[
+        Entry::File("usr/share/doc/.wh.CHANGES", b""),
+        Entry::File("etc/.wh.app.conf", b""),
+        Entry::File("./usr/share/doc/NEWS", b"news"),
+    ]);
+    vec![gzip(&base), gzip(&top)]
+}
+
+#[test]
+fn test_unpack_partial_strips_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack_partial(&layers(), dir.path(), "usr/share", true).unwrap();
+
+    let root = dir.path();
+    assert_eq!(fs::read(root.join("doc/README")).unwrap(), b"readme");
+    assert_eq!(fs::read(root.join("doc/NEWS")).unwrap(), b"news");
+    assert!(!root.join("doc/CHANGES").exists());
+    assert!(!root.join("doc/.wh.CHANGES").exists());
+    assert!(!root.join("usr").exists());
+    assert!(!root.join("etc").exists());
+}
+
+#[test]
+fn test_unpack_partial_keeps_full_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack_partial(&layers(), dir.path(), "/usr/share/", false).unwrap();
+
+    let root = dir.path();
+    assert_eq!(
+        fs::read(root.join("usr/share/doc/README")).unwrap(),
+        b"readme"
+    );
+    assert!(!root.join("usr/share/doc/CHANGES").exists());
+    assert!(!root.join("usr/bin").exists());
+    assert!(!root.join("etc").exists());
+}
+
+#[test]
+fn test_unpack_partial_whiteouts_stay_in_subtree() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("etc")).unwrap();
+    fs::write(dir.path().join("etc/app.conf"), b"local").unwrap();
+
+    render::unpack_partial(&layers(), dir.path(), "usr/share", false).unwrap();
+    assert_eq!(fs::read(dir.path().join("etc/app.conf")).unwrap(), b"local");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_partial_hardlinks() {
+    let layer = tar_of(&[
+        Entry::File("opt/tool/bin/tool", b"tool"),
+        Entry::Hardlink("opt/tool/bin/alias", "opt/tool/bin/tool"),
+        Entry::File("usr/bin/other", b"other"),
+        Entry::Hardlink("opt/tool/bin/other", "usr/bin/other"),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack_partial(&[gzip(&layer)], dir.path(), "opt/tool", true).unwrap();
+
+    assert_eq!(fs::read(dir.path().join("bin/alias")).unwrap(), b"tool");
+    // The target is outside of the filter, so the link becomes a copy
+    assert_eq!(fs::read(dir.path().join("bin/other")).unwrap(), b"other");
+    assert!(!dir.path().join("usr/bin/other").exists());
+}
+
+#[test]
+fn test_unpack_filtered() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut seen = Vec::new();
+    render::unpack_filtered(&layers(), dir.path(), |entry| {
+        seen.push((entry.path.clone(), entry.entry_type, entry.size));
+        if entry.path.starts_with("usr/share/doc") {
+            EntryAction::Skip
+        } else if let Ok(name) = entry.path.strip_prefix("etc") {
+            EntryAction::ExtractTo(Path::new("config").join(name))
+        } else {
+            EntryAction::Extract
+        }
+    })
+    .unwrap();
+
+    let root = dir.path();
+    assert_eq!(fs::read(root.join("usr/bin/app")).unwrap(), b"app");
+    assert!(!root.join("usr/share").exists());
+    assert!(!root.join("etc").exists());
+    // The whiteout removes the file where it was extracted to
+    assert!(!root.join("config/app.conf").exists());
+    assert!(root.join("config").is_dir());
+
+    // Whiteouts aren't passed to the filter
+    assert!(seen
+        .iter()
+        .all(|(path, _, _)| !path.to_string_lossy().contains(".wh.")));
+    assert!(seen.contains(&(PathBuf::from("usr/share/doc"), EntryType::Directory, 0)));
+    assert!(seen.contains(&(PathBuf::from("usr/share/doc/NEWS"), EntryType::File, 4)));
+}
+
+#[test]
+fn test_unpack_filtered_rejects_escaping_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let result = render::unpack_filtered(&layers(), dir.path(), |_| {
+        EntryAction::ExtractTo(PathBuf::from("../outside"))
+    });
+    assert!(matches!(result, Err(render::RenderError::PathEscape(_))));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_filtered_hardlinks() {
+    let layer = tar_of(&[
+        Entry::File("big/blob", b"large content"),
+        Entry::Hardlink("bin/blob", "big/blob"),
+        Entry::File("bin/tool", b"tool"),
+        Entry::Hardlink("bin/alias", "bin/tool"),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut links = Vec::new();
+    render::unpack_filtered(&[layer], dir.path(), |entry| {
+        if entry.entry_type == EntryType::Hardlink {
+            links.push(entry.link_target.clone().unwrap());
+        }
+        match entry.path.strip_prefix("bin") {
+            Ok(name) => EntryAction::ExtractTo(Path::new("tools").join(name)),
+            Err(_) => EntryAction::Skip,
+        }
+    })
+    .unwrap();
+
+    assert_eq!(
+        links,
+        vec![PathBuf::from("big/blob"), PathBuf::from("bin/tool")]
+    );
+    assert!(!dir.path().join("big").exists());
+    assert_eq!(
+        fs::read(dir.path().join("tools/blob")).unwrap(),
+        b"large content"
+    );
+    // Links follow their target to where it was extracted
+    assert_eq!(fs::read(dir.path().join("tools/alias")).unwrap(), b"tool");
+}
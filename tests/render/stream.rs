@@ -0,0 +1,40 @@
+use super::{gzip, layer_tar};
+use ghregistry::render::{self, RenderError};
+use std::fs;
+use std::io::{Cursor, Read};
+
+#[test]
+fn test_unpack_stream() {
+    let base = gzip(&layer_tar(&[
+        ("etc/hostname", b"base"),
+        ("etc/motd", b"hi"),
+    ]));
+    let top = layer_tar(&[("etc/hostname", b"top"), ("etc/.wh.motd", b"")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let layers = vec![Cursor::new(base), Cursor::new(top)];
+    render::unpack_stream(layers, dir.path()).unwrap();
+
+    assert_eq!(fs::read(dir.path().join("etc/hostname")).unwrap(), b"top");
+    assert!(!dir.path().join("etc/motd").exists());
+}
+
+#[test]
+fn test_unpack_stream_reads_to_end() {
+    let mut layer = gzip(&layer_tar(&[("etc/hostname", b"host")]));
+    layer.extend_from_slice(b"trailing garbage");
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut reader = Cursor::new(layer);
+    render::unpack_stream(Some(&mut reader), dir.path()).unwrap();
+    assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+}
+
+#[test]
+fn test_unpack_stream_wrong_target() {
+    let layers = vec![Cursor::new(layer_tar(&[("etc/hostname", b"host")]))];
+    match render::unpack_stream(layers, std::path::Path::new("relative")) {
+        Err(RenderError::WrongTargetPath(_)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
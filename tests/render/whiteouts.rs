@@ -0,0 +1,126 @@
+use super::{gzip, tar_of, Entry};
+use ghregistry::render;
+use std::fs;
+
+#[test]
+fn test_opaque_whiteout_hides_lower_layers() {
+    let base = tar_of(&[
+        Entry::Dir("etc/app"),
+        Entry::File("etc/app/default.conf", b"default"),
+        Entry::File("etc/app/conf.d/extra.conf", b"extra"),
+        Entry::File("etc/app/conf.d/keep.conf", b"old"),
+        Entry::File("etc/other.conf", b"other"),
+    ]);
+    let top = tar_of(&[
+        Entry::Dir("etc/app"),
+        Entry::File("etc/app/.wh..wh..opq", b""),
+        Entry::File("etc/app/app.conf", b"new"),
+        Entry::File("etc/app/conf.d/keep.conf", b"new"),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+
+    let root = dir.path();
+    assert!(!root.join("etc/app/default.conf").exists());
+    assert!(!root.join("etc/app/conf.d/extra.conf").exists());
+    assert!(!root.join("etc/app/.wh..wh..opq").exists());
+    assert_eq!(fs::read(root.join("etc/app/app.conf")).unwrap(), b"new");
+    assert_eq!(
+        fs::read(root.join("etc/app/conf.d/keep.conf")).unwrap(),
+        b"new"
+    );
+    assert_eq!(fs::read(root.join("etc/other.conf")).unwrap(), b"other");
+}
+
+#[test]
+fn test_opaque_whiteout_of_new_directory() {
+    let base = tar_of(&[Entry::File("srv/data", b"data")]);
+    let top = tar_of(&[Entry::File("var/cache/.wh..wh..opq", b"")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+    assert!(dir.path().join("srv/data").exists());
+}
+
+#[test]
+fn test_whiteout_removes_lower_file() {
+    let base = tar_of(&[
+        Entry::File("etc/foo", b"foo"),
+        Entry::File("etc/bar", b"bar"),
+    ]);
+    let top = tar_of(&[Entry::File("etc/.wh.foo", b"")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+
+    let root = dir.path();
+    assert!(!root.join("etc/foo").exists());
+    assert!(!root.join("etc/.wh.foo").exists());
+    assert_eq!(fs::read(root.join("etc/bar")).unwrap(), b"bar");
+}
+
+#[test]
+fn test_whiteout_removes_lower_directory() {
+    let base = tar_of(&[
+        Entry::Dir("opt/tool"),
+        Entry::File("opt/tool/bin/tool", b"tool"),
+        Entry::File("opt/other", b"other"),
+    ]);
+    let top = tar_of(&[Entry::File("opt/.wh.tool", b"")]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+
+    assert!(!dir.path().join("opt/tool").exists());
+    assert!(dir.path().join("opt/other").exists());
+}
+
+#[test]
+fn test_whiteout_with_same_layer_file() {
+    let base = tar_of(&[Entry::File("etc/foo", b"old")]);
+    let top = tar_of(&[
+        Entry::File("etc/.wh.foo", b""),
+        Entry::File("etc/foo", b"new"),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+    assert_eq!(fs::read(dir.path().join("etc/foo")).unwrap(), b"new");
+}
+
+#[test]
+fn test_whiteout_of_directory_with_same_layer_file() {
+    let base = tar_of(&[
+        Entry::Dir("opt/tool"),
+        Entry::File("opt/tool/old", b"old"),
+        Entry::File("opt/tool/bin/tool", b"tool"),
+    ]);
+    let top = tar_of(&[
+        Entry::File("opt/.wh.tool", b""),
+        Entry::File("opt/tool/new", b"new"),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+
+    let root = dir.path();
+    assert!(!root.join("opt/tool/old").exists());
+    assert!(!root.join("opt/tool/bin").exists());
+    assert_eq!(fs::read(root.join("opt/tool/new")).unwrap(), b"new");
+}
+
+#[test]
+fn test_whiteout_of_missing_path() {
+    let base = tar_of(&[Entry::File("etc/conf", b"conf")]);
+    let top = tar_of(&[
+        Entry::File("etc/.wh.missing", b""),
+        Entry::File("var/.wh.missing", b""),
+        // Below a regular file of the lower layer
+        Entry::File("etc/conf/.wh.missing", b""),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack(&[gzip(&base), gzip(&top)], dir.path()).unwrap();
+    assert_eq!(fs::read(dir.path().join("etc/conf")).unwrap(), b"conf");
+}